@@ -0,0 +1,85 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use esp_idf_svc::{
+    eventloop::{EspSubscription, EspSystemEventLoop, System},
+    ipv4::IpEvent,
+    sys::{esp, esp_timer_get_time, esp_wifi_ap_get_sta_list, wifi_sta_list_t, EspError},
+    wifi::WifiEvent,
+};
+use proxy_core::ap_clients::ApClientTable;
+
+/// Feeds the AP client table from the driver and DHCP server events, which
+/// arrive on the system event loop.
+pub struct ApClientMonitor {
+    table: Arc<Mutex<ApClientTable>>,
+    _wifi_subscription: EspSubscription<'static, System>,
+    _ip_subscription: EspSubscription<'static, System>,
+}
+
+impl ApClientMonitor {
+    pub fn new(sys_loop: &EspSystemEventLoop) -> Result<Self, EspError> {
+        let table = Arc::new(Mutex::new(ApClientTable::new()));
+
+        let wifi_table = table.clone();
+        let wifi_subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| match event {
+            WifiEvent::ApStaConnected(station) => {
+                wifi_table
+                    .lock()
+                    .unwrap()
+                    .on_connected(station.mac(), uptime());
+            }
+            WifiEvent::ApStaDisconnected(station) => {
+                wifi_table
+                    .lock()
+                    .unwrap()
+                    .on_disconnected(station.mac(), uptime());
+            }
+            _ => (),
+        })?;
+
+        let ip_table = table.clone();
+        let ip_subscription = sys_loop.subscribe::<IpEvent, _>(move |event| {
+            if let IpEvent::ApStaIpAssigned(assignment) = event {
+                ip_table.lock().unwrap().on_ip_assigned(
+                    assignment.mac(),
+                    assignment.ip(),
+                    uptime(),
+                );
+            }
+        })?;
+
+        Ok(Self {
+            table,
+            _wifi_subscription: wifi_subscription,
+            _ip_subscription: ip_subscription,
+        })
+    }
+
+    /// Shared table, for the HTTP endpoint.
+    pub fn table(&self) -> Arc<Mutex<ApClientTable>> {
+        self.table.clone()
+    }
+}
+
+/// The driver only reports the signal on demand, refresh it before the table
+/// is shown.
+pub fn refresh_rssi(table: &mut ApClientTable) {
+    let mut stations = wifi_sta_list_t::default();
+
+    if let Err(e) = esp!(unsafe { esp_wifi_ap_get_sta_list(&mut stations) }) {
+        log::warn!("Failed to read AP station list ({})", e);
+        return;
+    }
+
+    for station in stations.sta.iter().take(stations.num.max(0) as usize) {
+        table.update_rssi(station.mac, station.rssi);
+    }
+}
+
+/// The `now` of the `proxy_core` state machines, see `link_supervisor`.
+pub fn uptime() -> Duration {
+    Duration::from_micros(unsafe { esp_timer_get_time() }.max(0) as u64)
+}
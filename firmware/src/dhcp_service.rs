@@ -0,0 +1,122 @@
+use std::{
+    net::{Ipv4Addr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use esp_idf_svc::wifi::{BlockingWifi, EspWifi};
+use proxy_core::ap_clients::{format_mac, ApClientTable};
+use proxy_core::dhcp_server::{client_mac, DhcpServer};
+
+use crate::ap_client_monitor::uptime;
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+/// How often the thread checks for the stop request.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_PACKET_LEN: usize = 576;
+
+/// Serves the AP subnet when DHCP reservations are set, in place of the IDF
+/// server. The socket also sees the upstream broadcasts, so only the stations
+/// associated to the AP are answered, and the replies go to the AP subnet
+/// broadcast address.
+pub struct DhcpService {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DhcpService {
+    /// With `napt`, the clients get the DNS server set on the AP interface by
+    /// `Napt`.
+    pub fn start(
+        server: DhcpServer,
+        table: Arc<Mutex<ApClientTable>>,
+        wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+        napt: bool,
+    ) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DHCP_SERVER_PORT))?;
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        let thread = thread::Builder::new()
+            .name("dhcp_server".to_string())
+            .stack_size(4096)
+            .spawn(move || serve(socket, server, table, wifi, napt, thread_stop))?;
+
+        log::info!("DHCP server with reservations started.");
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for DhcpService {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(
+    socket: UdpSocket,
+    mut server: DhcpServer,
+    table: Arc<Mutex<ApClientTable>>,
+    wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    napt: bool,
+    stop: Arc<AtomicBool>,
+) {
+    let mut buffer = [0u8; MAX_PACKET_LEN];
+    let broadcast = (server.broadcast_address(), DHCP_CLIENT_PORT);
+
+    while !stop.load(Ordering::Relaxed) {
+        let len = match socket.recv_from(&mut buffer) {
+            Ok((len, _)) => len,
+            Err(_) => continue,
+        };
+        let packet = &buffer[..len];
+
+        let Some(mac) = client_mac(packet) else {
+            continue;
+        };
+
+        if !table
+            .lock()
+            .unwrap()
+            .clients()
+            .iter()
+            .any(|client| client.mac == mac)
+        {
+            continue;
+        }
+
+        if napt {
+            let dns = wifi.lock().unwrap().wifi().ap_netif().get_dns();
+            server.set_dns(Some(dns).filter(|dns| !dns.is_unspecified()));
+        }
+
+        let Some(reply) = server.handle(packet, uptime()) else {
+            continue;
+        };
+
+        if let Err(e) = socket.send_to(&reply.packet, broadcast) {
+            log::warn!("Failed to send DHCP reply to {} ({})", format_mac(&mac), e);
+            continue;
+        }
+
+        if let Some((mac, ip)) = reply.assigned {
+            table.lock().unwrap().on_ip_assigned(mac, ip, uptime());
+        }
+    }
+}
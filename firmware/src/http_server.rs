@@ -0,0 +1,485 @@
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::http::server::{EspHttpConnection, Request};
+use esp_idf_svc::sys::{
+    esp_fill_random, httpd_req_to_sockfd, lwip_getpeername, sockaddr, sockaddr_in6, socklen_t,
+};
+use esp_idf_svc::{
+    http::{self, server::EspHttpServer, Method},
+    io::Write,
+    wifi::{BlockingWifi, EspWifi},
+};
+use proxy_core::admin_auth::{
+    admin_password, set_admin_password, validate_admin_password, AdminAuth, LoginError,
+    PasswordHash,
+};
+use proxy_core::ap_clients::ApClientTable;
+use proxy_core::boot_guard::reset_boot_failures;
+use proxy_core::config::ConfigStore;
+use proxy_core::discovery::HomeAssistantDiscovery;
+use proxy_core::ingest::{check_content_length, handle_reading, status_json, MAX_JSON_BODY_LEN};
+use proxy_core::mode::{handle_mode_request, ModeMachine, Trigger};
+use proxy_core::proxy_config::ProxyConfig;
+use proxy_core::sensor_allowlist::SensorAllowlist;
+use proxy_core::sensor_auth::{
+    ReadingHeaders, SensorAuth, HEADER_AUTHORIZATION, HEADER_NONCE, HEADER_SIGNATURE,
+};
+use proxy_core::sensor_route::SensorRoute;
+use url_encoded_data::UrlEncodedData;
+
+use crate::ap_client_monitor::{refresh_rssi, uptime};
+use crate::mqtt_publisher::MqttForwarder;
+use crate::template;
+use crate::wifi_helper::EspWifiStatus;
+
+const MAX_FORM_BODY_LEN: usize = 10240;
+const MAX_MODE_BODY_LEN: usize = 16;
+const MAX_LOGIN_BODY_LEN: usize = 512;
+const SETUP_REFUSED: &str =
+    "No admin password yet, hold the settings button to enter configuration mode and set it.";
+const PASSWORD_INVALID: &str = "The stored admin password is invalid, hold the settings button \
+    to enter configuration mode and set it again.";
+
+/// Which readings the sensor routes accept.
+#[derive(Clone)]
+pub struct SensorAccess {
+    pub auth: Arc<Mutex<SensorAuth>>,
+    pub allowlist: Arc<Mutex<SensorAllowlist>>,
+}
+
+/// Every page but the login one requires the admin session. The password is
+/// set from the login page on first use, see `is_setup_allowed`.
+pub fn create_http_config_server<'a, C: ConfigStore + Send + 'static>(
+    mutex_config: Arc<Mutex<C>>,
+    mutex_wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    mutex_modes: Arc<Mutex<ModeMachine>>,
+    mutex_auth: Arc<Mutex<AdminAuth>>,
+) -> anyhow::Result<EspHttpServer<'a>> {
+    log::info!("Creating configuration HTTP server.");
+    let mut server = EspHttpServer::new(&http::server::Configuration {
+        stack_size: 10240,
+        ..Default::default()
+    })?;
+
+    let handler_config = mutex_config.clone();
+    let handler_wifi = mutex_wifi.clone();
+    let handler_modes = mutex_modes.clone();
+    let handler_auth = mutex_auth.clone();
+    server.fn_handler::<anyhow::Error, _>("/", Method::Get, move |req| {
+        if !is_admin(&req, &handler_auth) {
+            return send_login_or_setup_page(req, 200, &handler_config, &handler_modes, None);
+        }
+
+        req.into_ok_response()?.write_all(
+            template::to_html(
+                &ProxyConfig::load(&*handler_config.lock().unwrap()),
+                handler_wifi.lock().unwrap().scan().ok(),
+                None,
+                &[],
+            )
+            .as_bytes(),
+        )?;
+
+        Ok(())
+    })?;
+
+    let handler_config = mutex_config.clone();
+    let handler_wifi = mutex_wifi.clone();
+    let handler_modes = mutex_modes.clone();
+    let handler_auth = mutex_auth.clone();
+    server.fn_handler::<anyhow::Error, _>("/", Method::Post, move |mut req| {
+        if !is_admin(&req, &handler_auth) {
+            return send_login_or_setup_page(
+                req,
+                401,
+                &handler_config,
+                &handler_modes,
+                Some("Session expired, log in again."),
+            );
+        }
+
+        let mut config = ProxyConfig::load(&*handler_config.lock().unwrap());
+        let mut field_errors = Vec::new();
+        let error_message;
+
+        match read_request_body(&mut req, MAX_FORM_BODY_LEN) {
+            Err(e) => {
+                error_message = format!("Save error: {}", e);
+            }
+            Result::Ok(post_str) => {
+                let post_data = UrlEncodedData::parse_str(&post_str);
+                let saved = config.save_form(&mut *handler_config.lock().unwrap(), |field| {
+                    post_data.get_first(field).map(|value| value.to_string())
+                });
+
+                match saved {
+                    Ok(errors) if !errors.is_empty() => {
+                        field_errors = errors;
+                        error_message =
+                            "Save error: Nothing saved, check the highlighted fields.".to_string();
+                    }
+                    Ok(_) => {
+                        if let Err(e) = reset_boot_failures(&mut *handler_config.lock().unwrap()) {
+                            log::warn!("Failed to reset boot failure counter ({})", e);
+                        }
+
+                        error_message = "Save successfully!".to_string();
+                    }
+                    Err(e) => {
+                        log::error!("Failed to save configuration ({})", e);
+                        error_message = "Save error: Failed to write configuration.".to_string();
+                    }
+                }
+            }
+        };
+
+        req.into_ok_response()?.write_all(
+            template::to_html(
+                &config,
+                handler_wifi.lock().unwrap().scan().ok(),
+                Some(error_message),
+                &field_errors,
+            )
+            .as_bytes(),
+        )?;
+
+        Ok(())
+    })?;
+
+    let handler_config = mutex_config.clone();
+    let handler_modes = mutex_modes.clone();
+    let handler_auth = mutex_auth.clone();
+    server.fn_handler::<anyhow::Error, _>("/login", Method::Post, move |mut req| {
+        let stored = match login_page(&handler_config, &handler_modes) {
+            LoginPage::Login(hash) => Some(hash),
+            LoginPage::Setup => None,
+            LoginPage::Refused(message) => return send_login_page(req, 403, false, Some(message)),
+        };
+        let setup = stored.is_none();
+        let client = client_ip(&mut req);
+
+        let post_str = match read_request_body(&mut req, MAX_LOGIN_BODY_LEN) {
+            Ok(post_str) => post_str,
+            Err(e) => return send_login_page(req, 400, setup, Some(e)),
+        };
+        let post_data = UrlEncodedData::parse_str(&post_str);
+        let password = post_data.get_first("password").unwrap_or("");
+
+        let cookie = match stored {
+            None => {
+                let confirmation = post_data.get_first("confirm").unwrap_or("");
+
+                if let Err(e) = validate_admin_password(password, confirmation) {
+                    return send_login_page(req, 400, true, Some(e));
+                }
+
+                if let Err(e) = set_admin_password(
+                    &mut *handler_config.lock().unwrap(),
+                    password,
+                    random_bytes(),
+                ) {
+                    log::error!("Failed to save the admin password ({})", e);
+                    return send_login_page(req, 500, true, Some("Failed to save the password."));
+                }
+
+                log::info!("Admin password set.");
+                handler_auth
+                    .lock()
+                    .unwrap()
+                    .open_session(random_bytes(), uptime())
+            }
+            Some(hash) => {
+                let login = handler_auth.lock().unwrap().login(
+                    &hash,
+                    password,
+                    client,
+                    random_bytes(),
+                    uptime(),
+                );
+
+                match login {
+                    Ok(cookie) => cookie,
+                    Err(LoginError::WrongPassword { attempts_left }) => {
+                        let message = format!(
+                            "Wrong password, {} attempt(s) left before lockout.",
+                            attempts_left
+                        );
+                        return send_login_page(req, 401, false, Some(&message));
+                    }
+                    Err(LoginError::LockedOut(remaining)) => {
+                        let retry_after = remaining.as_secs().to_string();
+                        let message = format!(
+                            "Too many failed logins, retry in {} s.",
+                            remaining.as_secs()
+                        );
+
+                        req.into_response(429, None, &[("Retry-After", &retry_after)])?
+                            .write_all(template::to_login_html(false, Some(&message)).as_bytes())?;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        req.into_response(303, None, &[("Location", "/"), ("Set-Cookie", &cookie)])?;
+        Ok(())
+    })?;
+
+    let handler_auth = mutex_auth.clone();
+    server.fn_handler::<anyhow::Error, _>("/logout", Method::Post, move |req| {
+        let cookie = handler_auth.lock().unwrap().logout(req.header("Cookie"));
+
+        req.into_response(303, None, &[("Location", "/"), ("Set-Cookie", &cookie)])?;
+        Ok(())
+    })?;
+
+    // Starts the proxy with the sensor enrolment window open.
+    let handler_modes = mutex_modes.clone();
+    let handler_auth = mutex_auth.clone();
+    server.fn_handler::<anyhow::Error, _>("/enrol", Method::Post, move |req| {
+        if !is_admin(&req, &handler_auth) {
+            req.into_status_response(401)?
+                .write_all(b"Login required")?;
+            return Ok(());
+        }
+
+        handler_modes.lock().unwrap().request_enrolment();
+
+        req.into_status_response(202)?.write_all(b"proxy")?;
+        Ok(())
+    })?;
+
+    // `POST /mode` with `proxy` or `config` as body. The switch happens in
+    // the main loop, after the response is sent.
+    server.fn_handler::<anyhow::Error, _>("/mode", Method::Post, move |mut req| {
+        if !is_admin(&req, &mutex_auth) {
+            req.into_status_response(401)?
+                .write_all(b"Login required")?;
+            return Ok(());
+        }
+
+        let body = read_request_body(&mut req, MAX_MODE_BODY_LEN);
+
+        if body.is_err() {
+            req.into_status_response(400)?
+                .write_all(body.as_ref().err().unwrap().as_bytes())?;
+            return Ok(());
+        }
+
+        let response = handle_mode_request(&mut mutex_modes.lock().unwrap(), &body.unwrap());
+
+        req.into_status_response(response.status)?
+            .write_all(response.body.as_bytes())?;
+        Ok(())
+    })?;
+
+    Ok(server)
+}
+
+/// Sensor routes and read-only status. Without admin sessions in proxy mode,
+/// the mode is switched from MQTT or with the button only.
+pub fn create_http_server<'a>(
+    mutex_forwarder: Arc<Mutex<MqttForwarder>>,
+    routes: Vec<SensorRoute>,
+    mutex_discovery: Option<Arc<Mutex<HomeAssistantDiscovery>>>,
+    wifi_status: EspWifiStatus,
+    mutex_modes: Arc<Mutex<ModeMachine>>,
+    mutex_ap_clients: Arc<Mutex<ApClientTable>>,
+    sensor_access: SensorAccess,
+) -> anyhow::Result<EspHttpServer<'a>> {
+    log::info!("Creating HTTP server.");
+    let mut server = EspHttpServer::new(&http::server::Configuration {
+        stack_size: 10240,
+        ..Default::default()
+    })?;
+
+    for route in routes {
+        log::info!("Register sensor route {} -> {}", route.path, route.topic);
+
+        let path = route.path.clone();
+        let forwarder = mutex_forwarder.clone();
+        let discovery = mutex_discovery.clone();
+        let sensor_access = sensor_access.clone();
+        server.fn_handler::<anyhow::Error, _>(&path, Method::Post, move |mut req| {
+            let headers = ReadingHeaders {
+                authorization: req.header(HEADER_AUTHORIZATION).map(str::to_string),
+                signature: req.header(HEADER_SIGNATURE).map(str::to_string),
+                nonce: req.header(HEADER_NONCE).map(str::to_string),
+            };
+            let body = read_request_body(&mut req, MAX_JSON_BODY_LEN);
+
+            if body.is_err() {
+                req.into_status_response(400)?
+                    .write_all(body.as_ref().err().unwrap().as_bytes())?;
+                return Ok(());
+            }
+
+            let mut discovery = discovery.as_ref().map(|d| d.lock().unwrap());
+            let response = handle_reading(
+                &route,
+                &body.unwrap(),
+                &headers,
+                &mut sensor_access.auth.lock().unwrap(),
+                &mut sensor_access.allowlist.lock().unwrap(),
+                &mut forwarder.lock().unwrap(),
+                discovery.as_deref_mut(),
+            );
+
+            req.into_status_response(response.status)?
+                .write_all(response.body.as_bytes())?;
+            Ok(())
+        })?;
+    }
+
+    let forwarder = mutex_forwarder.clone();
+    server.fn_handler::<anyhow::Error, _>("/status", Method::Get, move |req| {
+        let mode = mutex_modes.lock().unwrap().mode();
+        let status = status_json(mode, &wifi_status, &forwarder.lock().unwrap());
+
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(status.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/clients", Method::Get, move |req| {
+        let mut ap_clients = mutex_ap_clients.lock().unwrap();
+        refresh_rssi(&mut ap_clients);
+        let clients = ap_clients.to_json(uptime()).to_string();
+        drop(ap_clients);
+
+        req.into_response(200, None, &[("Content-Type", "application/json")])?
+            .write_all(clients.as_bytes())?;
+        Ok(())
+    })?;
+
+    Ok(server)
+}
+
+fn is_admin(req: &Request<&mut EspHttpConnection>, auth: &Mutex<AdminAuth>) -> bool {
+    auth.lock()
+        .unwrap()
+        .is_authenticated(req.header("Cookie"), uptime())
+}
+
+/// The password can only be set on first use when configuration mode was
+/// entered at boot or with the button. A remote switch, from MQTT, must not
+/// hand the portal to the first AP client.
+fn is_setup_allowed(modes: &Mutex<ModeMachine>) -> bool {
+    matches!(
+        modes.lock().unwrap().trigger(),
+        Trigger::Boot | Trigger::Button
+    )
+}
+
+enum LoginPage {
+    Login(PasswordHash),
+    Setup,
+    Refused(&'static str),
+}
+
+/// An invalid stored password is set again like a missing one, and only
+/// when `is_setup_allowed`: until then the portal stays locked.
+fn login_page<C: ConfigStore>(config: &Mutex<C>, modes: &Mutex<ModeMachine>) -> LoginPage {
+    let refused = match admin_password(&*config.lock().unwrap()) {
+        Ok(Some(hash)) => return LoginPage::Login(hash),
+        Ok(None) => SETUP_REFUSED,
+        Err(e) => {
+            log::error!("{}", e);
+            PASSWORD_INVALID
+        }
+    };
+
+    if is_setup_allowed(modes) {
+        LoginPage::Setup
+    } else {
+        LoginPage::Refused(refused)
+    }
+}
+
+/// Login page, or the first use page when no password is set yet.
+fn send_login_or_setup_page<C: ConfigStore>(
+    req: Request<&mut EspHttpConnection>,
+    status: u16,
+    config: &Mutex<C>,
+    modes: &Mutex<ModeMachine>,
+    message: Option<&str>,
+) -> anyhow::Result<()> {
+    match login_page(config, modes) {
+        LoginPage::Login(_) => send_login_page(req, status, false, message),
+        LoginPage::Setup => send_login_page(req, status, true, message),
+        LoginPage::Refused(refused) => send_login_page(req, 403, false, Some(refused)),
+    }
+}
+
+fn send_login_page(
+    req: Request<&mut EspHttpConnection>,
+    status: u16,
+    setup: bool,
+    message: Option<&str>,
+) -> anyhow::Result<()> {
+    req.into_status_response(status)?
+        .write_all(template::to_login_html(setup, message).as_bytes())?;
+    Ok(())
+}
+
+/// Peer address of the request, for the login lockout. The server socket is
+/// IPv6, IPv4 clients come as mapped addresses.
+fn client_ip(req: &mut Request<&mut EspHttpConnection>) -> Ipv4Addr {
+    let Ok(connection) = req.connection().raw_connection() else {
+        return Ipv4Addr::UNSPECIFIED;
+    };
+
+    let mut addr: sockaddr_in6 = unsafe { core::mem::zeroed() };
+    let mut len = core::mem::size_of::<sockaddr_in6>() as socklen_t;
+    let result = unsafe {
+        lwip_getpeername(
+            httpd_req_to_sockfd(connection.handle()),
+            &mut addr as *mut sockaddr_in6 as *mut sockaddr,
+            &mut len,
+        )
+    };
+
+    if result != 0 {
+        return Ipv4Addr::UNSPECIFIED;
+    }
+
+    Ipv4Addr::from(u32::from_be(unsafe { addr.sin6_addr.un.u32_addr[3] }))
+}
+
+/// From the hardware RNG, seeded by the radio which is on in every mode.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    unsafe { esp_fill_random(bytes.as_mut_ptr() as *mut core::ffi::c_void, N as _) };
+    bytes
+}
+
+fn read_request_body(
+    req: &mut Request<&mut EspHttpConnection>,
+    max_len: usize,
+) -> Result<String, &'static str> {
+    let len_body = req
+        .header("Content-Length")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    check_content_length(len_body, max_len)?;
+
+    let mut buffer = vec![0u8; len_body];
+    let mut bytes_read = 0;
+
+    while bytes_read < len_body {
+        match req.read(&mut buffer[bytes_read..]) {
+            Ok(0) => break,
+            Ok(n) => bytes_read += n,
+            Err(e) => {
+                log::error!("Read error: {}", e);
+                return Err("Failed to read request.");
+            }
+        }
+    }
+
+    buffer.truncate(bytes_read);
+    String::from_utf8(buffer).map_err(|_| "Body is not valid UTF-8")
+}
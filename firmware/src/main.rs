@@ -0,0 +1,227 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{
+        delay::FreeRtos,
+        gpio::{Output, OutputPin, PinDriver, Pull},
+        peripherals::Peripherals,
+    },
+};
+
+use nvs_configuration::NvsConfiguration;
+use on_board_led::OnBoardLed;
+use proxy_core::boot_guard::{
+    decide_boot, record_boot_failure, BootDecision, FALLBACK_RETRY_INTERVAL, MAX_BOOT_FAILURES,
+};
+use proxy_core::mode::{Mode, ModeMachine, Trigger};
+use services::{ConfigServices, Context, ProxyServices, Services};
+use wifi_helper::create_wifi;
+
+mod ap_client_monitor;
+mod dhcp_service;
+mod http_server;
+mod mqtt_publisher;
+mod napt;
+mod nvs_configuration;
+mod nvs_outbox;
+mod on_board_led;
+mod services;
+mod sta_supervisor;
+mod string_error;
+mod template;
+mod wifi_helper;
+
+/// Holding the settings button that long switches between proxy and
+/// configuration mode, in main loop periods of 250 ms. A shorter press in
+/// proxy mode opens the sensor enrolment window.
+const MODE_BUTTON_TICKS: u32 = 12;
+
+fn main() -> anyhow::Result<()> {
+    esp_idf_svc::sys::link_patches();
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    let nvs_config = Arc::new(Mutex::new(NvsConfiguration::take().unwrap()));
+    let modes = Arc::new(Mutex::new(ModeMachine::new()));
+
+    let peripherals = Peripherals::take()?;
+    let sys_loop = EspSystemEventLoop::take()?;
+    let pins = peripherals.pins;
+
+    let mut leds = OnBoardLed::new(pins.gpio3, pins.gpio4, pins.gpio5)?;
+
+    let mut settings_button = PinDriver::input(pins.gpio9)?;
+    settings_button.set_pull(Pull::Up)?;
+
+    log::info!("Wait 5 seconds for activation of configuration web server...");
+    leds.blue.set_high()?;
+    FreeRtos::delay_ms(5000);
+    let boot_decision = decide_boot(&*nvs_config.lock().unwrap());
+
+    let (boot_mode, boot_trigger) = match boot_decision {
+        _ if settings_button.is_low() => (Mode::Config, Trigger::Button),
+        BootDecision::Proxy => (Mode::Proxy, Trigger::Boot),
+        BootDecision::ConfigNoStation => {
+            log::warn!("No station SSID configured.");
+            (Mode::Config, Trigger::Boot)
+        }
+        BootDecision::ConfigTooManyFailures(failures) => {
+            log::warn!("{} consecutive failed start-ups.", failures);
+            (Mode::Config, Trigger::Fallback)
+        }
+    };
+    leds.blue.set_low()?;
+
+    let wifi = create_wifi(peripherals.modem, sys_loop.clone());
+
+    if wifi.is_err() {
+        log::error!("Failed to create WiFi driver !. Restart in 5 sec...");
+        log::error!("{}", wifi.as_ref().err().unwrap());
+
+        flash_led_and_restart(&mut leds.red, 5);
+    }
+
+    let mut context = Context {
+        config: nvs_config,
+        wifi: Arc::new(Mutex::new(wifi.unwrap())),
+        sys_loop,
+        modes: modes.clone(),
+        outbox: None,
+    };
+
+    let _ = modes.lock().unwrap().request_by(boot_mode, boot_trigger);
+
+    let mut services = Services::Idle;
+    // A press still held from the boot window must not toggle the mode.
+    let mut button_ticks = if settings_button.is_low() {
+        MODE_BUTTON_TICKS
+    } else {
+        0
+    };
+
+    loop {
+        let requested = modes.lock().unwrap().take_request();
+
+        if let Some(target) = requested {
+            std::mem::replace(&mut services, Services::Idle).stop(&mut context);
+
+            services = match start_services(target, &mut context) {
+                Ok(started) => started,
+                Err(e) => {
+                    log::error!("Failed to start {} mode ! ({})", target.as_str(), e);
+
+                    if target == Mode::Proxy && record_failed_start_up(&context) {
+                        log::warn!("Fall back to configuration mode.");
+                        let mut modes = modes.lock().unwrap();
+                        // Nothing runs, so configuration mode is entered again
+                        // after a retry from it.
+                        modes.entered(Mode::Booting);
+                        let _ = modes.request_by(Mode::Config, Trigger::Fallback);
+                        continue;
+                    }
+
+                    log::error!("Restart in 5 sec...");
+                    context.spill_outbox();
+                    flash_led_and_restart(&mut leds.red, 5);
+                }
+            };
+
+            modes.lock().unwrap().entered(target);
+            leds.blue.set_low()?;
+        }
+
+        FreeRtos::delay_ms(250);
+
+        match &mut services {
+            Services::Proxy(proxy) => proxy.poll(&context.config, &modes),
+            Services::Config(config)
+                if modes.lock().unwrap().trigger() == Trigger::Fallback
+                    && config.is_unattended_for(FALLBACK_RETRY_INTERVAL) =>
+            {
+                log::info!("Try proxy mode again.");
+                let _ = modes
+                    .lock()
+                    .unwrap()
+                    .request_by(Mode::Proxy, Trigger::Fallback);
+            }
+            _ => (),
+        }
+
+        if settings_button.is_low() {
+            button_ticks += 1;
+
+            if button_ticks == MODE_BUTTON_TICKS {
+                log::info!("Settings button held, switch mode.");
+                modes.lock().unwrap().request_toggle();
+            }
+        } else {
+            if button_ticks > 0
+                && button_ticks < MODE_BUTTON_TICKS
+                && matches!(services, Services::Proxy(_))
+            {
+                log::info!("Settings button pressed, open sensor enrolment.");
+                modes.lock().unwrap().request_enrolment();
+            }
+
+            button_ticks = 0;
+        }
+
+        match &services {
+            Services::Proxy(proxy) if proxy.is_enrolling() => leds.green.toggle()?,
+            _ => leds.green.set_low()?,
+        }
+
+        match modes.lock().unwrap().mode() {
+            Mode::Config => leds.blue.toggle()?,
+            Mode::Degraded => leds.red.toggle()?,
+            _ => leds.red.set_low()?,
+        }
+    }
+}
+
+fn start_services(target: Mode, context: &mut Context) -> anyhow::Result<Services> {
+    match target {
+        Mode::Config => {
+            log::info!("CONFIGURATION MODE");
+            Ok(Services::Config(ConfigServices::start(context)?))
+        }
+        _ => {
+            log::info!("PROXY MODE");
+            Ok(Services::Proxy(ProxyServices::start(context)?))
+        }
+    }
+}
+
+/// Counts a failed proxy mode start-up, returns whether it was the last one
+/// allowed before falling back to configuration mode.
+fn record_failed_start_up(context: &Context) -> bool {
+    match record_boot_failure(&mut *context.config.lock().unwrap()) {
+        Ok(failures) => {
+            log::warn!("Start-up failure {}/{}", failures, MAX_BOOT_FAILURES);
+            failures >= MAX_BOOT_FAILURES
+        }
+        Err(e) => {
+            log::error!("Failed to record start-up failure ({})", e);
+            false
+        }
+    }
+}
+
+/// The MQTT client, if any, announced `offline` when the services stopped.
+fn flash_led_and_restart<T: OutputPin>(led: &mut PinDriver<T, Output>, timeout_sec: u64) -> ! {
+    let timeout = SystemTime::now();
+
+    loop {
+        let _ = led.set_high();
+        FreeRtos::delay_ms(100);
+        let _ = led.set_low();
+        FreeRtos::delay_ms(100);
+
+        if timeout.elapsed().unwrap().as_secs() >= timeout_sec {
+            esp_idf_svc::hal::reset::restart();
+        }
+    }
+}
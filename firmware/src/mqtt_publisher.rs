@@ -0,0 +1,165 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use esp_idf_svc::{
+    mqtt::client::{EspMqttClient, EventPayload, LwtConfiguration, MqttClientConfiguration, QoS},
+    sys::{esp_crt_bundle_attach, EspError},
+    tls::X509,
+};
+use proxy_core::config::ConfigStore;
+use proxy_core::forwarder::Forwarder;
+use proxy_core::mode::{handle_mode_request, ModeMachine};
+use proxy_core::mqtt::{
+    availability_message, availability_topic, make_mqtt_url, mode_command_topic, MqttPublisher,
+    AVAILABILITY_OFFLINE,
+};
+use proxy_core::outbox::OutboxMessage;
+
+use crate::nvs_outbox::NvsOutboxStorage;
+
+pub type MqttForwarder = Forwarder<EspMqttPublisher, NvsOutboxStorage>;
+
+pub struct EspMqttPublisher {
+    client: EspMqttClient<'static>,
+    is_connected: Arc<AtomicBool>,
+    /// Set on every (re)connection, cleared once `online` is published.
+    online_pending: Arc<AtomicBool>,
+    availability_topic: String,
+    command_topic: String,
+}
+
+impl EspMqttPublisher {
+    /// Mode commands received on the command topic are forwarded to `modes`.
+    pub fn connect(
+        config: &impl ConfigStore,
+        client_id: &str,
+        modes: Arc<Mutex<ModeMachine>>,
+    ) -> Result<Self, EspError> {
+        let is_connected = Arc::new(AtomicBool::new(false));
+        let online_pending = Arc::new(AtomicBool::new(false));
+        let callback_connected = is_connected.clone();
+        let callback_online_pending = online_pending.clone();
+        let availability_topic = availability_topic(config, client_id);
+        let command_topic = mode_command_topic(config, client_id);
+        let callback_command_topic = command_topic.clone();
+
+        let username = config.get_mqtt_username();
+        let password = config.get_mqtt_password();
+        let tls = config.get_mqtt_tls();
+        let ca_cert = config.get_mqtt_ca_cert();
+
+        let client = EspMqttClient::new_cb(
+            &make_mqtt_url(config),
+            &MqttClientConfiguration {
+                client_id: Some(client_id),
+                lwt: Some(LwtConfiguration {
+                    topic: &availability_topic,
+                    payload: AVAILABILITY_OFFLINE.as_bytes(),
+                    qos: QoS::AtLeastOnce,
+                    retain: true,
+                }),
+                username: (!username.is_empty()).then_some(username.as_str()),
+                password: (!password.is_empty()).then_some(password.as_str()),
+                server_certificate: (tls && !ca_cert.is_empty()).then(|| leak_pem(ca_cert)),
+                crt_bundle_attach: if tls && ca_cert.is_empty() {
+                    Some(esp_crt_bundle_attach)
+                } else {
+                    None
+                },
+                ..Default::default()
+            },
+            move |event| {
+                log::info!("[MQTT Event]: {}", event.payload());
+
+                match event.payload() {
+                    EventPayload::Connected(_) => {
+                        callback_online_pending.store(true, Ordering::Relaxed);
+                        callback_connected.store(true, Ordering::Relaxed);
+                    }
+                    EventPayload::Disconnected => {
+                        callback_connected.store(false, Ordering::Relaxed)
+                    }
+                    EventPayload::Received {
+                        topic: Some(topic),
+                        data,
+                        ..
+                    } if topic == callback_command_topic => {
+                        let command = String::from_utf8_lossy(data);
+                        let response = handle_mode_request(&mut modes.lock().unwrap(), &command);
+                        log::info!("MQTT mode command {}: {}", command, response.body);
+                    }
+                    _ => (),
+                }
+            },
+        )?;
+
+        Ok(Self {
+            client,
+            is_connected,
+            online_pending,
+            availability_topic,
+            command_topic,
+        })
+    }
+
+    /// Publishes the retained `online` state and subscribes to the command
+    /// topic after each connection. The event callback cannot use the client,
+    /// so this is polled from the main loop.
+    pub fn announce_online(&mut self) {
+        if !self.is_connected() || !self.online_pending.swap(false, Ordering::Relaxed) {
+            return;
+        }
+
+        if let Err(e) = self.client.subscribe(&self.command_topic, QoS::AtLeastOnce) {
+            log::warn!("Failed to subscribe to {} ({})", self.command_topic, e);
+        }
+
+        if let Err(e) = self.publish(&availability_message(&self.availability_topic, true)) {
+            log::warn!("Failed to publish availability ({})", e);
+            self.online_pending.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Publishes the retained `offline` state before a deliberate restart, the
+    /// last will only covers unexpected disconnections.
+    pub fn announce_offline(&mut self) {
+        if !self.is_connected() {
+            return;
+        }
+
+        if let Err(e) = self.publish(&availability_message(&self.availability_topic, false)) {
+            log::warn!("Failed to publish availability ({})", e);
+        }
+    }
+}
+
+impl MqttPublisher for EspMqttPublisher {
+    type Error = EspError;
+
+    fn is_connected(&self) -> bool {
+        self.is_connected.load(Ordering::Relaxed)
+    }
+
+    fn publish(&mut self, message: &OutboxMessage) -> Result<(), Self::Error> {
+        let qos = match message.qos {
+            0 => QoS::AtMostOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::AtLeastOnce,
+        };
+
+        self.client
+            .publish(&message.topic, qos, message.retain, &message.payload)
+            .map(|_| ())
+    }
+}
+
+/// The MQTT client keeps a pointer on the certificate for its whole life, so
+/// the PEM is leaked.
+fn leak_pem(pem: String) -> X509<'static> {
+    let mut bytes = pem.into_bytes();
+    bytes.push(0);
+
+    X509::pem_until_nul(Box::leak(bytes.into_boxed_slice()))
+}
@@ -0,0 +1,129 @@
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+use esp_idf_svc::{
+    sys::{esp, esp_netif_napt_enable, ip_portmap_add, ip_portmap_remove, EspError},
+    wifi::{BlockingWifi, EspWifi},
+};
+use proxy_core::port_forward::{PortForward, Protocol};
+
+const IP_PROTO_TCP: u8 = 6;
+const IP_PROTO_UDP: u8 = 17;
+
+/// Routes the AP clients through the station once it has an address. Port
+/// forwards are bound to the station address, so they are set again whenever
+/// it changes.
+pub struct Napt {
+    wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    forwards: Vec<PortForward>,
+    sta_ip: Option<Ipv4Addr>,
+}
+
+impl Napt {
+    pub fn new(
+        wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+        forwards: Vec<PortForward>,
+    ) -> Self {
+        Self {
+            wifi,
+            forwards,
+            sta_ip: None,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let sta_ip = self
+            .wifi
+            .lock()
+            .unwrap()
+            .wifi()
+            .sta_netif()
+            .get_ip_info()
+            .ok()
+            .map(|info| info.ip)
+            .filter(|ip| !ip.is_unspecified());
+
+        if sta_ip == self.sta_ip {
+            return;
+        }
+
+        self.remove_port_forwards();
+        self.sta_ip = sta_ip;
+
+        if let Some(sta_ip) = sta_ip {
+            if let Err(e) = self.enable(sta_ip) {
+                log::error!("Failed to enable NAPT ({})", e);
+            }
+        }
+    }
+
+    fn enable(&self, sta_ip: Ipv4Addr) -> Result<(), EspError> {
+        let mut wifi = self.wifi.lock().unwrap();
+
+        esp!(unsafe { esp_netif_napt_enable(wifi.wifi().ap_netif().handle()) })?;
+
+        // Offered by the AP DHCP server, see `generate_ap_netif_configuration`.
+        let dns = wifi.wifi().sta_netif().get_dns();
+        if !dns.is_unspecified() {
+            wifi.wifi_mut().ap_netif_mut().set_dns(dns);
+        }
+
+        for forward in &self.forwards {
+            let added = unsafe {
+                ip_portmap_add(
+                    protocol_number(forward.protocol),
+                    lwip_address(sta_ip),
+                    forward.port,
+                    lwip_address(forward.to),
+                    forward.to_port,
+                )
+            };
+
+            if added == 0 {
+                log::warn!(
+                    "Failed to forward {} port {}",
+                    forward.protocol.as_str(),
+                    forward.port
+                );
+            }
+        }
+
+        log::info!(
+            "NAPT enabled through {} with {} port forward(s).",
+            sta_ip,
+            self.forwards.len()
+        );
+
+        Ok(())
+    }
+
+    fn remove_port_forwards(&self) {
+        if self.sta_ip.is_none() {
+            return;
+        }
+
+        for forward in &self.forwards {
+            unsafe { ip_portmap_remove(protocol_number(forward.protocol), forward.port) };
+        }
+    }
+}
+
+impl Drop for Napt {
+    fn drop(&mut self) {
+        self.remove_port_forwards();
+    }
+}
+
+fn protocol_number(protocol: Protocol) -> u8 {
+    match protocol {
+        Protocol::Tcp => IP_PROTO_TCP,
+        Protocol::Udp => IP_PROTO_UDP,
+    }
+}
+
+/// lwIP keeps addresses in network order.
+fn lwip_address(ip: Ipv4Addr) -> u32 {
+    u32::from_ne_bytes(ip.octets())
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use esp_idf_svc::nvs::{EspCustomNvsPartition, EspNvs, NvsCustom};
+use proxy_core::config::ConfigStore;
+use proxy_core::config_journal::recover;
+use proxy_core::migration::migrate;
+
+use crate::string_error::{StringError, StringEspError};
+
+static IS_NVS_TAKEN: AtomicBool = AtomicBool::new(false);
+
+const PARTITION_NAME: &str = "config";
+const NAMESPACE: &str = "config";
+
+pub struct NvsConfiguration {
+    nvs: EspNvs<NvsCustom>,
+}
+
+impl NvsConfiguration {
+    pub fn take() -> Result<Self, StringError> {
+        if IS_NVS_TAKEN.load(Ordering::Relaxed) {
+            return Err(StringError("MainConfiguration NVS already taken"));
+        }
+
+        IS_NVS_TAKEN.store(true, Ordering::Relaxed);
+
+        let nvs_custom = match EspCustomNvsPartition::take(PARTITION_NAME) {
+            Ok(nvs) => nvs,
+            Err(_) => return Err(StringError("Fail to take partition")),
+        };
+
+        let mut config = match EspNvs::new(nvs_custom, NAMESPACE, true) {
+            Ok(nvs) => Self { nvs },
+            Err(_) => return Err(StringError("Failed to create EspNvs. Bad namespace ?")),
+        };
+
+        if let Err(e) = recover(&mut config) {
+            log::error!(
+                "Configuration commit recovery failed ({}), retry at next boot.",
+                e
+            );
+        }
+
+        if let Err(e) = migrate(&mut config) {
+            log::error!(
+                "Configuration migration failed ({}), retry at next boot.",
+                e
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+impl ConfigStore for NvsConfiguration {
+    type Error = StringEspError;
+
+    fn load_str(&self, key: &str) -> Option<String> {
+        let size = self.nvs.str_len(key).unwrap_or(None).unwrap_or(0);
+        let mut buf = vec![0; size];
+
+        if size == 0 {
+            return None;
+        }
+
+        self.nvs
+            .get_str(key, &mut buf)
+            .unwrap_or(None)
+            .map(str::to_string)
+    }
+
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.nvs
+            .set_str(key, value)
+            .map_err(|e| StringEspError("Failed to store string", e))
+    }
+
+    fn load_u8(&self, key: &str) -> Option<u8> {
+        self.nvs.get_u8(key).unwrap_or(None)
+    }
+
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error> {
+        self.nvs
+            .set_u8(key, value)
+            .map_err(|e| StringEspError("Failed to store U8", e))
+    }
+
+    fn load_u16(&self, key: &str) -> Option<u16> {
+        self.nvs.get_u16(key).unwrap_or(None)
+    }
+
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error> {
+        self.nvs
+            .set_u16(key, value)
+            .map_err(|e| StringEspError("Failed to store U16", e))
+    }
+
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>> {
+        let size = self.nvs.blob_len(key).unwrap_or(None).unwrap_or(0);
+        let mut buf = vec![0; size];
+
+        if size == 0 {
+            return None;
+        }
+
+        self.nvs
+            .get_blob(key, &mut buf)
+            .unwrap_or(None)
+            .map(<[u8]>::to_vec)
+    }
+
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.nvs
+            .set_blob(key, value)
+            .map_err(|e| StringEspError("Failed to store blob", e))
+    }
+}
+
+impl Drop for NvsConfiguration {
+    fn drop(&mut self) {
+        IS_NVS_TAKEN.store(false, Ordering::Relaxed);
+    }
+}
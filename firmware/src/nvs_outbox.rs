@@ -0,0 +1,105 @@
+use esp_idf_svc::nvs::{EspCustomNvsPartition, EspNvs, NvsCustom};
+use proxy_core::outbox::{OutboxMessage, SpillStorage};
+
+use crate::string_error::StringError;
+
+const PARTITION_NAME: &str = "outbox";
+const NAMESPACE: &str = "outbox";
+
+const KEY_HEAD: &str = "HEAD";
+const KEY_TAIL: &str = "TAIL";
+
+pub struct NvsOutboxStorage {
+    nvs: EspNvs<NvsCustom>,
+    capacity: usize,
+    head: u32,
+    tail: u32,
+}
+
+impl NvsOutboxStorage {
+    pub fn take(capacity: usize) -> Result<Self, StringError> {
+        let nvs_custom = match EspCustomNvsPartition::take(PARTITION_NAME) {
+            Ok(nvs) => nvs,
+            Err(_) => return Err(StringError("Fail to take outbox partition")),
+        };
+
+        let nvs = match EspNvs::new(nvs_custom, NAMESPACE, true) {
+            Ok(nvs) => nvs,
+            Err(_) => return Err(StringError("Failed to create EspNvs. Bad namespace ?")),
+        };
+
+        let head = nvs.get_u32(KEY_HEAD).unwrap_or(None).unwrap_or(0);
+        let tail = nvs.get_u32(KEY_TAIL).unwrap_or(None).unwrap_or(0);
+
+        if tail.wrapping_sub(head) as usize > capacity {
+            log::warn!("Outbox partition is inconsistent, discard spilled messages.");
+            return Ok(Self {
+                nvs,
+                capacity,
+                head: tail,
+                tail,
+            });
+        }
+
+        Ok(Self {
+            nvs,
+            capacity,
+            head,
+            tail,
+        })
+    }
+
+    fn slot_key(&self, index: u32) -> String {
+        format!("M{}", index as usize % self.capacity)
+    }
+}
+
+impl SpillStorage for NvsOutboxStorage {
+    fn len(&self) -> usize {
+        self.tail.wrapping_sub(self.head) as usize
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn push_back(&mut self, message: &OutboxMessage) -> Result<(), StringError> {
+        self.nvs
+            .set_blob(&self.slot_key(self.tail), &message.to_bytes())
+            .map_err(|_| StringError("Failed to store outbox message"))?;
+
+        self.tail = self.tail.wrapping_add(1);
+        self.nvs
+            .set_u32(KEY_TAIL, self.tail)
+            .map_err(|_| StringError("Failed to store outbox tail"))
+    }
+
+    fn front(&self) -> Result<Option<OutboxMessage>, StringError> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.slot_key(self.head);
+        let size = self.nvs.blob_len(&key).unwrap_or(None).unwrap_or(0);
+        let mut buf = vec![0; size];
+
+        match self.nvs.get_blob(&key, &mut buf) {
+            Ok(Some(bytes)) => OutboxMessage::from_bytes(bytes).map(Some),
+            _ => Err(StringError("Failed to read outbox message")),
+        }
+    }
+
+    fn pop_front(&mut self) -> Result<(), StringError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let key = self.slot_key(self.head);
+        let _ = self.nvs.remove(&key);
+
+        self.head = self.head.wrapping_add(1);
+        self.nvs
+            .set_u32(KEY_HEAD, self.head)
+            .map_err(|_| StringError("Failed to store outbox head"))
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    http::server::EspHttpServer,
+    wifi::{BlockingWifi, EspWifi},
+};
+use proxy_core::admin_auth::AdminAuth;
+use proxy_core::boot_guard::{
+    record_boot_failure, reset_boot_failures, StartupEvent, StartupGuard, MAX_BOOT_FAILURES,
+};
+use proxy_core::config::ConfigStore;
+use proxy_core::dhcp_server::DhcpServer;
+use proxy_core::discovery::HomeAssistantDiscovery;
+use proxy_core::link_supervisor::LinkState;
+use proxy_core::mode::{Mode, ModeMachine, Trigger};
+use proxy_core::mqtt;
+use proxy_core::outbox::Outbox;
+use proxy_core::sensor_allowlist::SensorAllowlist;
+use proxy_core::sensor_auth::{load_sensor_nonces, save_sensor_nonces, SensorAuth};
+use proxy_core::sta_ip::mask_prefix_len;
+
+use crate::ap_client_monitor::{uptime, ApClientMonitor};
+use crate::dhcp_service::DhcpService;
+use crate::http_server::{create_http_config_server, create_http_server, SensorAccess};
+use crate::mqtt_publisher::{EspMqttPublisher, MqttForwarder};
+use crate::napt::Napt;
+use crate::nvs_configuration::NvsConfiguration;
+use crate::nvs_outbox::NvsOutboxStorage;
+use crate::sta_supervisor::StaSupervisor;
+use crate::wifi_helper::{start_ap_sta_wifi, start_ap_wifi, EspWifiStatus};
+
+const OUTBOX_RAM_CAPACITY: usize = 16;
+const OUTBOX_FLASH_CAPACITY: usize = 64;
+
+/// Long lived objects shared by every mode.
+pub struct Context {
+    pub config: Arc<Mutex<NvsConfiguration>>,
+    pub wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    pub sys_loop: EspSystemEventLoop,
+    pub modes: Arc<Mutex<ModeMachine>>,
+    /// Kept between two proxy mode runs, so queued readings are not lost.
+    pub outbox: Option<Outbox<NvsOutboxStorage>>,
+}
+
+/// What runs in the current mode. Dropping it stops everything.
+pub enum Services {
+    Idle,
+    Config(ConfigServices),
+    Proxy(ProxyServices),
+}
+
+pub struct ConfigServices {
+    _http_server: EspHttpServer<'static>,
+    auth: Arc<Mutex<AdminAuth>>,
+    started: Instant,
+}
+
+pub struct ProxyServices {
+    _http_server: EspHttpServer<'static>,
+    forwarder: Arc<Mutex<MqttForwarder>>,
+    supervisor: StaSupervisor,
+    napt: Option<Napt>,
+    /// Set when DHCP reservations replace the IDF server.
+    dhcp: Option<DhcpService>,
+    ap_clients: ApClientMonitor,
+    /// Set when the AP client events are published.
+    ap_clients_topic: Option<String>,
+    sensor_auth: Arc<Mutex<SensorAuth>>,
+    allowlist: Arc<Mutex<SensorAllowlist>>,
+    diagnostics_topic: String,
+    startup: StartupGuard,
+}
+
+impl Context {
+    /// Writes the RAM part of the queue to flash, before a restart.
+    pub fn spill_outbox(&mut self) {
+        let Some(outbox) = &mut self.outbox else {
+            return;
+        };
+
+        match outbox.spill_ram() {
+            Ok(moved) if moved > 0 => log::info!("{} queued message(s) moved to flash.", moved),
+            Ok(_) => (),
+            Err(e) => log::error!("Failed to move queued messages to flash ({})", e),
+        }
+    }
+}
+
+impl Services {
+    /// Stops the running services and hands the outbox back to the context.
+    pub fn stop(self, context: &mut Context) {
+        if let Services::Proxy(proxy) = self {
+            if let Some(outbox) = proxy.stop(&context.config) {
+                context.outbox = Some(outbox);
+            }
+        }
+    }
+}
+
+impl ConfigServices {
+    pub fn start(context: &mut Context) -> anyhow::Result<Self> {
+        start_ap_wifi(
+            &mut context.wifi.lock().unwrap(),
+            &*context.config.lock().unwrap(),
+        )?;
+
+        let auth = Arc::new(Mutex::new(AdminAuth::new()));
+        let http_server = create_http_config_server(
+            context.config.clone(),
+            context.wifi.clone(),
+            context.modes.clone(),
+            auth.clone(),
+        )?;
+
+        Ok(Self {
+            _http_server: http_server,
+            auth,
+            started: Instant::now(),
+        })
+    }
+
+    /// Running for `time`, and no admin logged in.
+    pub fn is_unattended_for(&self, time: Duration) -> bool {
+        self.started.elapsed() >= time && !self.auth.lock().unwrap().has_sessions(uptime())
+    }
+}
+
+impl ProxyServices {
+    pub fn start(context: &mut Context) -> anyhow::Result<Self> {
+        // Subscribed first, the clients join again once the AP restarts.
+        let ap_clients = ApClientMonitor::new(&context.sys_loop)?;
+
+        start_ap_sta_wifi(
+            &mut context.wifi.lock().unwrap(),
+            &*context.config.lock().unwrap(),
+        )?;
+
+        let (sta_networks, sta_auth_mode) = {
+            let config = context.config.lock().unwrap();
+            (config.get_sta_networks(), config.get_sta_auth_mode())
+        };
+        let supervisor = StaSupervisor::new(
+            context.wifi.clone(),
+            sta_networks,
+            sta_auth_mode,
+            &context.sys_loop,
+        )?;
+
+        let outbox = match context.outbox.take() {
+            Some(outbox) => outbox,
+            None => Outbox::new(
+                OUTBOX_RAM_CAPACITY,
+                NvsOutboxStorage::take(OUTBOX_FLASH_CAPACITY)?,
+            ),
+        };
+
+        let sta_mac = context.wifi.lock().unwrap().wifi().sta_netif().get_mac()?;
+        let config = context.config.lock().unwrap();
+        let client_id = mqtt::client_id(&*config, &sta_mac);
+        log::info!("MQTT client ID: {}", client_id);
+
+        let mqtt = EspMqttPublisher::connect(&*config, &client_id, context.modes.clone());
+
+        let mqtt = match mqtt {
+            Ok(mqtt) => mqtt,
+            Err(e) => {
+                context.outbox = Some(outbox);
+                return Err(e.into());
+            }
+        };
+
+        let forwarder = Arc::new(Mutex::new(MqttForwarder::new(mqtt, outbox)));
+
+        let mut discovery = None;
+
+        if config.get_ha_discovery() {
+            let ha_discovery = HomeAssistantDiscovery::new(
+                &client_id,
+                &mqtt::availability_topic(&*config, &client_id),
+            );
+
+            for message in ha_discovery.proxy_messages() {
+                if let Err(e) = forwarder.lock().unwrap().forward(message) {
+                    log::warn!("Failed to announce the proxy ({})", e);
+                }
+            }
+
+            discovery = Some(Arc::new(Mutex::new(ha_discovery)));
+        }
+
+        let napt = config
+            .get_napt()
+            .then(|| Napt::new(context.wifi.clone(), config.get_port_forwards()));
+
+        let reservations = config.get_dhcp_reservations();
+        let dhcp = if reservations.is_empty() {
+            None
+        } else {
+            let server = DhcpServer::new(
+                config.get_ap_ip(),
+                mask_prefix_len(config.get_ap_mask()).unwrap_or(24),
+                reservations,
+            );
+
+            Some(DhcpService::start(
+                server,
+                ap_clients.table(),
+                context.wifi.clone(),
+                config.get_napt(),
+            )?)
+        };
+
+        let ap_clients_topic = config
+            .get_ap_client_events()
+            .then(|| mqtt::ap_clients_topic(&*config, &client_id));
+
+        let sensor_auth = SensorAuth::new(
+            config.get_sensor_credentials(),
+            &load_sensor_nonces(&*config),
+        );
+        if !sensor_auth.is_enabled() {
+            log::warn!("No sensor credentials, readings are not authenticated.");
+        }
+        let sensor_auth = Arc::new(Mutex::new(sensor_auth));
+
+        let allowlist =
+            SensorAllowlist::new(config.get_sensor_allowlist(), config.get_sensor_allow_all());
+        if allowlist.refuses_all() {
+            log::warn!("No allowed sensors, readings are refused until an enrolment.");
+        }
+        let allowlist = Arc::new(Mutex::new(allowlist));
+        let diagnostics_topic = mqtt::diagnostics_topic(&*config, &client_id);
+
+        let topic_prefix = config.get_mqtt_topic_prefix();
+        let routes = config
+            .get_sensor_routes()
+            .into_iter()
+            .map(|route| route.with_topic_prefix(&topic_prefix))
+            .collect();
+        drop(config);
+
+        let http_server = create_http_server(
+            forwarder.clone(),
+            routes,
+            discovery,
+            EspWifiStatus {
+                wifi: context.wifi.clone(),
+                link: supervisor.link(),
+            },
+            context.modes.clone(),
+            ap_clients.table(),
+            SensorAccess {
+                auth: sensor_auth.clone(),
+                allowlist: allowlist.clone(),
+            },
+        );
+
+        let http_server = match http_server {
+            Ok(http_server) => http_server,
+            Err(e) => {
+                forwarder.lock().unwrap().publisher_mut().announce_offline();
+                context.outbox = Arc::try_unwrap(forwarder)
+                    .ok()
+                    .map(|forwarder| forwarder.into_inner().unwrap().into_outbox());
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            _http_server: http_server,
+            forwarder,
+            supervisor,
+            napt,
+            dhcp,
+            ap_clients,
+            ap_clients_topic,
+            sensor_auth,
+            allowlist,
+            diagnostics_topic,
+            startup: StartupGuard::new(uptime()),
+        })
+    }
+
+    pub fn is_enrolling(&self) -> bool {
+        self.allowlist.lock().unwrap().is_enrolling()
+    }
+
+    /// Keeps the links alive and reports their health. Falls back to
+    /// configuration mode after `MAX_BOOT_FAILURES` failed start-ups.
+    pub fn poll(&mut self, config: &Mutex<NvsConfiguration>, modes: &Mutex<ModeMachine>) {
+        self.supervisor.poll();
+
+        if self.supervisor.take_association_failure() {
+            self.startup.on_association_failure();
+        }
+
+        if let Some(napt) = &mut self.napt {
+            napt.poll();
+        }
+
+        let is_enrolment_requested = modes.lock().unwrap().take_enrolment_request();
+        let now = uptime();
+        let (enrolled, rejections) = {
+            let mut allowlist = self.allowlist.lock().unwrap();
+
+            if is_enrolment_requested {
+                allowlist.start_enrolment(now);
+            }
+
+            allowlist.expire_enrolment(now);
+            (allowlist.take_enrolled(), allowlist.take_rejections(now))
+        };
+
+        if let Some(ids) = enrolled {
+            if let Err(e) = config.lock().unwrap().set_sensor_allowlist(&ids) {
+                log::error!("Failed to save the sensor allowlist ({})", e);
+            }
+        }
+
+        let nonces = self.sensor_auth.lock().unwrap().take_nonces(now);
+        if let Some(nonces) = nonces {
+            save_nonces(config, &nonces);
+        }
+
+        let mut forwarder = self.forwarder.lock().unwrap();
+        forwarder.publisher_mut().announce_online();
+
+        let events = self.ap_clients.table().lock().unwrap().take_events();
+        if let Some(topic) = &self.ap_clients_topic {
+            for event in events {
+                if let Err(e) = forwarder.forward(event.to_message(topic)) {
+                    log::warn!("Failed to queue AP client event ({})", e);
+                }
+            }
+        }
+
+        for rejection in rejections {
+            if let Err(e) = forwarder.forward(rejection.to_message(&self.diagnostics_topic)) {
+                log::warn!("Failed to queue diagnostics ({})", e);
+            }
+        }
+
+        forwarder.flush();
+
+        let is_associated = self.supervisor.link().lock().unwrap().state() == LinkState::Connected;
+        let healthy = forwarder.is_connected() && is_associated;
+        drop(forwarder);
+
+        modes.lock().unwrap().on_health(healthy);
+
+        match self.startup.poll(is_associated, uptime()) {
+            Some(StartupEvent::Confirmed) => {
+                log::info!("Start-up successful.");
+
+                if let Err(e) = reset_boot_failures(&mut *config.lock().unwrap()) {
+                    log::error!("Failed to reset boot failure counter ({})", e);
+                }
+            }
+            Some(StartupEvent::Failed) => match record_boot_failure(&mut *config.lock().unwrap()) {
+                Ok(failures) if failures >= MAX_BOOT_FAILURES => {
+                    log::warn!(
+                        "{} failed start-ups, fall back to configuration mode.",
+                        failures
+                    );
+                    let _ = modes
+                        .lock()
+                        .unwrap()
+                        .request_by(Mode::Config, Trigger::Fallback);
+                }
+                Ok(failures) => {
+                    log::warn!("Start-up failure {}/{}", failures, MAX_BOOT_FAILURES)
+                }
+                Err(e) => log::error!("Failed to record start-up failure ({})", e),
+            },
+            None => (),
+        }
+    }
+
+    /// Announces `offline`, then drops the HTTP server and the MQTT client.
+    /// Returns the outbox, unless an HTTP handler still holds the forwarder.
+    fn stop(self, config: &Mutex<NvsConfiguration>) -> Option<Outbox<NvsOutboxStorage>> {
+        self.forwarder
+            .lock()
+            .unwrap()
+            .publisher_mut()
+            .announce_offline();
+
+        drop(self._http_server);
+
+        let nonces = self.sensor_auth.lock().unwrap().take_unsaved_nonces();
+        if let Some(nonces) = nonces {
+            save_nonces(config, &nonces);
+        }
+
+        drop(self.dhcp);
+        drop(self.napt);
+        drop(self.supervisor);
+
+        match Arc::try_unwrap(self.forwarder) {
+            Ok(forwarder) => Some(forwarder.into_inner().unwrap().into_outbox()),
+            Err(_) => {
+                log::warn!("Forwarder still in use, queued readings stay in flash only.");
+                None
+            }
+        }
+    }
+}
+
+fn save_nonces(config: &Mutex<NvsConfiguration>, nonces: &HashMap<String, u64>) {
+    if let Err(e) = save_sensor_nonces(&mut *config.lock().unwrap(), nonces) {
+        log::error!("Failed to save the sensor nonces ({})", e);
+    }
+}
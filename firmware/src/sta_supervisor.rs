@@ -0,0 +1,165 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use esp_idf_svc::{
+    eventloop::{EspSubscription, EspSystemEventLoop, System},
+    sys::EspError,
+    wifi::{BlockingWifi, EspWifi, WifiEvent},
+};
+use proxy_core::auth_mode::AuthMode;
+use proxy_core::boot_guard::is_association_failure;
+use proxy_core::link_supervisor::{ExponentialBackoff, LinkState, LinkSupervisor};
+use proxy_core::sta_network::{rank_networks, scanned_auth_mode, ScannedNetwork, StaNetwork};
+
+use crate::wifi_helper::{from_auth_method, set_sta_network};
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(120);
+const CONNECT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps the station connected. Disconnections are caught on the system event
+/// loop, reconnections are started from `poll` so the AP is never touched.
+///
+/// Each attempt goes to the next candidate network. Once every candidate was
+/// tried, or after a disconnection, a new scan gives the next candidates, the
+/// strongest known network first.
+pub struct StaSupervisor {
+    wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    networks: Vec<StaNetwork>,
+    auth_mode: AuthMode,
+    candidates: Vec<usize>,
+    last_scan: Vec<ScannedNetwork>,
+    link: Arc<Mutex<LinkSupervisor>>,
+    link_lost: Arc<AtomicBool>,
+    /// Set when the access point refused the station.
+    association_failed: Arc<AtomicBool>,
+    started: Instant,
+    _subscription: EspSubscription<'static, System>,
+}
+
+impl StaSupervisor {
+    pub fn new(
+        wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+        networks: Vec<StaNetwork>,
+        auth_mode: AuthMode,
+        sys_loop: &EspSystemEventLoop,
+    ) -> Result<Self, EspError> {
+        let link_lost = Arc::new(AtomicBool::new(false));
+        let callback_link_lost = link_lost.clone();
+        let association_failed = Arc::new(AtomicBool::new(false));
+        let callback_association_failed = association_failed.clone();
+
+        let subscription = sys_loop.subscribe::<WifiEvent, _>(move |event| {
+            if let WifiEvent::StaDisconnected(disconnected) = event {
+                callback_link_lost.store(true, Ordering::Relaxed);
+
+                if is_association_failure(disconnected.reason().into()) {
+                    callback_association_failed.store(true, Ordering::Relaxed);
+                }
+            }
+        })?;
+
+        Ok(Self {
+            wifi,
+            networks,
+            auth_mode,
+            candidates: Vec::new(),
+            last_scan: Vec::new(),
+            link: Arc::new(Mutex::new(LinkSupervisor::new_idle(
+                ExponentialBackoff::new(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY),
+                CONNECT_ATTEMPT_TIMEOUT,
+                Duration::ZERO,
+            ))),
+            link_lost,
+            association_failed,
+            started: Instant::now(),
+            _subscription: subscription,
+        })
+    }
+
+    /// Shared view of the link, for the status reporting.
+    pub fn link(&self) -> Arc<Mutex<LinkSupervisor>> {
+        self.link.clone()
+    }
+
+    /// Whether the access point refused the station since the last call.
+    pub fn take_association_failure(&self) -> bool {
+        self.association_failed.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn poll(&mut self) {
+        let now = self.started.elapsed();
+        let mut link = self.link.lock().unwrap();
+
+        if self.link_lost.swap(false, Ordering::Relaxed) {
+            if link.state() == LinkState::Connected {
+                self.candidates.clear();
+            }
+
+            link.on_link_down(now);
+        }
+
+        let mut wifi = self.wifi.lock().unwrap();
+
+        if wifi.is_up().unwrap_or(false) {
+            link.on_link_up();
+        }
+
+        if !link.poll(now) {
+            return;
+        }
+
+        if self.networks.is_empty() {
+            log::warn!("No station network configured.");
+            link.on_link_down(now);
+            return;
+        }
+
+        if self.candidates.is_empty() {
+            self.last_scan = scan(&mut wifi);
+            self.candidates = rank_networks(&self.networks, &self.last_scan);
+        }
+
+        let network = &self.networks[self.candidates.remove(0)];
+        let auth_mode = self.auth_mode.resolve(
+            &network.passphrase,
+            scanned_auth_mode(&self.last_scan, &network.ssid),
+        );
+        log::info!(
+            "Connect station to {} with {} (attempt {})...",
+            network.ssid,
+            auth_mode.label(),
+            link.failures() + 1
+        );
+
+        let result = set_sta_network(&mut wifi, network, auth_mode)
+            .and_then(|_| wifi.wifi_mut().connect().map_err(Into::into));
+
+        if let Err(e) = result {
+            log::warn!("Failed to start station connection ({})", e);
+            link.on_link_down(now);
+        }
+    }
+}
+
+fn scan(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Vec<ScannedNetwork> {
+    match wifi.scan() {
+        Ok(aps) => aps
+            .into_iter()
+            .map(|ap| ScannedNetwork {
+                ssid: ap.ssid.to_string(),
+                rssi: ap.signal_strength,
+                auth_mode: ap.auth_method.and_then(from_auth_method),
+            })
+            .collect(),
+        Err(e) => {
+            log::warn!("Station scan failed ({})", e);
+            Vec::new()
+        }
+    }
+}
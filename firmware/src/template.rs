@@ -0,0 +1,224 @@
+use std::net::Ipv4Addr;
+
+use esp_idf_svc::wifi::AccessPointInfo;
+use proxy_core::auth_mode::{AuthMode, AUTH_MODES};
+use proxy_core::dhcp_server::DhcpReservation;
+use proxy_core::html_template::{escape_html, HtmlTemplate};
+use proxy_core::port_forward::PortForward;
+use proxy_core::proxy_config::{
+    FieldError, ProxyConfig, FIELD_STA_PASSPHRASES, FIELD_STA_SSIDS, FORM_FIELDS,
+};
+use proxy_core::regulatory::COUNTRIES;
+use proxy_core::sensor_auth::SensorCredential;
+use proxy_core::sensor_route::SensorRoute;
+use proxy_core::sta_network::MAX_STA_NETWORKS;
+use serde_json::{json, Value};
+
+const BASE_HTML: &str = include_str!("html/base.html");
+const LOGIN_HTML: &str = include_str!("html/login.html");
+
+pub fn to_html(
+    config: &ProxyConfig,
+    aps: Option<Vec<AccessPointInfo>>,
+    error_message: Option<String>,
+    field_errors: &[FieldError],
+) -> String {
+    let mut template = HtmlTemplate::new(BASE_HTML);
+
+    template
+        .text("ERROR_MSG", &error_message.unwrap_or_default())
+        .json("AP_LIST", accespoint_to_template(aps))
+        .text("MQTTSRV", &config.mqtt_server)
+        .text("MQTTPRT", &config.mqtt_port.to_string());
+
+    for i in 0..MAX_STA_NETWORKS {
+        let network = config.sta_networks.get(i);
+
+        template
+            .text(
+                &FIELD_STA_SSIDS[i].to_uppercase(),
+                network.map(|n| n.ssid.as_str()).unwrap_or(""),
+            )
+            .text(
+                &format!("{}_STATE", FIELD_STA_PASSPHRASES[i].to_uppercase()),
+                secret_to_template(network.map(|n| n.passphrase.as_str()).unwrap_or("")),
+            );
+    }
+
+    template
+        .markup(
+            "STAAUTH_OPTIONS",
+            auth_modes_to_template(config.sta_auth_mode),
+        )
+        .checked("STASTATIC_CHECKED", config.sta_ip.static_ip)
+        .text("STAIP", &ipv4_to_template(config.sta_ip.ip))
+        .text("STAMASK", &ipv4_to_template(config.sta_ip.mask))
+        .text("STAGW", &ipv4_to_template(config.sta_ip.gateway))
+        .text("STADNS1", &ipv4_to_template(config.sta_ip.dns))
+        .text("STADNS2", &ipv4_to_template(config.sta_ip.secondary_dns))
+        .text("STAHOST", &config.sta_ip.hostname)
+        .text("APSSID", &config.ap_ssid)
+        .text("APPASS_STATE", secret_to_template(&config.ap_passphrase))
+        .checked("APHIDDEN_CHECKED", config.ap_hidden_ssid)
+        .markup(
+            "APAUTH_OPTIONS",
+            auth_modes_to_template(config.ap_auth_mode),
+        )
+        .text("APIP", &config.ap_ip.to_string())
+        .text("APMASK", &config.ap_mask.to_string())
+        .text("APCHAN", &config.ap_channel.to_string())
+        .markup("APCC_OPTIONS", countries_to_template(&config.ap_country))
+        .text("APMAXCL", &config.ap_max_clients.to_string())
+        .checked("APCLIEVT_CHECKED", config.ap_client_events)
+        .json(
+            "DHCPRES",
+            Value::Array(
+                config
+                    .dhcp_reservations
+                    .iter()
+                    .map(DhcpReservation::to_json)
+                    .collect(),
+            ),
+        )
+        .checked("NAPT_CHECKED", config.napt)
+        .json(
+            "PORTFWD",
+            Value::Array(
+                config
+                    .port_forwards
+                    .iter()
+                    .map(PortForward::to_json)
+                    .collect(),
+            ),
+        )
+        .text("MQTTUSER", &config.mqtt_username)
+        .text("MQTTPASS_STATE", secret_to_template(&config.mqtt_password))
+        .checked("MQTTTLS_CHECKED", config.mqtt_tls)
+        .text("MQTTCA", &config.mqtt_ca_cert)
+        .text("MQTTCLID", &config.mqtt_client_id)
+        .text("MQTTPFX", &config.mqtt_topic_prefix)
+        .checked("HADISCO_CHECKED", config.ha_discovery)
+        .json(
+            "ROUTES",
+            Value::Array(
+                config
+                    .sensor_routes
+                    .iter()
+                    .map(SensorRoute::to_json)
+                    .collect(),
+            ),
+        )
+        .json(
+            "SENSCRED",
+            Value::Array(
+                config
+                    .sensor_credentials
+                    .iter()
+                    .map(SensorCredential::to_public_json)
+                    .collect(),
+            ),
+        )
+        .json("SENSALLOW", Value::from(config.sensor_allowlist.as_slice()))
+        .checked("SENSALLALL_CHECKED", config.sensor_allow_all);
+
+    for field in FORM_FIELDS {
+        template.text(
+            &format!("{}_ERR", field.to_uppercase()),
+            field_errors
+                .iter()
+                .find(|e| e.field == *field)
+                .map(|e| e.message)
+                .unwrap_or(""),
+        );
+    }
+
+    template.render()
+}
+
+/// Login page, or the first use page setting the admin password when
+/// `setup` is true.
+pub fn to_login_html(setup: bool, error_message: Option<&str>) -> String {
+    HtmlTemplate::new(LOGIN_HTML)
+        .text(
+            "LOGIN_TITLE",
+            if setup {
+                "Set the admin password"
+            } else {
+                "Proxy Settings"
+            },
+        )
+        .markup(
+            "CONFIRM_FIELD",
+            if setup {
+                "<label for=\"confirm\">Confirm: </label><input type=\"password\" id=\"confirm\" name=\"confirm\" maxlength=\"64\" required/>"
+            } else {
+                ""
+            }
+            .to_string(),
+        )
+        .text("LOGIN_ACTION", if setup { "Set" } else { "Log in" })
+        .text("LOGIN_ERR", error_message.unwrap_or(""))
+        .render()
+}
+
+fn auth_modes_to_template(selected: AuthMode) -> String {
+    AUTH_MODES
+        .iter()
+        .map(|mode| {
+            format!(
+                "<option value=\"{}\"{}>{}</option>",
+                mode.as_str(),
+                if *mode == selected { " selected" } else { "" },
+                escape_html(mode.label())
+            )
+        })
+        .collect()
+}
+
+fn countries_to_template(selected: &str) -> String {
+    COUNTRIES
+        .iter()
+        .map(|country| {
+            format!(
+                "<option value=\"{}\" data-channels=\"{}\"{}>{} ({})</option>",
+                country.code,
+                country.last_channel(),
+                if country.code == selected {
+                    " selected"
+                } else {
+                    ""
+                },
+                escape_html(country.name),
+                country.code
+            )
+        })
+        .collect()
+}
+
+/// Secrets are write-only, only their presence is shown.
+fn secret_to_template(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        "not set"
+    } else {
+        "set"
+    }
+}
+
+fn ipv4_to_template(ip: Option<Ipv4Addr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_default()
+}
+
+/// Scanned networks, for `load_ssid` in the page script.
+fn accespoint_to_template(aps: Option<Vec<AccessPointInfo>>) -> Value {
+    Value::Array(
+        aps.unwrap_or_default()
+            .iter()
+            .map(|ap| {
+                json!({
+                    "ssid": ap.ssid.as_str(),
+                    "rssi": ap.signal_strength,
+                })
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,342 @@
+use esp_idf_svc::hal::sys::{
+    esp, esp_wifi_set_config, esp_wifi_set_country, wifi_auth_mode_t,
+    wifi_auth_mode_t_WIFI_AUTH_OPEN, wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK,
+    wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK, wifi_config_t,
+    wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_interface_t_WIFI_IF_STA,
+    wifi_pmf_config_t, wifi_scan_method_t_WIFI_ALL_CHANNEL_SCAN, wifi_scan_threshold_t,
+    wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL, wifi_sta_config_t,
+};
+use esp_idf_svc::hal::{modem::Modem, peripheral::Peripheral, sys::wifi_country_t};
+use esp_idf_svc::wifi::AccessPointConfiguration;
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    ipv4::{self, Mask, Subnet},
+    netif::{EspNetif, NetifConfiguration, NetifStack},
+    nvs::EspDefaultNvsPartition,
+    wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, WifiDriver},
+};
+use proxy_core::auth_mode::AuthMode;
+use proxy_core::config::ConfigStore;
+use proxy_core::link_supervisor::{LinkState, LinkSupervisor};
+use proxy_core::regulatory::{find_country, DEFAULT_COUNTRY};
+use proxy_core::sta_ip::{mask_prefix_len, StaIpConfig};
+use proxy_core::sta_network::StaNetwork;
+use proxy_core::wifi_status::WifiStatus;
+
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+
+pub struct EspWifiStatus {
+    pub wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    pub link: Arc<Mutex<LinkSupervisor>>,
+}
+
+impl WifiStatus for EspWifiStatus {
+    fn is_sta_connected(&self) -> bool {
+        self.wifi.lock().unwrap().is_connected().unwrap_or(false)
+    }
+
+    fn sta_ip(&self) -> Option<Ipv4Addr> {
+        self.wifi
+            .lock()
+            .unwrap()
+            .wifi()
+            .sta_netif()
+            .get_ip_info()
+            .ok()
+            .map(|info| info.ip)
+            .filter(|ip| !ip.is_unspecified())
+    }
+
+    fn sta_link_state(&self) -> LinkState {
+        self.link.lock().unwrap().state()
+    }
+}
+
+/// Driver and interfaces, created once and reconfigured on each mode switch.
+/// The AP interface and the country are set from the configuration on start.
+pub fn create_wifi<'a>(
+    modem: impl Peripheral<P = Modem> + 'a,
+    sys_loop: EspSystemEventLoop,
+) -> anyhow::Result<BlockingWifi<EspWifi<'a>>> {
+    let nvs = EspDefaultNvsPartition::take()?;
+
+    let wifi_drv = WifiDriver::new(modem, sys_loop.clone(), Some(nvs))?;
+    let wifi_esp = EspWifi::wrap_all(
+        wifi_drv,
+        EspNetif::new(NetifStack::Sta)?,
+        EspNetif::new(NetifStack::Ap)?,
+    )?;
+
+    let wifi = BlockingWifi::wrap(wifi_esp, sys_loop)?;
+
+    Ok(wifi)
+}
+
+/// Starts the AP and the station interface, the `StaSupervisor` picks the
+/// upstream network and connects. The station netif is recreated so the saved
+/// DHCP or static settings apply. With DHCP reservations, the AP addresses are
+/// given by the `DhcpService` instead of the IDF server.
+pub fn start_ap_sta_wifi(
+    wifi: &mut BlockingWifi<EspWifi<'_>>,
+    main_config: &impl ConfigStore,
+) -> anyhow::Result<()> {
+    if wifi.is_started()? {
+        log::info!("Stop WiFi...");
+        wifi.stop()?;
+    }
+
+    let sta_netif = EspNetif::new_with_conf(&generate_sta_netif_configuration(
+        &main_config.get_sta_ip_config(),
+    ))?;
+    wifi.wifi_mut().swap_netif_sta(sta_netif)?;
+
+    restart_wifi(
+        wifi,
+        main_config,
+        main_config.get_dhcp_reservations().is_empty(),
+        &Configuration::Mixed(
+            ClientConfiguration::default(),
+            generate_accespoint_configuration(main_config),
+        ),
+    )
+}
+
+/// Points the station to `network`. Only the station interface is
+/// configured: setting a `Mixed` configuration applies the AP one again,
+/// which can restart the softAP and drop the sensors. The driver is taken to
+/// serialize with its other users. `auth_mode` must be resolved, see
+/// `AuthMode::resolve`.
+pub fn set_sta_network(
+    _wifi: &mut BlockingWifi<EspWifi<'_>>,
+    network: &StaNetwork,
+    auth_mode: AuthMode,
+) -> anyhow::Result<()> {
+    let mut configuration = wifi_config_t {
+        sta: generate_sta_config(network, auth_mode)?,
+    };
+
+    esp!(unsafe { esp_wifi_set_config(wifi_interface_t_WIFI_IF_STA, &mut configuration) })?;
+
+    Ok(())
+}
+
+pub fn start_ap_wifi(
+    wifi: &mut BlockingWifi<EspWifi<'_>>,
+    main_config: &impl ConfigStore,
+) -> anyhow::Result<()> {
+    restart_wifi(
+        wifi,
+        main_config,
+        true,
+        &Configuration::Mixed(
+            ClientConfiguration::default(),
+            generate_accespoint_configuration(main_config),
+        ),
+    )
+}
+
+/// Recreates the AP interface and applies the country before starting, so
+/// subnet and regulatory changes are picked up on each mode switch.
+fn restart_wifi(
+    wifi: &mut BlockingWifi<EspWifi<'_>>,
+    main_config: &impl ConfigStore,
+    builtin_dhcp: bool,
+    configuration: &Configuration,
+) -> anyhow::Result<()> {
+    if wifi.is_started()? {
+        log::info!("Stop WiFi...");
+        wifi.stop()?;
+    }
+
+    let ap_netif =
+        EspNetif::new_with_conf(&generate_ap_netif_configuration(main_config, builtin_dhcp))?;
+    wifi.wifi_mut().swap_netif_ap(ap_netif)?;
+
+    let country = generate_country_setting(main_config);
+    esp!(unsafe { esp_wifi_set_country(&country) })?;
+
+    wifi.set_configuration(configuration)?;
+
+    log::info!("Start WiFi...");
+    wifi.start()?;
+
+    Ok(())
+}
+
+/// Raw station settings, the `ClientConfiguration` conversion of the
+/// service crate is private.
+fn generate_sta_config(
+    network: &StaNetwork,
+    auth_mode: AuthMode,
+) -> anyhow::Result<wifi_sta_config_t> {
+    let mut config = wifi_sta_config_t {
+        scan_method: wifi_scan_method_t_WIFI_ALL_CHANNEL_SCAN,
+        sort_method: wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL,
+        threshold: wifi_scan_threshold_t {
+            rssi: -127,
+            authmode: to_sta_threshold(auth_mode),
+            ..Default::default()
+        },
+        pmf_cfg: wifi_pmf_config_t {
+            capable: true,
+            required: false,
+        },
+        ..Default::default()
+    };
+
+    copy_bytes(&mut config.ssid, &network.ssid, "SSID too long")?;
+    copy_bytes(
+        &mut config.password,
+        &network.passphrase,
+        "Passphrase too long",
+    )?;
+
+    Ok(config)
+}
+
+/// For the station the threshold is the weakest accepted method, a
+/// transition mode network is joined with WPA3 when possible.
+fn to_sta_threshold(mode: AuthMode) -> wifi_auth_mode_t {
+    match mode {
+        AuthMode::Open => wifi_auth_mode_t_WIFI_AUTH_OPEN,
+        AuthMode::Auto | AuthMode::Wpa2 | AuthMode::Wpa2Wpa3 => wifi_auth_mode_t_WIFI_AUTH_WPA2_PSK,
+        AuthMode::Wpa3 => wifi_auth_mode_t_WIFI_AUTH_WPA3_PSK,
+    }
+}
+
+fn copy_bytes(dest: &mut [u8], value: &str, error: &'static str) -> anyhow::Result<()> {
+    let bytes = value.as_bytes();
+
+    if bytes.len() > dest.len() {
+        return Err(anyhow::Error::msg(error));
+    }
+
+    dest[..bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// `mode` must be resolved, `Auto` is mapped as WPA2.
+fn to_auth_method(mode: AuthMode) -> AuthMethod {
+    match mode {
+        AuthMode::Open => AuthMethod::None,
+        AuthMode::Auto | AuthMode::Wpa2 => AuthMethod::WPA2Personal,
+        AuthMode::Wpa3 => AuthMethod::WPA3Personal,
+        AuthMode::Wpa2Wpa3 => AuthMethod::WPA2WPA3Personal,
+    }
+}
+
+/// `None` for the methods the proxy does not handle (WEP, WPA, enterprise...).
+pub fn from_auth_method(method: AuthMethod) -> Option<AuthMode> {
+    match method {
+        AuthMethod::None => Some(AuthMode::Open),
+        AuthMethod::WPA2Personal | AuthMethod::WPAWPA2Personal => Some(AuthMode::Wpa2),
+        AuthMethod::WPA3Personal => Some(AuthMode::Wpa3),
+        AuthMethod::WPA2WPA3Personal => Some(AuthMode::Wpa2Wpa3),
+        _ => None,
+    }
+}
+
+fn generate_ap_netif_configuration(
+    main_config: &impl ConfigStore,
+    builtin_dhcp: bool,
+) -> NetifConfiguration {
+    let ap_ip = main_config.get_ap_ip();
+    let prefix_len = mask_prefix_len(main_config.get_ap_mask()).unwrap_or(24);
+    log::info!("AP address {}/{}", ap_ip, prefix_len);
+
+    // With NAPT the clients get a DNS server, replaced by the upstream one
+    // once the station is up.
+    let dns = main_config.get_napt().then(|| {
+        let sta_ip = main_config.get_sta_ip_config();
+        sta_ip.dns.filter(|_| sta_ip.static_ip).unwrap_or(ap_ip)
+    });
+
+    NetifConfiguration {
+        ip_configuration: ipv4::Configuration::Router(ipv4::RouterConfiguration {
+            subnet: Subnet {
+                gateway: ap_ip,
+                mask: Mask(prefix_len),
+            },
+            dhcp_enabled: builtin_dhcp,
+            dns,
+            ..Default::default()
+        }),
+        ..NetifConfiguration::wifi_default_router()
+    }
+}
+
+/// Unknown codes fall back to the default country. The policy is manual so
+/// the station does not adopt the country advertised by the upstream AP.
+fn generate_country_setting(main_config: &impl ConfigStore) -> wifi_country_t {
+    let country = find_country(&main_config.get_ap_country())
+        .or_else(|| find_country(DEFAULT_COUNTRY))
+        .unwrap();
+    let code = country.code.as_bytes();
+
+    wifi_country_t {
+        cc: [code[0] as i8, code[1] as i8, 0 as i8],
+        schan: country.first_channel,
+        nchan: country.channel_count,
+        max_tx_power: 80,
+        policy: wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL,
+        ..Default::default()
+    }
+}
+
+fn generate_sta_netif_configuration(sta_ip: &StaIpConfig) -> NetifConfiguration {
+    let client_configuration = match (
+        sta_ip.static_ip,
+        sta_ip.ip,
+        sta_ip.gateway,
+        sta_ip.prefix_len(),
+    ) {
+        (true, Some(ip), Some(gateway), Some(prefix_len)) => {
+            log::info!("Station static address {}/{}", ip, prefix_len);
+
+            ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                ip,
+                subnet: Subnet {
+                    gateway,
+                    mask: Mask(prefix_len),
+                },
+                dns: sta_ip.dns,
+                secondary_dns: sta_ip.secondary_dns,
+            })
+        }
+        (static_ip, ..) => {
+            if static_ip {
+                log::warn!("Incomplete static address settings, fall back to DHCP.");
+            }
+
+            ipv4::ClientConfiguration::DHCP(ipv4::DHCPClientSettings {
+                hostname: (!sta_ip.hostname.is_empty())
+                    .then(|| sta_ip.hostname.as_str().try_into().ok())
+                    .flatten(),
+            })
+        }
+    };
+
+    NetifConfiguration {
+        ip_configuration: ipv4::Configuration::Client(client_configuration),
+        ..NetifConfiguration::wifi_default_client()
+    }
+}
+
+fn generate_accespoint_configuration(main_config: &impl ConfigStore) -> AccessPointConfiguration {
+    AccessPointConfiguration {
+        ssid: main_config.get_ap_ssid().as_str().try_into().unwrap(),
+        ssid_hidden: main_config.get_ap_hidden_ssid(),
+        auth_method: to_auth_method(
+            main_config
+                .get_ap_auth_mode()
+                .resolve(&main_config.get_ap_passphrase(), None),
+        ),
+        password: main_config.get_ap_passphrase().as_str().try_into().unwrap(),
+        max_connections: main_config.get_ap_max_clients() as u16,
+        channel: main_config.get_ap_channel(),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,616 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::ConfigStore;
+use crate::hex::{constant_time_eq, from_hex, to_hex};
+use crate::string_error::StringError;
+
+pub const KEY_ADMIN_PASSWORD: &str = "ADMINPW";
+
+pub const MIN_ADMIN_PASSWORD_LEN: usize = 8;
+pub const MAX_ADMIN_PASSWORD_LEN: usize = 64;
+pub const SALT_LEN: usize = 16;
+pub const SESSION_TOKEN_LEN: usize = 16;
+pub const SESSION_COOKIE: &str = "session";
+
+/// Consecutive wrong passwords of a client before its logins are locked.
+pub const MAX_FAILED_LOGINS: u32 = 5;
+pub const LOCKOUT_TIME: Duration = Duration::from_secs(5 * 60);
+/// A session unused that long must log in again.
+pub const SESSION_IDLE_TIME: Duration = Duration::from_secs(30 * 60);
+/// Above, the oldest session is closed.
+const MAX_SESSIONS: usize = 4;
+/// Above, the client with the oldest failed login is forgotten.
+const MAX_TRACKED_CLIENTS: usize = 16;
+
+/// Slows the brute force of a leaked hash, still a fraction of a second on
+/// the device.
+const PBKDF2_ROUNDS: u32 = 4096;
+/// Above, a stored hash is refused: checking a password would hold the portal
+/// for minutes.
+const MAX_PBKDF2_ROUNDS: u32 = 16 * PBKDF2_ROUNDS;
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Salted PBKDF2-HMAC-SHA256 of the admin password, as stored in NVS.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PasswordHash {
+    rounds: u32,
+    salt: [u8; SALT_LEN],
+    hash: [u8; 32],
+}
+
+impl PasswordHash {
+    pub fn new(password: &str, salt: [u8; SALT_LEN]) -> Self {
+        Self {
+            rounds: PBKDF2_ROUNDS,
+            salt,
+            hash: pbkdf2(password.as_bytes(), &salt, PBKDF2_ROUNDS),
+        }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        constant_time_eq(
+            &pbkdf2(password.as_bytes(), &self.salt, self.rounds),
+            &self.hash,
+        )
+    }
+
+    /// `pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>`
+    pub fn to_stored(&self) -> String {
+        format!(
+            "{}${}${}${}",
+            HASH_SCHEME,
+            self.rounds,
+            to_hex(&self.salt),
+            to_hex(&self.hash)
+        )
+    }
+
+    pub fn from_stored(s: &str) -> Option<Self> {
+        let mut parts = s.split('$');
+
+        if parts.next()? != HASH_SCHEME {
+            return None;
+        }
+
+        let rounds = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|r| (1..=MAX_PBKDF2_ROUNDS).contains(r))?;
+        let salt = from_hex(parts.next()?)?.try_into().ok()?;
+        let hash = from_hex(parts.next()?)?.try_into().ok()?;
+
+        parts
+            .next()
+            .is_none()
+            .then_some(Self { rounds, salt, hash })
+    }
+}
+
+/// `None` until the password is set on first use of the portal. An invalid
+/// stored hash is an error, not a missing password: the portal must stay
+/// locked until it is set again on purpose.
+pub fn admin_password(store: &impl ConfigStore) -> Result<Option<PasswordHash>, StringError> {
+    let Some(stored) = store.load_str(KEY_ADMIN_PASSWORD) else {
+        return Ok(None);
+    };
+
+    PasswordHash::from_stored(&stored)
+        .map(Some)
+        .ok_or(StringError("Invalid stored admin password"))
+}
+
+pub fn set_admin_password<S: ConfigStore>(
+    store: &mut S,
+    password: &str,
+    salt: [u8; SALT_LEN],
+) -> Result<(), S::Error> {
+    store.save_str(
+        KEY_ADMIN_PASSWORD,
+        &PasswordHash::new(password, salt).to_stored(),
+    )
+}
+
+pub fn validate_admin_password(password: &str, confirmation: &str) -> Result<(), &'static str> {
+    let len = password.chars().count();
+
+    if len < MIN_ADMIN_PASSWORD_LEN {
+        Err("Admin password must be at least 8 characters")
+    } else if len > MAX_ADMIN_PASSWORD_LEN {
+        Err("Admin password must be at most 64 characters")
+    } else if password != confirmation {
+        Err("Passwords do not match")
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LoginError {
+    WrongPassword {
+        attempts_left: u32,
+    },
+    /// Remaining lockout time.
+    LockedOut(Duration),
+}
+
+#[derive(Clone, Debug)]
+struct Session {
+    token: String,
+    expires: Duration,
+}
+
+#[derive(Clone, Debug)]
+struct FailedLogins {
+    client: Ipv4Addr,
+    failures: u32,
+    locked_until: Option<Duration>,
+    last: Duration,
+}
+
+/// Sessions and failed logins of the portal, kept in RAM so a restart closes
+/// the sessions. The lockout is per client address, so another AP client
+/// cannot lock the admin out.
+#[derive(Clone, Default, Debug)]
+pub struct AdminAuth {
+    failed_logins: Vec<FailedLogins>,
+    sessions: Vec<Session>,
+}
+
+impl AdminAuth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks the session of a `Cookie` header, and extends it.
+    pub fn is_authenticated(&mut self, cookie_header: Option<&str>, now: Duration) -> bool {
+        self.sessions.retain(|session| session.expires > now);
+
+        let Some(token) = cookie_header.and_then(session_token) else {
+            return false;
+        };
+
+        match self
+            .sessions
+            .iter_mut()
+            .find(|session| constant_time_eq(session.token.as_bytes(), token.as_bytes()))
+        {
+            Some(session) => {
+                session.expires = now + SESSION_IDLE_TIME;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Opens a session when the password matches. The password is not
+    /// checked at all while `client` is locked out. Returns the `Set-Cookie`
+    /// value.
+    pub fn login(
+        &mut self,
+        hash: &PasswordHash,
+        password: &str,
+        client: Ipv4Addr,
+        token: [u8; SESSION_TOKEN_LEN],
+        now: Duration,
+    ) -> Result<String, LoginError> {
+        if let Some(remaining) = self.lockout_remaining(client, now) {
+            return Err(LoginError::LockedOut(remaining));
+        }
+
+        if hash.verify(password) {
+            self.failed_logins.retain(|logins| logins.client != client);
+            return Ok(self.open_session(token, now));
+        }
+
+        let logins = self.failed_logins_of(client, now);
+        logins.failures += 1;
+        logins.last = now;
+        log::warn!(
+            "Portal login failed from {} ({}/{})",
+            client,
+            logins.failures,
+            MAX_FAILED_LOGINS
+        );
+
+        if logins.failures >= MAX_FAILED_LOGINS {
+            log::warn!(
+                "Portal logins from {} locked for {}s",
+                client,
+                LOCKOUT_TIME.as_secs()
+            );
+            logins.failures = 0;
+            logins.locked_until = Some(now + LOCKOUT_TIME);
+            return Err(LoginError::LockedOut(LOCKOUT_TIME));
+        }
+
+        Err(LoginError::WrongPassword {
+            attempts_left: MAX_FAILED_LOGINS - logins.failures,
+        })
+    }
+
+    /// An open session keeps the portal attended.
+    pub fn has_sessions(&mut self, now: Duration) -> bool {
+        self.sessions.retain(|session| session.expires > now);
+        !self.sessions.is_empty()
+    }
+
+    /// Also used right after the password is set. Returns the `Set-Cookie`
+    /// value.
+    pub fn open_session(&mut self, token: [u8; SESSION_TOKEN_LEN], now: Duration) -> String {
+        self.sessions.retain(|session| session.expires > now);
+
+        if self.sessions.len() >= MAX_SESSIONS {
+            self.sessions.remove(0);
+        }
+
+        let token = to_hex(&token);
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Strict",
+            SESSION_COOKIE, token
+        );
+
+        self.sessions.push(Session {
+            token,
+            expires: now + SESSION_IDLE_TIME,
+        });
+
+        cookie
+    }
+
+    /// Closes the session of a `Cookie` header. Returns the `Set-Cookie`
+    /// value clearing it.
+    pub fn logout(&mut self, cookie_header: Option<&str>) -> String {
+        if let Some(token) = cookie_header.and_then(session_token) {
+            self.sessions.retain(|session| session.token != token);
+        }
+
+        format!("{}=; Path=/; Max-Age=0", SESSION_COOKIE)
+    }
+
+    pub fn lockout_remaining(&self, client: Ipv4Addr, now: Duration) -> Option<Duration> {
+        self.failed_logins
+            .iter()
+            .find(|logins| logins.client == client)
+            .and_then(|logins| logins.locked_until)
+            .filter(|&until| until > now)
+            .map(|until| until - now)
+    }
+
+    fn failed_logins_of(&mut self, client: Ipv4Addr, now: Duration) -> &mut FailedLogins {
+        let index = match self
+            .failed_logins
+            .iter()
+            .position(|logins| logins.client == client)
+        {
+            Some(index) => index,
+            None => {
+                if self.failed_logins.len() >= MAX_TRACKED_CLIENTS {
+                    self.forget_oldest_client();
+                }
+
+                self.failed_logins.push(FailedLogins {
+                    client,
+                    failures: 0,
+                    locked_until: None,
+                    last: now,
+                });
+                self.failed_logins.len() - 1
+            }
+        };
+
+        &mut self.failed_logins[index]
+    }
+
+    fn forget_oldest_client(&mut self) {
+        if let Some(oldest) = self
+            .failed_logins
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, logins)| logins.last)
+            .map(|(index, _)| index)
+        {
+            self.failed_logins.remove(oldest);
+        }
+    }
+}
+
+/// Value of the session cookie in a `Cookie` header.
+pub fn session_token(cookie_header: &str) -> Option<&str> {
+    cookie_header
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+fn pbkdf2(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let keyed = HmacSha256::new_from_slice(password).expect("HMAC accepts any key length");
+
+    // A single block, the output is the size of the hash.
+    let mut mac = keyed.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block: [u8; 32] = mac.finalize().into_bytes().into();
+    let mut result = block;
+
+    for _ in 1..rounds {
+        let mut mac = keyed.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes().into();
+
+        for (r, b) in result.iter_mut().zip(block.iter()) {
+            *r ^= b;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryConfigStore;
+
+    const ADMIN: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 2);
+    const OTHER: Ipv4Addr = Ipv4Addr::new(192, 168, 71, 3);
+
+    fn hash() -> PasswordHash {
+        PasswordHash::new("correct horse", [7; SALT_LEN])
+    }
+
+    fn cookie_header(set_cookie: &str) -> String {
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn pbkdf2_matches_known_answers() {
+        let cases = [
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ];
+
+        for (rounds, expected) in cases {
+            assert_eq!(to_hex(&pbkdf2(b"password", b"salt", rounds)), expected);
+        }
+    }
+
+    #[test]
+    fn password_is_verified() {
+        let hash = hash();
+
+        assert!(hash.verify("correct horse"));
+        assert!(!hash.verify("correct horse "));
+        assert!(!hash.verify(""));
+    }
+
+    #[test]
+    fn stored_hash_round_trips() {
+        let hash = hash();
+        let stored = hash.to_stored();
+
+        assert!(stored.starts_with("pbkdf2-sha256$4096$"));
+        assert_eq!(PasswordHash::from_stored(&stored), Some(hash));
+    }
+
+    #[test]
+    fn malformed_stored_hash_is_refused() {
+        let salt = to_hex(&[7; SALT_LEN]);
+        let digest = to_hex(&[1; 32]);
+        let stored = |scheme: &str, rounds: &str, salt: &str, digest: &str| {
+            format!("{}${}${}${}", scheme, rounds, salt, digest)
+        };
+
+        assert!(PasswordHash::from_stored(&stored(HASH_SCHEME, "4096", &salt, &digest)).is_some());
+
+        for bad in [
+            String::new(),
+            stored("pbkdf2-sha1", "4096", &salt, &digest),
+            stored(HASH_SCHEME, "0", &salt, &digest),
+            stored(HASH_SCHEME, "-1", &salt, &digest),
+            stored(
+                HASH_SCHEME,
+                &(MAX_PBKDF2_ROUNDS + 1).to_string(),
+                &salt,
+                &digest,
+            ),
+            stored(HASH_SCHEME, "4096", "zz", &digest),
+            stored(HASH_SCHEME, "4096", &salt[2..], &digest),
+            stored(HASH_SCHEME, "4096", &salt, &digest[2..]),
+            format!("{}$4096${}", HASH_SCHEME, salt),
+            format!("{}$extra", stored(HASH_SCHEME, "4096", &salt, &digest)),
+        ] {
+            assert_eq!(PasswordHash::from_stored(&bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn corrupt_admin_password_is_an_error() {
+        let mut store = MemoryConfigStore::new();
+        assert_eq!(admin_password(&store), Ok(None));
+
+        set_admin_password(&mut store, "correct horse", [7; SALT_LEN]).unwrap();
+        assert_eq!(admin_password(&store), Ok(Some(hash())));
+
+        store.save_str(KEY_ADMIN_PASSWORD, "garbage").unwrap();
+        assert!(admin_password(&store).is_err());
+    }
+
+    #[test]
+    fn admin_password_rules() {
+        assert!(validate_admin_password("12345678", "12345678").is_ok());
+        assert!(validate_admin_password("1234567", "1234567").is_err());
+        assert!(validate_admin_password(&"x".repeat(65), &"x".repeat(65)).is_err());
+        assert!(validate_admin_password("12345678", "12345679").is_err());
+    }
+
+    #[test]
+    fn wrong_passwords_lock_out_only_that_client() {
+        let mut auth = AdminAuth::new();
+        let hash = hash();
+        let now = Duration::from_secs(100);
+
+        for attempts_left in (1..MAX_FAILED_LOGINS).rev() {
+            assert_eq!(
+                auth.login(&hash, "wrong", OTHER, [1; SESSION_TOKEN_LEN], now),
+                Err(LoginError::WrongPassword { attempts_left })
+            );
+        }
+        assert_eq!(
+            auth.login(&hash, "wrong", OTHER, [1; SESSION_TOKEN_LEN], now),
+            Err(LoginError::LockedOut(LOCKOUT_TIME))
+        );
+
+        // The right password is not even checked while locked out.
+        let later = now + Duration::from_secs(60);
+        assert_eq!(
+            auth.login(&hash, "correct horse", OTHER, [1; SESSION_TOKEN_LEN], later),
+            Err(LoginError::LockedOut(
+                LOCKOUT_TIME - Duration::from_secs(60)
+            ))
+        );
+        assert_eq!(auth.lockout_remaining(ADMIN, later), None);
+        assert!(auth
+            .login(&hash, "correct horse", ADMIN, [2; SESSION_TOKEN_LEN], later)
+            .is_ok());
+
+        let expired = now + LOCKOUT_TIME;
+        assert_eq!(auth.lockout_remaining(OTHER, expired), None);
+        assert!(auth
+            .login(
+                &hash,
+                "correct horse",
+                OTHER,
+                [3; SESSION_TOKEN_LEN],
+                expired
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn successful_login_resets_the_failures() {
+        let mut auth = AdminAuth::new();
+        let hash = hash();
+        let now = Duration::from_secs(100);
+
+        for _ in 1..MAX_FAILED_LOGINS {
+            let _ = auth.login(&hash, "wrong", ADMIN, [1; SESSION_TOKEN_LEN], now);
+        }
+        assert!(auth
+            .login(&hash, "correct horse", ADMIN, [1; SESSION_TOKEN_LEN], now)
+            .is_ok());
+        assert_eq!(
+            auth.login(&hash, "wrong", ADMIN, [1; SESSION_TOKEN_LEN], now),
+            Err(LoginError::WrongPassword {
+                attempts_left: MAX_FAILED_LOGINS - 1
+            })
+        );
+    }
+
+    #[test]
+    fn tracked_clients_are_bounded() {
+        let mut auth = AdminAuth::new();
+        let hash = hash();
+
+        for client in 0..=MAX_TRACKED_CLIENTS as u8 {
+            let now = Duration::from_secs(client as u64);
+            let client = Ipv4Addr::new(192, 168, 71, 10 + client);
+            let _ = auth.login(&hash, "wrong", client, [1; SESSION_TOKEN_LEN], now);
+        }
+
+        assert_eq!(auth.failed_logins.len(), MAX_TRACKED_CLIENTS);
+        assert!(auth
+            .failed_logins
+            .iter()
+            .all(|logins| logins.client != Ipv4Addr::new(192, 168, 71, 10)));
+    }
+
+    #[test]
+    fn sessions_expire_when_idle() {
+        let mut auth = AdminAuth::new();
+        let now = Duration::from_secs(100);
+        let cookie = cookie_header(&auth.open_session([1; SESSION_TOKEN_LEN], now));
+
+        assert!(auth.has_sessions(now));
+
+        // Each use extends the session.
+        let used = now + SESSION_IDLE_TIME - Duration::from_secs(1);
+        assert!(auth.is_authenticated(Some(&cookie), used));
+        assert!(auth.is_authenticated(
+            Some(&cookie),
+            used + SESSION_IDLE_TIME - Duration::from_secs(1)
+        ));
+
+        let idle = used + 2 * SESSION_IDLE_TIME;
+        assert!(!auth.is_authenticated(Some(&cookie), idle));
+        assert!(!auth.has_sessions(idle));
+    }
+
+    #[test]
+    fn oldest_session_is_closed_above_the_limit() {
+        let mut auth = AdminAuth::new();
+        let now = Duration::from_secs(100);
+        let cookies: Vec<String> = (0..=MAX_SESSIONS as u8)
+            .map(|token| cookie_header(&auth.open_session([token; SESSION_TOKEN_LEN], now)))
+            .collect();
+
+        assert!(!auth.is_authenticated(Some(&cookies[0]), now));
+        assert!(cookies[1..]
+            .iter()
+            .all(|cookie| auth.is_authenticated(Some(cookie), now)));
+    }
+
+    #[test]
+    fn logout_closes_the_session() {
+        let mut auth = AdminAuth::new();
+        let now = Duration::from_secs(100);
+        let cookie = cookie_header(&auth.open_session([1; SESSION_TOKEN_LEN], now));
+
+        assert_eq!(auth.logout(Some(&cookie)), "session=; Path=/; Max-Age=0");
+        assert!(!auth.is_authenticated(Some(&cookie), now));
+    }
+
+    #[test]
+    fn malformed_cookies_are_not_authenticated() {
+        let mut auth = AdminAuth::new();
+        let now = Duration::from_secs(100);
+        let cookie = cookie_header(&auth.open_session([1; SESSION_TOKEN_LEN], now));
+        let token = session_token(&cookie).unwrap().to_string();
+
+        assert_eq!(
+            session_token(&format!("theme=dark; {}", cookie)),
+            Some(token.as_str())
+        );
+        assert!(auth.is_authenticated(Some(&format!("a=b;{} ; c", cookie)), now));
+
+        for bad in [
+            String::new(),
+            "session".to_string(),
+            "session=".to_string(),
+            "sessions=".to_string() + &token,
+            format!("session={}", &token[1..]),
+            format!("session={}0", token),
+            format!("Session={}", token),
+        ] {
+            assert!(!auth.is_authenticated(Some(&bad), now), "{}", bad);
+        }
+        assert!(!auth.is_authenticated(None, now));
+    }
+}
@@ -0,0 +1,283 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::outbox::OutboxMessage;
+
+/// Above the AP client limit, stale entries are dropped first.
+pub const MAX_AP_CLIENTS: usize = 16;
+/// Events waiting for the MQTT forwarder, the oldest are dropped.
+const MAX_PENDING_EVENTS: usize = 32;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ApClient {
+    pub mac: [u8; 6],
+    /// Last address leased by the DHCP server.
+    pub ip: Option<Ipv4Addr>,
+    pub rssi: Option<i8>,
+    /// Uptime at association.
+    pub connected_since: Duration,
+}
+
+impl ApClient {
+    pub fn to_json(&self, now: Duration) -> Value {
+        json!({
+            "mac": format_mac(&self.mac),
+            "ip": self.ip.map(|ip| ip.to_string()),
+            "rssi": self.rssi,
+            "connected_since": self.connected_since.as_secs(),
+            "connected_for": now.saturating_sub(self.connected_since).as_secs(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ApClientEventKind {
+    Joined,
+    Left,
+}
+
+impl ApClientEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApClientEventKind::Joined => "join",
+            ApClientEventKind::Left => "leave",
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ApClientEvent {
+    pub kind: ApClientEventKind,
+    pub client: ApClient,
+    /// Uptime of the event.
+    pub at: Duration,
+}
+
+impl ApClientEvent {
+    /// Not retained, QoS 0: the events are diagnostics, the table is the
+    /// reference.
+    pub fn to_message(&self, topic: &str) -> OutboxMessage {
+        let mut payload = self.client.to_json(self.at);
+        payload["event"] = self.kind.as_str().into();
+
+        OutboxMessage::new(topic, 0, payload.to_string().as_bytes())
+    }
+}
+
+/// Stations associated to the AP, fed by the driver events.
+#[derive(Clone, Default, Debug)]
+pub struct ApClientTable {
+    clients: Vec<ApClient>,
+    events: Vec<ApClientEvent>,
+}
+
+impl ApClientTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clients(&self) -> &[ApClient] {
+        &self.clients
+    }
+
+    pub fn on_connected(&mut self, mac: [u8; 6], now: Duration) {
+        self.clients.retain(|client| client.mac != mac);
+
+        if self.clients.len() >= MAX_AP_CLIENTS {
+            self.clients.remove(0);
+        }
+
+        let client = ApClient {
+            mac,
+            ip: None,
+            rssi: None,
+            connected_since: now,
+        };
+
+        self.clients.push(client.clone());
+        self.push_event(ApClientEventKind::Joined, client, now);
+    }
+
+    pub fn on_disconnected(&mut self, mac: [u8; 6], now: Duration) {
+        let Some(index) = self.clients.iter().position(|client| client.mac == mac) else {
+            return;
+        };
+
+        let client = self.clients.remove(index);
+        self.push_event(ApClientEventKind::Left, client, now);
+    }
+
+    /// A lease can be given before the association event is seen, the
+    /// client is then added.
+    pub fn on_ip_assigned(&mut self, mac: [u8; 6], ip: Ipv4Addr, now: Duration) {
+        if !self.clients.iter().any(|client| client.mac == mac) {
+            self.on_connected(mac, now);
+        }
+
+        if let Some(client) = self.clients.iter_mut().find(|client| client.mac == mac) {
+            client.ip = Some(ip);
+        }
+    }
+
+    pub fn update_rssi(&mut self, mac: [u8; 6], rssi: i8) {
+        if let Some(client) = self.clients.iter_mut().find(|client| client.mac == mac) {
+            client.rssi = Some(rssi);
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<ApClientEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn to_json(&self, now: Duration) -> Value {
+        Value::Array(
+            self.clients
+                .iter()
+                .map(|client| client.to_json(now))
+                .collect(),
+        )
+    }
+
+    fn push_event(&mut self, kind: ApClientEventKind, client: ApClient, at: Duration) {
+        log::info!("AP client {} {}", format_mac(&client.mac), kind.as_str());
+
+        if self.events.len() >= MAX_PENDING_EVENTS {
+            self.events.remove(0);
+        }
+
+        self.events.push(ApClientEvent { kind, client, at });
+    }
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x24, 0x0a, 0xc4, 0, 0, 1];
+    const OTHER_MAC: [u8; 6] = [0x24, 0x0a, 0xc4, 0, 0, 2];
+
+    fn kinds(events: &[ApClientEvent]) -> Vec<(ApClientEventKind, [u8; 6])> {
+        events.iter().map(|e| (e.kind, e.client.mac)).collect()
+    }
+
+    #[test]
+    fn join_and_leave_give_events() {
+        let mut table = ApClientTable::new();
+        table.on_connected(MAC, Duration::from_secs(10));
+        table.on_connected(OTHER_MAC, Duration::from_secs(11));
+        table.on_disconnected(MAC, Duration::from_secs(20));
+
+        assert_eq!(
+            kinds(&table.take_events()),
+            [
+                (ApClientEventKind::Joined, MAC),
+                (ApClientEventKind::Joined, OTHER_MAC),
+                (ApClientEventKind::Left, MAC),
+            ]
+        );
+        assert!(table.take_events().is_empty());
+        assert_eq!(table.clients().len(), 1);
+        assert_eq!(table.clients()[0].mac, OTHER_MAC);
+    }
+
+    #[test]
+    fn unknown_client_leaving_is_ignored() {
+        let mut table = ApClientTable::new();
+        table.on_disconnected(MAC, Duration::from_secs(1));
+
+        assert!(table.take_events().is_empty());
+    }
+
+    #[test]
+    fn reassociation_restarts_the_client() {
+        let mut table = ApClientTable::new();
+        table.on_connected(MAC, Duration::from_secs(1));
+        table.on_ip_assigned(MAC, Ipv4Addr::new(192, 168, 70, 2), Duration::from_secs(2));
+        table.on_connected(MAC, Duration::from_secs(5));
+
+        assert_eq!(table.clients().len(), 1);
+        assert_eq!(table.clients()[0].connected_since, Duration::from_secs(5));
+        assert_eq!(table.clients()[0].ip, None);
+        assert_eq!(table.take_events().len(), 2);
+    }
+
+    #[test]
+    fn lease_before_association_adds_the_client() {
+        let mut table = ApClientTable::new();
+        let ip = Ipv4Addr::new(192, 168, 70, 2);
+        table.on_ip_assigned(MAC, ip, Duration::from_secs(3));
+        table.on_ip_assigned(MAC, ip, Duration::from_secs(4));
+        table.update_rssi(MAC, -60);
+        table.update_rssi(OTHER_MAC, -70);
+
+        assert_eq!(
+            table.clients(),
+            [ApClient {
+                mac: MAC,
+                ip: Some(ip),
+                rssi: Some(-60),
+                connected_since: Duration::from_secs(3),
+            }]
+        );
+        assert_eq!(
+            kinds(&table.take_events()),
+            [(ApClientEventKind::Joined, MAC)]
+        );
+    }
+
+    #[test]
+    fn table_and_events_are_bounded() {
+        let mut table = ApClientTable::new();
+
+        for i in 0..40u8 {
+            table.on_connected([0, 0, 0, 0, 0, i], Duration::from_secs(i as u64));
+        }
+
+        assert_eq!(table.clients().len(), MAX_AP_CLIENTS);
+        assert_eq!(
+            table.clients()[0].mac,
+            [0, 0, 0, 0, 0, 40 - MAX_AP_CLIENTS as u8]
+        );
+
+        let events = table.take_events();
+        assert_eq!(events.len(), MAX_PENDING_EVENTS);
+        assert_eq!(
+            events[0].client.mac,
+            [0, 0, 0, 0, 0, 40 - MAX_PENDING_EVENTS as u8]
+        );
+    }
+
+    #[test]
+    fn event_message() {
+        let mut table = ApClientTable::new();
+        table.on_connected(MAC, Duration::from_secs(10));
+        table.on_disconnected(MAC, Duration::from_secs(70));
+
+        let message = table.take_events()[1].to_message("proxy/clients");
+        let payload: Value = serde_json::from_slice(&message.payload).unwrap();
+
+        assert_eq!(message.topic, "proxy/clients");
+        assert_eq!(message.qos, 0);
+        assert!(!message.retain);
+        assert_eq!(
+            payload,
+            json!({
+                "event": "leave",
+                "mac": "24:0a:c4:00:00:01",
+                "ip": null,
+                "rssi": null,
+                "connected_since": 10,
+                "connected_for": 60,
+            })
+        );
+    }
+}
@@ -0,0 +1,126 @@
+/// Security of a WiFi interface, mapped to the driver auth methods by the
+/// firmware.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AuthMode {
+    /// AP: open without passphrase, WPA2 otherwise. Station: the mode seen by
+    /// the scan, falling back to the AP rule.
+    Auto,
+    Open,
+    Wpa2,
+    Wpa3,
+    /// WPA2/WPA3 transition mode, WPA2 only clients can still join.
+    Wpa2Wpa3,
+}
+
+pub const AUTH_MODES: &[AuthMode] = &[
+    AuthMode::Auto,
+    AuthMode::Open,
+    AuthMode::Wpa2,
+    AuthMode::Wpa3,
+    AuthMode::Wpa2Wpa3,
+];
+
+impl AuthMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMode::Auto => "auto",
+            AuthMode::Open => "open",
+            AuthMode::Wpa2 => "wpa2",
+            AuthMode::Wpa3 => "wpa3",
+            AuthMode::Wpa2Wpa3 => "wpa2wpa3",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        AUTH_MODES
+            .iter()
+            .copied()
+            .find(|mode| mode.as_str() == s.trim().to_ascii_lowercase())
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            AuthMode::Auto => "Automatic",
+            AuthMode::Open => "Open",
+            AuthMode::Wpa2 => "WPA2-Personal",
+            AuthMode::Wpa3 => "WPA3-Personal",
+            AuthMode::Wpa2Wpa3 => "WPA2/WPA3-Personal",
+        }
+    }
+
+    /// Mode actually used, `scanned` being the one advertised by the network
+    /// when known. Never returns `Auto`.
+    pub fn resolve(self, passphrase: &str, scanned: Option<AuthMode>) -> AuthMode {
+        match (self, scanned) {
+            (AuthMode::Auto, Some(scanned)) if scanned != AuthMode::Auto => scanned,
+            (AuthMode::Auto, _) if passphrase.is_empty() => AuthMode::Open,
+            (AuthMode::Auto, _) => AuthMode::Wpa2,
+            (mode, _) => mode,
+        }
+    }
+
+    /// Open networks have no passphrase, the others need one.
+    pub fn check_passphrase(&self, passphrase: &str) -> Result<(), &'static str> {
+        match self {
+            AuthMode::Auto => Ok(()),
+            AuthMode::Open if !passphrase.is_empty() => {
+                Err("An open network has no passphrase, clear it or change the security")
+            }
+            AuthMode::Wpa2 | AuthMode::Wpa3 | AuthMode::Wpa2Wpa3 if passphrase.is_empty() => {
+                Err("This security mode requires a passphrase")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for mode in AUTH_MODES {
+            assert_eq!(AuthMode::from_name(mode.as_str()), Some(*mode));
+        }
+        assert_eq!(AuthMode::from_name(" WPA3 "), Some(AuthMode::Wpa3));
+        assert_eq!(AuthMode::from_name("wep"), None);
+    }
+
+    #[test]
+    fn auto_follows_the_scan_then_the_passphrase() {
+        assert_eq!(
+            AuthMode::Auto.resolve("passphrase", Some(AuthMode::Wpa3)),
+            AuthMode::Wpa3
+        );
+        assert_eq!(
+            AuthMode::Auto.resolve("", Some(AuthMode::Open)),
+            AuthMode::Open
+        );
+        assert_eq!(AuthMode::Auto.resolve("passphrase", None), AuthMode::Wpa2);
+        assert_eq!(AuthMode::Auto.resolve("", None), AuthMode::Open);
+        assert_eq!(
+            AuthMode::Auto.resolve("passphrase", Some(AuthMode::Auto)),
+            AuthMode::Wpa2
+        );
+    }
+
+    #[test]
+    fn explicit_modes_ignore_the_scan() {
+        for mode in &AUTH_MODES[1..] {
+            assert_eq!(mode.resolve("passphrase", Some(AuthMode::Open)), *mode);
+        }
+    }
+
+    #[test]
+    fn passphrase_must_match_the_mode() {
+        assert_eq!(AuthMode::Auto.check_passphrase(""), Ok(()));
+        assert_eq!(AuthMode::Open.check_passphrase(""), Ok(()));
+        assert!(AuthMode::Open.check_passphrase("passphrase").is_err());
+
+        for mode in [AuthMode::Wpa2, AuthMode::Wpa3, AuthMode::Wpa2Wpa3] {
+            assert!(mode.check_passphrase("").is_err(), "{:?}", mode);
+            assert_eq!(mode.check_passphrase("passphrase"), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use crate::config::ConfigStore;
+
+pub const KEY_BOOT_FAILURES: &str = "BOOTFAIL";
+
+/// Consecutive failed proxy mode start-ups, see `StartupGuard`, before the
+/// device falls back to configuration mode.
+pub const MAX_BOOT_FAILURES: u8 = 3;
+/// A start-up not associated by then fails, if the station was refused.
+pub const STARTUP_TIMEOUT: Duration = Duration::from_secs(180);
+/// Proxy mode is tried again this often from the fallback configuration mode.
+pub const FALLBACK_RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BootDecision {
+    Proxy,
+    /// No upstream network configured yet.
+    ConfigNoStation,
+    ConfigTooManyFailures(u8),
+}
+
+pub fn decide_boot(store: &impl ConfigStore) -> BootDecision {
+    let failures = boot_failures(store);
+
+    if store.get_sta_networks().is_empty() {
+        BootDecision::ConfigNoStation
+    } else if failures >= MAX_BOOT_FAILURES {
+        BootDecision::ConfigTooManyFailures(failures)
+    } else {
+        BootDecision::Proxy
+    }
+}
+
+pub fn boot_failures(store: &impl ConfigStore) -> u8 {
+    store.load_u8(KEY_BOOT_FAILURES).unwrap_or(0)
+}
+
+/// Counts a failed start-up, returns the consecutive failures.
+pub fn record_boot_failure<S: ConfigStore>(store: &mut S) -> Result<u8, S::Error> {
+    let failures = boot_failures(store).saturating_add(1);
+
+    store.save_u8(KEY_BOOT_FAILURES, failures)?;
+    Ok(failures)
+}
+
+/// Clears the counter, after a successful start-up or a configuration change.
+pub fn reset_boot_failures<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    if boot_failures(store) == 0 {
+        return Ok(());
+    }
+
+    store.save_u8(KEY_BOOT_FAILURES, 0)
+}
+
+/// Lets the next boot try proxy mode once more, from the fallback
+/// configuration mode. Another failure falls back right away.
+pub fn allow_boot_retry<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    if boot_failures(store) < MAX_BOOT_FAILURES {
+        return Ok(());
+    }
+
+    store.save_u8(KEY_BOOT_FAILURES, MAX_BOOT_FAILURES - 1)
+}
+
+/// Station disconnection reasons of the IDF that point at the configuration:
+/// the access point refused the authentication or the association, or the
+/// key handshake failed, as with a wrong passphrase. A missing access point
+/// or a lost beacon is not one, the network may just be down.
+pub fn is_association_failure(reason: u32) -> bool {
+    const AUTH_EXPIRE: u32 = 2;
+    const FOUR_WAY_HANDSHAKE_TIMEOUT: u32 = 15;
+    const IEEE_802_1X_AUTH_FAILED: u32 = 23;
+    const AUTH_FAIL: u32 = 202;
+    const ASSOC_FAIL: u32 = 203;
+    const HANDSHAKE_TIMEOUT: u32 = 204;
+    const NO_AP_FOUND_W_COMPATIBLE_SECURITY: u32 = 210;
+
+    matches!(
+        reason,
+        AUTH_EXPIRE
+            | FOUR_WAY_HANDSHAKE_TIMEOUT
+            | IEEE_802_1X_AUTH_FAILED
+            | AUTH_FAIL
+            | ASSOC_FAIL
+            | HANDSHAKE_TIMEOUT
+            | NO_AP_FOUND_W_COMPATIBLE_SECURITY
+    )
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum StartupEvent {
+    /// The station associated, the failures can be reset.
+    Confirmed,
+    /// The station was refused for a whole `STARTUP_TIMEOUT`.
+    Failed,
+}
+
+/// Judges a proxy mode start-up on the station alone: MQTT may be down for
+/// reasons the device cannot fix, the outbox keeps the readings meanwhile.
+///
+/// Each `STARTUP_TIMEOUT` window without association in which the station
+/// was refused, see `is_association_failure`, is a failure. The station keeps
+/// retrying, the caller decides when to fall back.
+#[derive(Clone, Debug)]
+pub struct StartupGuard {
+    window_start: Duration,
+    is_refused: bool,
+    is_confirmed: bool,
+}
+
+impl StartupGuard {
+    pub fn new(now: Duration) -> Self {
+        Self {
+            window_start: now,
+            is_refused: false,
+            is_confirmed: false,
+        }
+    }
+
+    pub fn on_association_failure(&mut self) {
+        self.is_refused = true;
+    }
+
+    pub fn poll(&mut self, is_associated: bool, now: Duration) -> Option<StartupEvent> {
+        if self.is_confirmed {
+            return None;
+        }
+
+        if is_associated {
+            self.is_confirmed = true;
+            return Some(StartupEvent::Confirmed);
+        }
+
+        if now < self.window_start + STARTUP_TIMEOUT {
+            return None;
+        }
+
+        self.window_start = now;
+        std::mem::take(&mut self.is_refused).then_some(StartupEvent::Failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryConfigStore;
+    use crate::sta_network::StaNetwork;
+
+    #[test]
+    fn boot_decision_follows_networks_and_failures() {
+        let mut store = MemoryConfigStore::new();
+        assert_eq!(decide_boot(&store), BootDecision::ConfigNoStation);
+
+        store
+            .set_sta_networks(&[StaNetwork::new("Home", "passphrase")])
+            .unwrap();
+        assert_eq!(decide_boot(&store), BootDecision::Proxy);
+
+        for _ in 0..MAX_BOOT_FAILURES {
+            record_boot_failure(&mut store).unwrap();
+        }
+        assert_eq!(
+            decide_boot(&store),
+            BootDecision::ConfigTooManyFailures(MAX_BOOT_FAILURES)
+        );
+
+        reset_boot_failures(&mut store).unwrap();
+        assert_eq!(decide_boot(&store), BootDecision::Proxy);
+    }
+
+    #[test]
+    fn retry_allows_a_single_start_up() {
+        let mut store = MemoryConfigStore::new();
+        store
+            .set_sta_networks(&[StaNetwork::new("Home", "passphrase")])
+            .unwrap();
+        allow_boot_retry(&mut store).unwrap();
+        assert_eq!(boot_failures(&store), 0);
+
+        for _ in 0..MAX_BOOT_FAILURES + 2 {
+            record_boot_failure(&mut store).unwrap();
+        }
+        allow_boot_retry(&mut store).unwrap();
+        assert_eq!(decide_boot(&store), BootDecision::Proxy);
+
+        record_boot_failure(&mut store).unwrap();
+        assert_eq!(
+            decide_boot(&store),
+            BootDecision::ConfigTooManyFailures(MAX_BOOT_FAILURES)
+        );
+    }
+
+    #[test]
+    fn association_confirms_the_start_up_once() {
+        let mut guard = StartupGuard::new(Duration::from_secs(10));
+        guard.on_association_failure();
+
+        assert_eq!(guard.poll(false, Duration::from_secs(20)), None);
+        assert_eq!(
+            guard.poll(true, Duration::from_secs(30)),
+            Some(StartupEvent::Confirmed)
+        );
+        assert_eq!(guard.poll(true, Duration::from_secs(40)), None);
+        assert_eq!(guard.poll(false, Duration::from_secs(1000)), None);
+    }
+
+    #[test]
+    fn refused_station_fails_once_per_window() {
+        let mut guard = StartupGuard::new(Duration::from_secs(10));
+        guard.on_association_failure();
+
+        assert_eq!(guard.poll(false, Duration::from_secs(189)), None);
+        assert_eq!(
+            guard.poll(false, Duration::from_secs(190)),
+            Some(StartupEvent::Failed)
+        );
+        assert_eq!(guard.poll(false, Duration::from_secs(191)), None);
+
+        guard.on_association_failure();
+        assert_eq!(guard.poll(false, Duration::from_secs(369)), None);
+        assert_eq!(
+            guard.poll(false, Duration::from_secs(370)),
+            Some(StartupEvent::Failed)
+        );
+    }
+
+    #[test]
+    fn missing_network_is_not_a_failure() {
+        let mut guard = StartupGuard::new(Duration::ZERO);
+
+        assert_eq!(guard.poll(false, Duration::from_secs(180)), None);
+        assert_eq!(guard.poll(false, Duration::from_secs(360)), None);
+        assert_eq!(
+            guard.poll(true, Duration::from_secs(400)),
+            Some(StartupEvent::Confirmed)
+        );
+    }
+
+    #[test]
+    fn association_failures_are_told_apart() {
+        assert!(is_association_failure(15));
+        assert!(is_association_failure(202));
+        assert!(!is_association_failure(200));
+        assert!(!is_association_failure(201));
+    }
+}
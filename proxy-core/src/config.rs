@@ -0,0 +1,766 @@
+use std::error::Error;
+use std::net::Ipv4Addr;
+
+use serde_json::{Map, Value};
+
+use crate::auth_mode::AuthMode;
+use crate::dhcp_server::{reservations_from_json, reservations_to_json, DhcpReservation};
+use crate::port_forward::{port_forwards_from_json, port_forwards_to_json, PortForward};
+use crate::regulatory::DEFAULT_COUNTRY;
+use crate::sensor_allowlist::{allowlist_from_json, allowlist_to_json};
+use crate::sensor_auth::{credentials_from_json, credentials_to_json, SensorCredential};
+use crate::sensor_route::{default_routes, routes_from_json, routes_to_json, SensorRoute};
+use crate::sta_ip::{StaIpConfig, MAX_DHCP_HOSTNAME_LEN};
+use crate::sta_network::{networks_from_json, networks_to_json, StaNetwork};
+use crate::string_error::StringError;
+
+/// Single station network of schema v2 and older, see `KEY_STA_NETWORKS`.
+pub const KEY_STA_SSID: &str = "STASSID";
+pub const KEY_STA_PASSPHRASE: &str = "STAPASS";
+pub const KEY_STA_NETWORKS: &str = "STANETS";
+pub const KEY_STA_STATIC_IP: &str = "STAIPST";
+pub const KEY_STA_IP: &str = "STAIP";
+pub const KEY_STA_MASK: &str = "STAMASK";
+pub const KEY_STA_GATEWAY: &str = "STAGW";
+pub const KEY_STA_DNS: &str = "STADNS1";
+pub const KEY_STA_SECONDARY_DNS: &str = "STADNS2";
+pub const KEY_STA_HOSTNAME: &str = "STAHOST";
+pub const KEY_STA_AUTH_MODE: &str = "STAAUTH";
+pub const KEY_AP_SSID: &str = "APSSID";
+pub const KEY_AP_PASSPHRASE: &str = "APPASS";
+pub const KEY_AP_SSID_HIDDEN: &str = "APHIDDEN";
+pub const KEY_AP_IP: &str = "APIP";
+pub const KEY_AP_MASK: &str = "APMASK";
+pub const KEY_AP_CHANNEL: &str = "APCHAN";
+pub const KEY_AP_COUNTRY: &str = "APCC";
+pub const KEY_AP_MAX_CLIENTS: &str = "APMAXCL";
+pub const KEY_AP_AUTH_MODE: &str = "APAUTH";
+pub const KEY_AP_CLIENT_EVENTS: &str = "APCLIEVT";
+pub const KEY_DHCP_RESERVATIONS: &str = "DHCPRES";
+pub const KEY_NAPT: &str = "NAPT";
+pub const KEY_PORT_FORWARDS: &str = "PORTFWD";
+pub const KEY_MQTT_SERVER: &str = "MQTTSRV";
+pub const KEY_MQTT_PORT: &str = "MQTTPRT";
+pub const KEY_SENSOR_ROUTES: &str = "ROUTES";
+pub const KEY_SENSOR_CREDENTIALS: &str = "SENSCRED";
+pub const KEY_SENSOR_ALLOWLIST: &str = "SENSALLOW";
+pub const KEY_SENSOR_ALLOW_ALL: &str = "SENSALLALL";
+pub const KEY_MQTT_USERNAME: &str = "MQTTUSER";
+pub const KEY_MQTT_PASSWORD: &str = "MQTTPASS";
+pub const KEY_MQTT_TLS: &str = "MQTTTLS";
+pub const KEY_MQTT_CA_CERT: &str = "MQTTCA";
+pub const KEY_MQTT_CLIENT_ID: &str = "MQTTCLID";
+pub const KEY_MQTT_TOPIC_PREFIX: &str = "MQTTPFX";
+pub const KEY_HA_DISCOVERY: &str = "HADISCO";
+
+/// Key/value backend of the proxy configuration.
+///
+/// Implementors only provide the typed `load_*` / `save_*` primitives, the
+/// getters, setters and their defaults are shared by every backend.
+pub trait ConfigStore {
+    type Error: Error + Send + Sync + 'static;
+
+    fn load_str(&self, key: &str) -> Option<String>;
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error>;
+    fn load_u8(&self, key: &str) -> Option<u8>;
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error>;
+    fn load_u16(&self, key: &str) -> Option<u16>;
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error>;
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>>;
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error>;
+
+    /// Ranked upstream networks, empty when none is configured.
+    fn get_sta_networks(&self) -> Vec<StaNetwork> {
+        let networks = self.load_blob(KEY_STA_NETWORKS).unwrap_or_default();
+
+        if networks.is_empty() {
+            return Vec::new();
+        }
+
+        match String::from_utf8(networks)
+            .map_err(|_| StringError("Station networks are not UTF-8"))
+            .and_then(|s| networks_from_json(&s))
+        {
+            Ok(networks) => networks,
+            Err(e) => {
+                log::error!("Invalid stored station networks ({}).", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn get_sta_ip_config(&self) -> StaIpConfig {
+        StaIpConfig {
+            static_ip: self.load_u8(KEY_STA_STATIC_IP).unwrap_or(0) == 1,
+            ip: self.read_ipv4(KEY_STA_IP),
+            mask: self.read_ipv4(KEY_STA_MASK),
+            gateway: self.read_ipv4(KEY_STA_GATEWAY),
+            dns: self.read_ipv4(KEY_STA_DNS),
+            secondary_dns: self.read_ipv4(KEY_STA_SECONDARY_DNS),
+            hostname: self.read_string(KEY_STA_HOSTNAME, ""),
+        }
+    }
+
+    /// Applies to every station network.
+    fn get_sta_auth_mode(&self) -> AuthMode {
+        self.read_auth_mode(KEY_STA_AUTH_MODE)
+    }
+
+    fn get_ap_ssid(&self) -> String {
+        self.read_string(KEY_AP_SSID, "ESP-WiFi Proxy")
+    }
+
+    fn get_ap_passphrase(&self) -> String {
+        self.read_string(KEY_AP_PASSPHRASE, "")
+    }
+
+    fn get_ap_auth_mode(&self) -> AuthMode {
+        self.read_auth_mode(KEY_AP_AUTH_MODE)
+    }
+
+    fn get_ap_hidden_ssid(&self) -> bool {
+        self.load_u8(KEY_AP_SSID_HIDDEN).unwrap_or(0) == 1
+    }
+
+    /// Address of the proxy on the AP subnet, also its gateway.
+    fn get_ap_ip(&self) -> Ipv4Addr {
+        self.read_ipv4(KEY_AP_IP)
+            .unwrap_or(Ipv4Addr::new(192, 168, 70, 1))
+    }
+
+    fn get_ap_mask(&self) -> Ipv4Addr {
+        self.read_ipv4(KEY_AP_MASK)
+            .unwrap_or(Ipv4Addr::new(255, 255, 255, 0))
+    }
+
+    /// Only used when the station is not connected, the AP follows the
+    /// channel of the upstream network otherwise.
+    fn get_ap_channel(&self) -> u8 {
+        self.load_u8(KEY_AP_CHANNEL).unwrap_or(11)
+    }
+
+    /// Regulatory domain, see `regulatory::COUNTRIES`.
+    fn get_ap_country(&self) -> String {
+        self.read_string(KEY_AP_COUNTRY, DEFAULT_COUNTRY)
+    }
+
+    fn get_ap_max_clients(&self) -> u8 {
+        self.load_u8(KEY_AP_MAX_CLIENTS).unwrap_or(10)
+    }
+
+    /// Publishes AP client join / leave events to MQTT.
+    fn get_ap_client_events(&self) -> bool {
+        self.load_u8(KEY_AP_CLIENT_EVENTS).unwrap_or(0) == 1
+    }
+
+    /// Fixed addresses of the AP clients, by MAC.
+    fn get_dhcp_reservations(&self) -> Vec<DhcpReservation> {
+        let reservations = self.load_blob(KEY_DHCP_RESERVATIONS).unwrap_or_default();
+
+        if reservations.is_empty() {
+            return Vec::new();
+        }
+
+        match String::from_utf8(reservations)
+            .map_err(|_| StringError("Reservations are not UTF-8"))
+            .and_then(|s| reservations_from_json(&s))
+        {
+            Ok(reservations) => reservations,
+            Err(e) => {
+                log::error!("Invalid stored DHCP reservations ({}).", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Routes the AP clients to the station uplink.
+    fn get_napt(&self) -> bool {
+        self.load_u8(KEY_NAPT).unwrap_or(0) == 1
+    }
+
+    fn get_port_forwards(&self) -> Vec<PortForward> {
+        let forwards = self.load_blob(KEY_PORT_FORWARDS).unwrap_or_default();
+
+        if forwards.is_empty() {
+            return Vec::new();
+        }
+
+        match String::from_utf8(forwards)
+            .map_err(|_| StringError("Port forwards are not UTF-8"))
+            .and_then(|s| port_forwards_from_json(&s))
+        {
+            Ok(forwards) => forwards,
+            Err(e) => {
+                log::error!("Invalid stored port forwards ({}).", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn get_mqtt_server(&self) -> String {
+        self.read_string(KEY_MQTT_SERVER, "")
+    }
+
+    fn get_mqtt_port(&self) -> u16 {
+        self.load_u16(KEY_MQTT_PORT).unwrap_or(1883)
+    }
+
+    fn get_mqtt_username(&self) -> String {
+        self.read_string(KEY_MQTT_USERNAME, "")
+    }
+
+    fn get_mqtt_password(&self) -> String {
+        self.read_string(KEY_MQTT_PASSWORD, "")
+    }
+
+    fn get_mqtt_tls(&self) -> bool {
+        self.load_u8(KEY_MQTT_TLS).unwrap_or(0) == 1
+    }
+
+    /// PEM encoded CA certificate, empty when the default bundle must be used.
+    fn get_mqtt_ca_cert(&self) -> String {
+        self.load_blob(KEY_MQTT_CA_CERT)
+            .and_then(|cert| String::from_utf8(cert).ok())
+            .unwrap_or_default()
+    }
+
+    /// Client ID override, empty when the MAC derived default must be used.
+    fn get_mqtt_client_id(&self) -> String {
+        self.read_string(KEY_MQTT_CLIENT_ID, "")
+    }
+
+    /// Prepended as is to every published topic.
+    fn get_mqtt_topic_prefix(&self) -> String {
+        self.read_string(KEY_MQTT_TOPIC_PREFIX, "")
+    }
+
+    fn get_ha_discovery(&self) -> bool {
+        self.load_u8(KEY_HA_DISCOVERY).unwrap_or(1) == 1
+    }
+
+    fn get_sensor_routes(&self) -> Vec<SensorRoute> {
+        let routes = self.load_blob(KEY_SENSOR_ROUTES).unwrap_or_default();
+
+        if routes.is_empty() {
+            return default_routes();
+        }
+
+        match String::from_utf8(routes)
+            .map_err(|_| StringError("Sensor routes are not UTF-8"))
+            .and_then(|s| routes_from_json(&s))
+        {
+            Ok(routes) => routes,
+            Err(e) => {
+                log::error!("Invalid stored sensor routes ({}), use defaults.", e);
+                default_routes()
+            }
+        }
+    }
+
+    /// Readings are only authenticated when the table is not empty.
+    fn get_sensor_credentials(&self) -> Vec<SensorCredential> {
+        let credentials = self.load_blob(KEY_SENSOR_CREDENTIALS).unwrap_or_default();
+
+        if credentials.is_empty() {
+            return Vec::new();
+        }
+
+        match String::from_utf8(credentials)
+            .map_err(|_| StringError("Sensor credentials are not UTF-8"))
+            .and_then(|s| credentials_from_json(&s))
+        {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                log::error!("Invalid stored sensor credentials ({}).", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Readings of sensors missing from the allowlist are forwarded as well.
+    /// Set by `migration` for configurations older than the allowlist.
+    fn get_sensor_allow_all(&self) -> bool {
+        self.load_u8(KEY_SENSOR_ALLOW_ALL).unwrap_or(0) == 1
+    }
+
+    /// An empty list refuses every sensor until an enrolment fills it, unless
+    /// `get_sensor_allow_all`.
+    fn get_sensor_allowlist(&self) -> Vec<String> {
+        let ids = self.load_blob(KEY_SENSOR_ALLOWLIST).unwrap_or_default();
+
+        if ids.is_empty() {
+            return Vec::new();
+        }
+
+        match String::from_utf8(ids)
+            .map_err(|_| StringError("Sensor allowlist is not UTF-8"))
+            .and_then(|s| allowlist_from_json(&s))
+        {
+            Ok(ids) => ids,
+            Err(e) => {
+                log::error!("Invalid stored sensor allowlist ({}).", e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn set_sta_networks(&mut self, networks: &[StaNetwork]) -> Result<(), Self::Error> {
+        self.save_blob(KEY_STA_NETWORKS, networks_to_json(networks).as_bytes())
+    }
+
+    fn set_sta_ip_config(&mut self, value: &StaIpConfig) -> Result<(), Self::Error> {
+        self.save_u8(KEY_STA_STATIC_IP, if value.static_ip { 1 } else { 0 })?;
+        self.store_ipv4(KEY_STA_IP, value.ip)?;
+        self.store_ipv4(KEY_STA_MASK, value.mask)?;
+        self.store_ipv4(KEY_STA_GATEWAY, value.gateway)?;
+        self.store_ipv4(KEY_STA_DNS, value.dns)?;
+        self.store_ipv4(KEY_STA_SECONDARY_DNS, value.secondary_dns)?;
+        self.store_string(KEY_STA_HOSTNAME, &value.hostname, MAX_DHCP_HOSTNAME_LEN)
+    }
+
+    fn set_sta_auth_mode(&mut self, value: AuthMode) -> Result<(), Self::Error> {
+        self.save_str(KEY_STA_AUTH_MODE, value.as_str())
+    }
+
+    fn set_ap_ssid(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_AP_SSID, value, 32)
+    }
+
+    fn set_ap_passphrase(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_AP_PASSPHRASE, value, 63)
+    }
+
+    fn set_ap_auth_mode(&mut self, value: AuthMode) -> Result<(), Self::Error> {
+        self.save_str(KEY_AP_AUTH_MODE, value.as_str())
+    }
+
+    fn set_ap_hidden_ssid(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_AP_SSID_HIDDEN, if value { 1 } else { 0 })
+    }
+
+    fn set_ap_ip(&mut self, value: Ipv4Addr) -> Result<(), Self::Error> {
+        self.store_ipv4(KEY_AP_IP, Some(value))
+    }
+
+    fn set_ap_mask(&mut self, value: Ipv4Addr) -> Result<(), Self::Error> {
+        self.store_ipv4(KEY_AP_MASK, Some(value))
+    }
+
+    fn set_ap_channel(&mut self, value: u8) -> Result<(), Self::Error> {
+        self.save_u8(KEY_AP_CHANNEL, value)
+    }
+
+    fn set_ap_country(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_AP_COUNTRY, value, 2)
+    }
+
+    fn set_ap_max_clients(&mut self, value: u8) -> Result<(), Self::Error> {
+        self.save_u8(KEY_AP_MAX_CLIENTS, value)
+    }
+
+    fn set_ap_client_events(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_AP_CLIENT_EVENTS, if value { 1 } else { 0 })
+    }
+
+    fn set_dhcp_reservations(
+        &mut self,
+        reservations: &[DhcpReservation],
+    ) -> Result<(), Self::Error> {
+        self.save_blob(
+            KEY_DHCP_RESERVATIONS,
+            reservations_to_json(reservations).as_bytes(),
+        )
+    }
+
+    fn set_napt(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_NAPT, if value { 1 } else { 0 })
+    }
+
+    fn set_port_forwards(&mut self, forwards: &[PortForward]) -> Result<(), Self::Error> {
+        self.save_blob(
+            KEY_PORT_FORWARDS,
+            port_forwards_to_json(forwards).as_bytes(),
+        )
+    }
+
+    fn set_mqtt_server(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_MQTT_SERVER, value, 128)
+    }
+
+    fn set_mqtt_port(&mut self, value: u16) -> Result<(), Self::Error> {
+        self.save_u16(KEY_MQTT_PORT, value)
+    }
+
+    fn set_mqtt_username(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_MQTT_USERNAME, value, 64)
+    }
+
+    fn set_mqtt_password(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_MQTT_PASSWORD, value, 64)
+    }
+
+    fn set_mqtt_tls(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_MQTT_TLS, if value { 1 } else { 0 })
+    }
+
+    fn set_mqtt_ca_cert(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.save_blob(KEY_MQTT_CA_CERT, value.as_bytes())
+    }
+
+    fn set_mqtt_client_id(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_MQTT_CLIENT_ID, value, 64)
+    }
+
+    fn set_mqtt_topic_prefix(&mut self, value: &str) -> Result<(), Self::Error> {
+        self.store_string(KEY_MQTT_TOPIC_PREFIX, value, 64)
+    }
+
+    fn set_ha_discovery(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_HA_DISCOVERY, if value { 1 } else { 0 })
+    }
+
+    fn set_sensor_routes(&mut self, routes: &[SensorRoute]) -> Result<(), Self::Error> {
+        self.save_blob(KEY_SENSOR_ROUTES, routes_to_json(routes).as_bytes())
+    }
+
+    fn set_sensor_credentials(
+        &mut self,
+        credentials: &[SensorCredential],
+    ) -> Result<(), Self::Error> {
+        self.save_blob(
+            KEY_SENSOR_CREDENTIALS,
+            credentials_to_json(credentials).as_bytes(),
+        )
+    }
+
+    fn set_sensor_allow_all(&mut self, value: bool) -> Result<(), Self::Error> {
+        self.save_u8(KEY_SENSOR_ALLOW_ALL, if value { 1 } else { 0 })
+    }
+
+    fn set_sensor_allowlist(&mut self, ids: &[String]) -> Result<(), Self::Error> {
+        self.save_blob(KEY_SENSOR_ALLOWLIST, allowlist_to_json(ids).as_bytes())
+    }
+
+    fn store_string(&mut self, key: &str, value: &str, max_size: usize) -> Result<(), Self::Error> {
+        self.save_str(key, trunc_string(value, max_size))
+    }
+
+    fn read_string(&self, key: &str, default: &str) -> String {
+        match self.load_str(key) {
+            Some(value) if !value.is_empty() => value,
+            _ => default.to_string(),
+        }
+    }
+
+    /// Addresses are stored dotted, an empty string being unset.
+    fn store_ipv4(&mut self, key: &str, value: Option<Ipv4Addr>) -> Result<(), Self::Error> {
+        self.save_str(key, &value.map(|ip| ip.to_string()).unwrap_or_default())
+    }
+
+    fn read_ipv4(&self, key: &str) -> Option<Ipv4Addr> {
+        self.load_str(key)?.parse().ok()
+    }
+
+    /// `Auto` when unset, which keeps the passphrase based behaviour.
+    fn read_auth_mode(&self, key: &str) -> AuthMode {
+        self.load_str(key)
+            .and_then(|s| AuthMode::from_name(&s))
+            .unwrap_or(AuthMode::Auto)
+    }
+}
+
+/// Truncates to at most `max` bytes, without splitting a character.
+pub fn trunc_string(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    &s[..end]
+}
+
+/// Configuration store backed by a JSON object, used on hosts and in tests.
+/// Blobs that are valid UTF-8 are kept as JSON strings so the object stays
+/// readable when saved to a file.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryConfigStore {
+    values: Map<String, Value>,
+}
+
+impl MemoryConfigStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(values: Map<String, Value>) -> Self {
+        Self { values }
+    }
+
+    pub fn as_json(&self) -> &Map<String, Value> {
+        &self.values
+    }
+}
+
+impl ConfigStore for MemoryConfigStore {
+    type Error = StringError;
+
+    fn load_str(&self, key: &str) -> Option<String> {
+        self.values.get(key)?.as_str().map(str::to_string)
+    }
+
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.values.insert(key.to_string(), value.into());
+        Ok(())
+    }
+
+    fn load_u8(&self, key: &str) -> Option<u8> {
+        u8::try_from(self.values.get(key)?.as_u64()?).ok()
+    }
+
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error> {
+        self.values.insert(key.to_string(), value.into());
+        Ok(())
+    }
+
+    fn load_u16(&self, key: &str) -> Option<u16> {
+        u16::try_from(self.values.get(key)?.as_u64()?).ok()
+    }
+
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error> {
+        self.values.insert(key.to_string(), value.into());
+        Ok(())
+    }
+
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>> {
+        match self.values.get(key)? {
+            Value::String(s) => Some(s.as_bytes().to_vec()),
+            Value::Array(bytes) => bytes
+                .iter()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect(),
+            _ => None,
+        }
+    }
+
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        let value = match std::str::from_utf8(value) {
+            Ok(s) => Value::String(s.to_string()),
+            Err(_) => Value::Array(value.iter().map(|&b| b.into()).collect()),
+        };
+
+        self.values.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Fails every write once `writes_left` reaches zero, as a power loss.
+#[cfg(test)]
+pub(crate) struct FailingStore {
+    pub store: MemoryConfigStore,
+    pub writes: usize,
+    writes_left: usize,
+}
+
+#[cfg(test)]
+impl FailingStore {
+    pub fn new(store: MemoryConfigStore, writes_left: usize) -> Self {
+        Self {
+            store,
+            writes: 0,
+            writes_left,
+        }
+    }
+
+    fn write(&mut self) -> Result<(), StringError> {
+        if self.writes_left == 0 {
+            return Err(StringError("Power loss"));
+        }
+
+        self.writes_left -= 1;
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl ConfigStore for FailingStore {
+    type Error = StringError;
+
+    fn load_str(&self, key: &str) -> Option<String> {
+        self.store.load_str(key)
+    }
+
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.write()?;
+        self.store.save_str(key, value)
+    }
+
+    fn load_u8(&self, key: &str) -> Option<u8> {
+        self.store.load_u8(key)
+    }
+
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error> {
+        self.write()?;
+        self.store.save_u8(key, value)
+    }
+
+    fn load_u16(&self, key: &str) -> Option<u16> {
+        self.store.load_u16(key)
+    }
+
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error> {
+        self.write()?;
+        self.store.save_u16(key, value)
+    }
+
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.load_blob(key)
+    }
+
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.write()?;
+        self.store.save_blob(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port_forward::Protocol;
+
+    #[test]
+    fn getters_default_on_empty_store() {
+        let store = MemoryConfigStore::new();
+
+        assert!(store.get_sta_networks().is_empty());
+        assert_eq!(store.get_sta_ip_config(), StaIpConfig::default());
+        assert_eq!(store.get_sta_auth_mode(), AuthMode::Auto);
+        assert_eq!(store.get_ap_ssid(), "ESP-WiFi Proxy");
+        assert_eq!(store.get_ap_passphrase(), "");
+        assert!(!store.get_ap_hidden_ssid());
+        assert_eq!(store.get_ap_ip(), Ipv4Addr::new(192, 168, 70, 1));
+        assert_eq!(store.get_ap_mask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(store.get_ap_channel(), 11);
+        assert_eq!(store.get_ap_country(), DEFAULT_COUNTRY);
+        assert_eq!(store.get_ap_max_clients(), 10);
+        assert!(!store.get_ap_client_events());
+        assert!(store.get_dhcp_reservations().is_empty());
+        assert!(!store.get_napt());
+        assert!(store.get_port_forwards().is_empty());
+        assert_eq!(store.get_mqtt_server(), "");
+        assert_eq!(store.get_mqtt_port(), 1883);
+        assert!(!store.get_mqtt_tls());
+        assert_eq!(store.get_mqtt_ca_cert(), "");
+        assert_eq!(store.get_mqtt_client_id(), "");
+        assert_eq!(store.get_mqtt_topic_prefix(), "");
+        assert!(store.get_ha_discovery());
+        assert_eq!(store.get_sensor_routes(), default_routes());
+        assert!(store.get_sensor_credentials().is_empty());
+        assert!(store.get_sensor_allowlist().is_empty());
+        assert!(!store.get_sensor_allow_all());
+    }
+
+    #[test]
+    fn scalar_values_round_trip() {
+        let mut store = MemoryConfigStore::new();
+        let sta_ip = StaIpConfig {
+            static_ip: true,
+            ip: Some(Ipv4Addr::new(10, 0, 0, 5)),
+            mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+            gateway: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            dns: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            secondary_dns: None,
+            hostname: "proxy".to_string(),
+        };
+
+        store.set_sta_ip_config(&sta_ip).unwrap();
+        store.set_sta_auth_mode(AuthMode::Wpa3).unwrap();
+        store.set_ap_ssid("Sensors").unwrap();
+        store.set_ap_passphrase("secret passphrase").unwrap();
+        store.set_ap_hidden_ssid(true).unwrap();
+        store.set_ap_ip(Ipv4Addr::new(192, 168, 4, 1)).unwrap();
+        store.set_ap_channel(6).unwrap();
+        store.set_ap_country("DE").unwrap();
+        store.set_ap_max_clients(4).unwrap();
+        store.set_mqtt_server("broker.local").unwrap();
+        store.set_mqtt_port(8883).unwrap();
+        store.set_mqtt_tls(true).unwrap();
+        store.set_ha_discovery(false).unwrap();
+        store.set_sensor_allow_all(true).unwrap();
+
+        assert_eq!(store.get_sta_ip_config(), sta_ip);
+        assert_eq!(store.get_sta_auth_mode(), AuthMode::Wpa3);
+        assert_eq!(store.get_ap_ssid(), "Sensors");
+        assert_eq!(store.get_ap_passphrase(), "secret passphrase");
+        assert!(store.get_ap_hidden_ssid());
+        assert_eq!(store.get_ap_ip(), Ipv4Addr::new(192, 168, 4, 1));
+        assert_eq!(store.get_ap_channel(), 6);
+        assert_eq!(store.get_ap_country(), "DE");
+        assert_eq!(store.get_ap_max_clients(), 4);
+        assert_eq!(store.get_mqtt_server(), "broker.local");
+        assert_eq!(store.get_mqtt_port(), 8883);
+        assert!(store.get_mqtt_tls());
+        assert!(!store.get_ha_discovery());
+        assert!(store.get_sensor_allow_all());
+    }
+
+    #[test]
+    fn lists_round_trip() {
+        let mut store = MemoryConfigStore::new();
+        let networks = vec![StaNetwork::new("home", "passphrase")];
+        let reservations = vec![DhcpReservation::new(
+            [0x02, 0, 0, 0, 0, 1],
+            Ipv4Addr::new(192, 168, 70, 20),
+        )];
+        let forwards = vec![PortForward::new(
+            Protocol::Tcp,
+            8080,
+            Ipv4Addr::new(192, 168, 70, 20),
+            80,
+        )];
+        let routes = vec![SensorRoute::new("/probe", &["t"], "probe").with_rename("t", "temp")];
+
+        store.set_sta_networks(&networks).unwrap();
+        store.set_dhcp_reservations(&reservations).unwrap();
+        store.set_port_forwards(&forwards).unwrap();
+        store.set_sensor_routes(&routes).unwrap();
+        store.set_sensor_allowlist(&["garden".to_string()]).unwrap();
+
+        assert_eq!(store.get_sta_networks(), networks);
+        assert_eq!(store.get_dhcp_reservations(), reservations);
+        assert_eq!(store.get_port_forwards(), forwards);
+        assert_eq!(store.get_sensor_routes(), routes);
+        assert_eq!(store.get_sensor_allowlist(), ["garden"]);
+    }
+
+    #[test]
+    fn empty_strings_read_as_defaults() {
+        let mut store = MemoryConfigStore::new();
+        store.set_ap_ssid("").unwrap();
+
+        assert_eq!(store.get_ap_ssid(), "ESP-WiFi Proxy");
+    }
+
+    #[test]
+    fn strings_are_truncated_on_a_char_boundary() {
+        let mut store = MemoryConfigStore::new();
+        store.set_ap_ssid(&"é".repeat(20)).unwrap();
+
+        assert_eq!(store.get_ap_ssid(), "é".repeat(16));
+        assert_eq!(trunc_string("abc", 2), "ab");
+    }
+
+    #[test]
+    fn invalid_blobs_read_as_defaults() {
+        let mut store = MemoryConfigStore::new();
+        store.save_blob(KEY_STA_NETWORKS, b"not json").unwrap();
+        store.save_blob(KEY_SENSOR_ROUTES, b"not json").unwrap();
+
+        assert!(store.get_sta_networks().is_empty());
+        assert_eq!(store.get_sensor_routes(), default_routes());
+    }
+}
@@ -0,0 +1,371 @@
+use std::convert::Infallible;
+
+use serde_json::{json, Value};
+
+use crate::config::ConfigStore;
+use crate::hex::{from_hex, to_hex};
+use crate::string_error::StringError;
+
+/// Changed keys of the commit in progress.
+pub const KEY_COMMIT_JOURNAL: &str = "CFGJRNL";
+/// Set once the journal is complete, the single write that commits it.
+pub const KEY_COMMIT_PENDING: &str = "CFGPEND";
+
+const NVS_ENTRY_SIZE: usize = 32;
+/// Data of a blob chunk, 125 entries of a page.
+const NVS_CHUNK_SIZE: usize = 4000;
+
+/// NVS space of a blob: its index entry, then a header entry per chunk and
+/// the data, in whole entries.
+pub fn nvs_blob_size(len: usize) -> usize {
+    NVS_ENTRY_SIZE * (1 + len.div_ceil(NVS_CHUNK_SIZE).max(1))
+        + len.div_ceil(NVS_ENTRY_SIZE) * NVS_ENTRY_SIZE
+}
+
+fn nvs_size(value: &StagedValue) -> usize {
+    match value {
+        StagedValue::U8(_) | StagedValue::U16(_) => NVS_ENTRY_SIZE,
+        StagedValue::Str(value) => {
+            NVS_ENTRY_SIZE + (value.len() + 1).div_ceil(NVS_ENTRY_SIZE) * NVS_ENTRY_SIZE
+        }
+        StagedValue::Blob(value) => nvs_blob_size(value.len()),
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+enum StagedValue {
+    Str(String),
+    U8(u8),
+    U16(u16),
+    Blob(Vec<u8>),
+}
+
+/// Records the writes of the `ConfigStore` setters instead of storing them,
+/// so a whole configuration can be compared to the stored one and committed
+/// at once, see `commit`.
+#[derive(Clone, Default, Debug)]
+pub struct StagedWrites {
+    entries: Vec<(String, StagedValue)>,
+}
+
+impl StagedWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, key: &str) -> Option<&StagedValue> {
+        self.entries
+            .iter()
+            .find(|(staged, _)| staged == key)
+            .map(|(_, value)| value)
+    }
+
+    fn stage(&mut self, key: &str, value: StagedValue) {
+        match self.entries.iter_mut().find(|(staged, _)| staged == key) {
+            Some((_, staged)) => *staged = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    /// NVS space of each staged key.
+    pub fn nvs_sizes(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), nvs_size(value)))
+    }
+
+    /// NVS space of the journal when every staged key changed, the most a
+    /// commit needs on top of the keys.
+    pub fn journal_nvs_size(&self) -> usize {
+        nvs_blob_size(journal_to_json(&self.entries).len())
+    }
+
+    /// Only the entries that differ from `store`.
+    fn changes(self, store: &impl ConfigStore) -> Vec<(String, StagedValue)> {
+        self.entries
+            .into_iter()
+            .filter(|(key, value)| {
+                let current = match value {
+                    StagedValue::Str(_) => store.load_str(key).map(StagedValue::Str),
+                    StagedValue::U8(_) => store.load_u8(key).map(StagedValue::U8),
+                    StagedValue::U16(_) => store.load_u16(key).map(StagedValue::U16),
+                    StagedValue::Blob(_) => store.load_blob(key).map(StagedValue::Blob),
+                };
+
+                current.as_ref() != Some(value)
+            })
+            .collect()
+    }
+}
+
+impl ConfigStore for StagedWrites {
+    type Error = Infallible;
+
+    fn load_str(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            StagedValue::Str(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.stage(key, StagedValue::Str(value.to_string()));
+        Ok(())
+    }
+
+    fn load_u8(&self, key: &str) -> Option<u8> {
+        match self.get(key)? {
+            StagedValue::U8(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error> {
+        self.stage(key, StagedValue::U8(value));
+        Ok(())
+    }
+
+    fn load_u16(&self, key: &str) -> Option<u16> {
+        match self.get(key)? {
+            StagedValue::U16(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error> {
+        self.stage(key, StagedValue::U16(value));
+        Ok(())
+    }
+
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>> {
+        match self.get(key)? {
+            StagedValue::Blob(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.stage(key, StagedValue::Blob(value.to_vec()));
+        Ok(())
+    }
+}
+
+/// Writes the changed entries of `staged` as a single transaction: they are
+/// saved to the journal first, then `KEY_COMMIT_PENDING` is set and the keys
+/// are written. A power loss before the flag keeps the previous
+/// configuration, after it `recover` completes the commit at the next start.
+/// Unchanged keys are not written at all.
+pub fn commit<S: ConfigStore>(store: &mut S, staged: StagedWrites) -> Result<(), S::Error> {
+    let changes = staged.changes(store);
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    store.save_blob(KEY_COMMIT_JOURNAL, journal_to_json(&changes).as_bytes())?;
+    store.save_u8(KEY_COMMIT_PENDING, 1)?;
+
+    apply(store, &changes)?;
+    finish(store)
+}
+
+/// Completes a commit interrupted after its journal was flagged. Called
+/// before the configuration is read, ahead of the migrations.
+pub fn recover<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    if store.load_u8(KEY_COMMIT_PENDING) != Some(1) {
+        return Ok(());
+    }
+
+    let journal = store.load_blob(KEY_COMMIT_JOURNAL).unwrap_or_default();
+
+    match String::from_utf8(journal)
+        .map_err(|_| StringError("Journal is not UTF-8"))
+        .and_then(|s| journal_from_json(&s))
+    {
+        Ok(changes) => {
+            log::warn!("Complete interrupted configuration commit.");
+            apply(store, &changes)?;
+        }
+        Err(e) => log::error!("Invalid configuration journal ({}), dropped.", e),
+    }
+
+    finish(store)
+}
+
+fn apply<S: ConfigStore>(store: &mut S, changes: &[(String, StagedValue)]) -> Result<(), S::Error> {
+    for (key, value) in changes {
+        match value {
+            StagedValue::Str(value) => store.save_str(key, value)?,
+            StagedValue::U8(value) => store.save_u8(key, *value)?,
+            StagedValue::U16(value) => store.save_u16(key, *value)?,
+            StagedValue::Blob(value) => store.save_blob(key, value)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// The journal is emptied so it does not hold on to flash and secrets.
+fn finish<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    store.save_u8(KEY_COMMIT_PENDING, 0)?;
+    store.save_blob(KEY_COMMIT_JOURNAL, b"[]")
+}
+
+/// `[{"key": ..., "<type>": <value>}]`, blobs as `blob` text when UTF-8, as
+/// `hex` otherwise.
+fn journal_to_json(changes: &[(String, StagedValue)]) -> String {
+    Value::Array(
+        changes
+            .iter()
+            .map(|(key, value)| match value {
+                StagedValue::Str(value) => json!({ "key": key, "str": value }),
+                StagedValue::U8(value) => json!({ "key": key, "u8": value }),
+                StagedValue::U16(value) => json!({ "key": key, "u16": value }),
+                StagedValue::Blob(value) => match std::str::from_utf8(value) {
+                    Ok(text) => json!({ "key": key, "blob": text }),
+                    Err(_) => json!({ "key": key, "hex": to_hex(value) }),
+                },
+            })
+            .collect(),
+    )
+    .to_string()
+}
+
+fn journal_from_json(s: &str) -> Result<Vec<(String, StagedValue)>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Journal is not valid JSON"))?;
+
+    value
+        .as_array()
+        .ok_or(StringError("Journal must be a JSON array"))?
+        .iter()
+        .map(|entry| {
+            let key = entry
+                .get("key")
+                .and_then(Value::as_str)
+                .ok_or(StringError("Journal entry without key"))?;
+
+            let value = if let Some(value) = entry.get("str").and_then(Value::as_str) {
+                StagedValue::Str(value.to_string())
+            } else if let Some(value) = entry.get("u8").and_then(Value::as_u64) {
+                StagedValue::U8(u8::try_from(value).map_err(|_| StringError("Invalid u8"))?)
+            } else if let Some(value) = entry.get("u16").and_then(Value::as_u64) {
+                StagedValue::U16(u16::try_from(value).map_err(|_| StringError("Invalid u16"))?)
+            } else if let Some(value) = entry.get("blob").and_then(Value::as_str) {
+                StagedValue::Blob(value.as_bytes().to_vec())
+            } else if let Some(value) = entry.get("hex").and_then(Value::as_str) {
+                StagedValue::Blob(from_hex(value).ok_or(StringError("Invalid hex"))?)
+            } else {
+                return Err(StringError("Journal entry without value"));
+            };
+
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FailingStore, MemoryConfigStore, KEY_AP_SSID, KEY_SENSOR_ROUTES};
+    use crate::proxy_config::ProxyConfig;
+
+    fn configured_store() -> MemoryConfigStore {
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+        config.ap_ssid = "Before".to_string();
+        config.commit(&mut store).unwrap();
+        store
+    }
+
+    #[test]
+    fn unchanged_config_writes_nothing() {
+        let store = configured_store();
+        let config = ProxyConfig::load(&store);
+        let mut failing = FailingStore::new(store, 0);
+
+        assert_eq!(config.commit(&mut failing), Ok(()));
+        assert_eq!(failing.writes, 0);
+    }
+
+    #[test]
+    fn only_changed_keys_are_written() {
+        let store = configured_store();
+        let mut config = ProxyConfig::load(&store);
+        config.ap_ssid = "After".to_string();
+        let mut failing = FailingStore::new(store, usize::MAX);
+
+        config.commit(&mut failing).unwrap();
+
+        // Journal, flag, the SSID, then the flag and journal cleared.
+        assert_eq!(failing.writes, 5);
+        assert_eq!(failing.store.get_ap_ssid(), "After");
+        assert_eq!(failing.store.load_u8(KEY_COMMIT_PENDING), Some(0));
+        assert!(failing.store.load_blob(KEY_SENSOR_ROUTES).is_some());
+    }
+
+    #[test]
+    fn power_loss_before_the_flag_keeps_previous_config() {
+        let store = configured_store();
+        let before = store.as_json().clone();
+        let mut config = ProxyConfig::load(&store);
+        config.ap_ssid = "After".to_string();
+        config.mqtt_port = 8883;
+
+        // Only the journal is written.
+        let mut failing = FailingStore::new(store, 1);
+        assert!(config.commit(&mut failing).is_err());
+
+        let mut store = failing.store;
+        recover(&mut store).unwrap();
+
+        assert_eq!(store.get_ap_ssid(), "Before");
+        assert_eq!(store.get_mqtt_port(), 1883);
+        assert_eq!(store.as_json().get(KEY_AP_SSID), before.get(KEY_AP_SSID));
+    }
+
+    #[test]
+    fn power_loss_after_the_flag_is_completed_by_recover() {
+        let store = configured_store();
+        let mut config = ProxyConfig::load(&store);
+        config.ap_ssid = "After".to_string();
+        config.mqtt_port = 8883;
+
+        // Journal, flag and the first key are written.
+        let mut failing = FailingStore::new(store, 3);
+        assert!(config.commit(&mut failing).is_err());
+
+        let mut store = failing.store;
+        recover(&mut store).unwrap();
+
+        assert_eq!(store.get_ap_ssid(), "After");
+        assert_eq!(store.get_mqtt_port(), 8883);
+        assert_eq!(store.load_u8(KEY_COMMIT_PENDING), Some(0));
+        assert_eq!(ProxyConfig::load(&store), config);
+    }
+
+    #[test]
+    fn journal_round_trips_every_type() {
+        let changes = vec![
+            ("S".to_string(), StagedValue::Str("text".to_string())),
+            ("A".to_string(), StagedValue::U8(7)),
+            ("B".to_string(), StagedValue::U16(8883)),
+            ("C".to_string(), StagedValue::Blob(b"[1]".to_vec())),
+            ("D".to_string(), StagedValue::Blob(vec![0xff, 0x00])),
+        ];
+
+        assert_eq!(journal_from_json(&journal_to_json(&changes)), Ok(changes));
+    }
+
+    #[test]
+    fn invalid_journal_is_dropped() {
+        let mut store = configured_store();
+        store.save_blob(KEY_COMMIT_JOURNAL, b"{").unwrap();
+        store.save_u8(KEY_COMMIT_PENDING, 1).unwrap();
+
+        recover(&mut store).unwrap();
+
+        assert_eq!(store.get_ap_ssid(), "Before");
+        assert_eq!(store.load_u8(KEY_COMMIT_PENDING), Some(0));
+    }
+}
@@ -0,0 +1,710 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::ap_clients::format_mac;
+use crate::string_error::StringError;
+
+pub const MAX_DHCP_RESERVATIONS: usize = 16;
+/// Dynamic leases start right after the AP address, as with the IDF server,
+/// and wrap to the start of the subnet when the AP is at its end.
+const MAX_POOL_SIZE: usize = 100;
+const LEASE_TIME: Duration = Duration::from_secs(2 * 60 * 60);
+/// An offer is kept that long for the client to request it.
+const OFFER_TIME: Duration = Duration::from_secs(60);
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed BOOTP header, the options follow the magic cookie.
+const HEADER_LEN: usize = 236;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+
+/// Fixed address of a sensor on the AP subnet.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DhcpReservation {
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+}
+
+impl DhcpReservation {
+    pub fn new(mac: [u8; 6], ip: Ipv4Addr) -> Self {
+        Self { mac, ip }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "mac": format_mac(&self.mac),
+            "ip": self.ip.to_string(),
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, StringError> {
+        let mac = value
+            .get("mac")
+            .and_then(Value::as_str)
+            .and_then(parse_mac)
+            .ok_or(StringError(
+                "Reservation MAC must look like aa:bb:cc:dd:ee:ff",
+            ))?;
+
+        let ip = value
+            .get("ip")
+            .and_then(Value::as_str)
+            .and_then(|ip| ip.parse().ok())
+            .ok_or(StringError("Reservation IP must be an IPv4 address"))?;
+
+        Ok(Self::new(mac, ip))
+    }
+}
+
+pub fn reservations_from_json(s: &str) -> Result<Vec<DhcpReservation>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Reservations are not valid JSON"))?;
+
+    let reservations = value
+        .as_array()
+        .ok_or(StringError("Reservations must be a JSON array"))?
+        .iter()
+        .map(DhcpReservation::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if reservations.len() > MAX_DHCP_RESERVATIONS {
+        return Err(StringError("Too many reservations (16 max)"));
+    }
+
+    Ok(reservations)
+}
+
+pub fn reservations_to_json(reservations: &[DhcpReservation]) -> String {
+    Value::Array(reservations.iter().map(DhcpReservation::to_json).collect()).to_string()
+}
+
+/// `aa:bb:cc:dd:ee:ff` or `aa-bb-cc-dd-ee-ff`, any case.
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let bytes = s
+        .trim()
+        .split([':', '-'])
+        .map(|b| {
+            (b.len() == 2)
+                .then(|| u8::from_str_radix(b, 16).ok())
+                .flatten()
+        })
+        .collect::<Option<Vec<u8>>>()?;
+
+    bytes.try_into().ok()
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+struct Lease {
+    mac: [u8; 6],
+    ip: Ipv4Addr,
+    expires: Duration,
+    /// Offered but not requested yet.
+    offered: bool,
+}
+
+/// Lease given by the last acknowledged request.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DhcpReply {
+    pub packet: Vec<u8>,
+    pub assigned: Option<([u8; 6], Ipv4Addr)>,
+}
+
+/// DHCP server of the AP subnet, used instead of the IDF one when addresses
+/// are reserved. It only builds the replies, the caller owns the socket and
+/// sends them to the subnet broadcast address.
+#[derive(Clone, Debug)]
+pub struct DhcpServer {
+    server_ip: Ipv4Addr,
+    prefix_len: u8,
+    dns: Option<Ipv4Addr>,
+    reservations: Vec<DhcpReservation>,
+    leases: Vec<Lease>,
+}
+
+impl DhcpServer {
+    pub fn new(server_ip: Ipv4Addr, prefix_len: u8, reservations: Vec<DhcpReservation>) -> Self {
+        Self {
+            server_ip,
+            prefix_len,
+            dns: None,
+            reservations,
+            leases: Vec::new(),
+        }
+    }
+
+    /// Offered to the clients, e.g. the upstream DNS with NAPT.
+    pub fn set_dns(&mut self, dns: Option<Ipv4Addr>) {
+        self.dns = dns;
+    }
+
+    pub fn broadcast_address(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.server_ip) | self.host_mask())
+    }
+
+    /// Returns the reply to send, if any.
+    pub fn handle(&mut self, packet: &[u8], now: Duration) -> Option<DhcpReply> {
+        let request = Request::parse(packet)?;
+        self.leases.retain(|lease| lease.expires > now);
+
+        match request.message_type {
+            DHCPDISCOVER => {
+                let ip = self.allocate(&request.mac, request.requested_ip)?;
+                self.record_lease(request.mac, ip, now + OFFER_TIME, true);
+
+                Some(DhcpReply {
+                    packet: self.reply(&request, DHCPOFFER, Some(ip)),
+                    assigned: None,
+                })
+            }
+            DHCPREQUEST => {
+                if request.server_id.is_some_and(|id| id != self.server_ip) {
+                    // The client took the offer of another server.
+                    self.leases
+                        .retain(|lease| !(lease.mac == request.mac && lease.offered));
+                    return None;
+                }
+
+                let requested = request
+                    .requested_ip
+                    .or((!request.client_ip.is_unspecified()).then_some(request.client_ip));
+
+                match (requested, self.allocate(&request.mac, requested)) {
+                    (Some(requested), Some(ip)) if requested == ip => {
+                        self.record_lease(request.mac, ip, now + LEASE_TIME, false);
+
+                        Some(DhcpReply {
+                            packet: self.reply(&request, DHCPACK, Some(ip)),
+                            assigned: Some((request.mac, ip)),
+                        })
+                    }
+                    _ => Some(DhcpReply {
+                        packet: self.reply(&request, DHCPNAK, None),
+                        assigned: None,
+                    }),
+                }
+            }
+            DHCPRELEASE | DHCPDECLINE => {
+                self.leases.retain(|lease| lease.mac != request.mac);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The reserved address, the current lease, the requested address when
+    /// free, or the first free address of the pool.
+    fn allocate(&self, mac: &[u8; 6], requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(reservation) = self.reservations.iter().find(|r| r.mac == *mac) {
+            return Some(reservation.ip);
+        }
+
+        if let Some(lease) = self.leases.iter().find(|lease| lease.mac == *mac) {
+            return Some(lease.ip);
+        }
+
+        if let Some(requested) = requested {
+            if self.pool().any(|ip| ip == requested) && self.is_free(requested) {
+                return Some(requested);
+            }
+        }
+
+        self.pool().find(|&ip| self.is_free(ip))
+    }
+
+    fn is_free(&self, ip: Ipv4Addr) -> bool {
+        ip != self.server_ip
+            && !self.reservations.iter().any(|r| r.ip == ip)
+            && !self.leases.iter().any(|lease| lease.ip == ip)
+    }
+
+    /// Host addresses following the AP one, in allocation order.
+    fn pool(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let network = u32::from(self.server_ip) & !self.host_mask();
+        let hosts = self.host_mask().saturating_sub(1);
+        let server = u32::from(self.server_ip) - network;
+
+        // Host offsets are 1..=hosts, the server one is last and left out.
+        (1..hosts)
+            .map(move |i| network + (server + i - 1) % hosts + 1)
+            .map(Ipv4Addr::from)
+            .take(MAX_POOL_SIZE)
+    }
+
+    fn host_mask(&self) -> u32 {
+        u32::MAX.checked_shr(self.prefix_len as u32).unwrap_or(0)
+    }
+
+    fn record_lease(&mut self, mac: [u8; 6], ip: Ipv4Addr, expires: Duration, offered: bool) {
+        self.leases.retain(|lease| lease.mac != mac);
+        self.leases.push(Lease {
+            mac,
+            ip,
+            expires,
+            offered,
+        });
+    }
+
+    fn reply(&self, request: &Request, message_type: u8, ip: Option<Ipv4Addr>) -> Vec<u8> {
+        let mut packet = vec![0u8; HEADER_LEN];
+
+        packet[0] = BOOTREPLY;
+        packet[1] = HTYPE_ETHERNET;
+        packet[2] = 6;
+        packet[4..8].copy_from_slice(&request.xid);
+        packet[10..12].copy_from_slice(&request.flags);
+        packet[16..20].copy_from_slice(&ip.unwrap_or(Ipv4Addr::UNSPECIFIED).octets());
+        packet[20..24].copy_from_slice(&self.server_ip.octets());
+        packet[28..34].copy_from_slice(&request.mac);
+        packet.extend_from_slice(&MAGIC_COOKIE);
+
+        push_option(&mut packet, OPTION_MESSAGE_TYPE, &[message_type]);
+        push_option(&mut packet, OPTION_SERVER_ID, &self.server_ip.octets());
+
+        if message_type != DHCPNAK {
+            let mask = Ipv4Addr::from(!self.host_mask());
+
+            push_option(
+                &mut packet,
+                OPTION_LEASE_TIME,
+                &(LEASE_TIME.as_secs() as u32).to_be_bytes(),
+            );
+            push_option(&mut packet, OPTION_SUBNET_MASK, &mask.octets());
+            push_option(&mut packet, OPTION_ROUTER, &self.server_ip.octets());
+
+            if let Some(dns) = self.dns {
+                push_option(&mut packet, OPTION_DNS, &dns.octets());
+            }
+        }
+
+        packet.push(OPTION_END);
+        packet
+    }
+}
+
+/// Fields of a client message the server uses.
+struct Request {
+    message_type: u8,
+    xid: [u8; 4],
+    flags: [u8; 2],
+    client_ip: Ipv4Addr,
+    mac: [u8; 6],
+    requested_ip: Option<Ipv4Addr>,
+    server_id: Option<Ipv4Addr>,
+}
+
+impl Request {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < HEADER_LEN + MAGIC_COOKIE.len()
+            || packet[0] != BOOTREQUEST
+            || packet[1] != HTYPE_ETHERNET
+            || packet[2] != 6
+            || packet[HEADER_LEN..HEADER_LEN + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        let mut request = Request {
+            message_type: 0,
+            xid: packet[4..8].try_into().ok()?,
+            flags: packet[10..12].try_into().ok()?,
+            client_ip: ipv4_at(packet, 12)?,
+            mac: packet[28..34].try_into().ok()?,
+            requested_ip: None,
+            server_id: None,
+        };
+
+        let mut options = &packet[HEADER_LEN + 4..];
+
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_END => break,
+                OPTION_PAD => options = rest,
+                _ => {
+                    let (&len, rest) = rest.split_first()?;
+                    let value = rest.get(..len as usize)?;
+
+                    match code {
+                        OPTION_MESSAGE_TYPE => request.message_type = *value.first()?,
+                        OPTION_REQUESTED_IP => request.requested_ip = ipv4_at(value, 0),
+                        OPTION_SERVER_ID => request.server_id = ipv4_at(value, 0),
+                        _ => (),
+                    }
+
+                    options = &rest[len as usize..];
+                }
+            }
+        }
+
+        (request.message_type != 0).then_some(request)
+    }
+}
+
+/// Hardware address of a client message, `None` when it is not one.
+pub fn client_mac(packet: &[u8]) -> Option<[u8; 6]> {
+    Request::parse(packet).map(|request| request.mac)
+}
+
+fn ipv4_at(bytes: &[u8], offset: usize) -> Option<Ipv4Addr> {
+    let octets: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+    Some(Ipv4Addr::from(octets))
+}
+
+fn push_option(packet: &mut Vec<u8>, code: u8, value: &[u8]) {
+    packet.push(code);
+    packet.push(value.len() as u8);
+    packet.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 70, 1);
+    const MAC: [u8; 6] = [0x24, 0x0a, 0xc4, 0, 0, 1];
+    const OTHER_MAC: [u8; 6] = [0x24, 0x0a, 0xc4, 0, 0, 2];
+
+    fn packet(message_type: u8, mac: [u8; 6], options: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut packet = vec![0u8; HEADER_LEN];
+        packet[0] = BOOTREQUEST;
+        packet[1] = HTYPE_ETHERNET;
+        packet[2] = 6;
+        packet[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        packet[28..34].copy_from_slice(&mac);
+        packet.extend_from_slice(&MAGIC_COOKIE);
+
+        push_option(&mut packet, OPTION_MESSAGE_TYPE, &[message_type]);
+        for (code, value) in options {
+            push_option(&mut packet, *code, value);
+        }
+
+        packet.push(OPTION_END);
+        packet
+    }
+
+    fn discover(mac: [u8; 6]) -> Vec<u8> {
+        packet(DHCPDISCOVER, mac, &[])
+    }
+
+    fn request(mac: [u8; 6], ip: Ipv4Addr) -> Vec<u8> {
+        request_to(AP_IP, mac, ip)
+    }
+
+    fn request_to(server_ip: Ipv4Addr, mac: [u8; 6], ip: Ipv4Addr) -> Vec<u8> {
+        packet(
+            DHCPREQUEST,
+            mac,
+            &[
+                (OPTION_REQUESTED_IP, &ip.octets()),
+                (OPTION_SERVER_ID, &server_ip.octets()),
+            ],
+        )
+    }
+
+    fn option(packet: &[u8], code: u8) -> Option<&[u8]> {
+        let mut options = &packet[HEADER_LEN + 4..];
+
+        while let [c, len, rest @ ..] = options {
+            if *c == code {
+                return Some(&rest[..*len as usize]);
+            }
+            options = &rest[*len as usize..];
+        }
+
+        None
+    }
+
+    fn message_type(reply: &DhcpReply) -> u8 {
+        option(&reply.packet, OPTION_MESSAGE_TYPE).unwrap()[0]
+    }
+
+    fn your_ip(reply: &DhcpReply) -> Ipv4Addr {
+        ipv4_at(&reply.packet, 16).unwrap()
+    }
+
+    /// DISCOVER then REQUEST of the offered address, returns the lease.
+    fn lease(server: &mut DhcpServer, mac: [u8; 6], now: Duration) -> Ipv4Addr {
+        let offer = server.handle(&discover(mac), now).unwrap();
+        let request = request_to(server.server_ip, mac, your_ip(&offer));
+        let ack = server.handle(&request, now).unwrap();
+
+        assert_eq!(message_type(&ack), DHCPACK);
+        your_ip(&ack)
+    }
+
+    #[test]
+    fn discover_offer_request_ack() {
+        let mut server = DhcpServer::new(AP_IP, 24, Vec::new());
+        server.set_dns(Some(Ipv4Addr::new(1, 1, 1, 1)));
+        let ip = Ipv4Addr::new(192, 168, 70, 2);
+
+        let offer = server.handle(&discover(MAC), Duration::ZERO).unwrap();
+        assert_eq!(message_type(&offer), DHCPOFFER);
+        assert_eq!(your_ip(&offer), ip);
+        assert_eq!(offer.assigned, None);
+        assert_eq!(offer.packet[0], BOOTREPLY);
+        assert_eq!(offer.packet[4..8], [1, 2, 3, 4]);
+        assert_eq!(offer.packet[28..34], MAC);
+        assert_eq!(
+            option(&offer.packet, OPTION_SERVER_ID),
+            Some(&AP_IP.octets()[..])
+        );
+        assert_eq!(
+            option(&offer.packet, OPTION_SUBNET_MASK),
+            Some(&[255, 255, 255, 0][..])
+        );
+        assert_eq!(
+            option(&offer.packet, OPTION_ROUTER),
+            Some(&AP_IP.octets()[..])
+        );
+        assert_eq!(option(&offer.packet, OPTION_DNS), Some(&[1, 1, 1, 1][..]));
+        assert_eq!(
+            option(&offer.packet, OPTION_LEASE_TIME),
+            Some(&7200u32.to_be_bytes()[..])
+        );
+
+        let ack = server.handle(&request(MAC, ip), Duration::ZERO).unwrap();
+        assert_eq!(message_type(&ack), DHCPACK);
+        assert_eq!(your_ip(&ack), ip);
+        assert_eq!(ack.assigned, Some((MAC, ip)));
+
+        // The next client gets the next address, the first one renews its own.
+        assert_eq!(
+            lease(&mut server, OTHER_MAC, Duration::ZERO),
+            Ipv4Addr::new(192, 168, 70, 3)
+        );
+        assert_eq!(lease(&mut server, MAC, Duration::from_secs(60)), ip);
+    }
+
+    #[test]
+    fn reservations_are_honoured() {
+        let reserved = Ipv4Addr::new(192, 168, 70, 2);
+        let mut server = DhcpServer::new(AP_IP, 24, vec![DhcpReservation::new(MAC, reserved)]);
+
+        // Another client does not get the reserved address, even asking for it.
+        let offer = server
+            .handle(
+                &packet(
+                    DHCPDISCOVER,
+                    OTHER_MAC,
+                    &[(OPTION_REQUESTED_IP, &reserved.octets())],
+                ),
+                Duration::ZERO,
+            )
+            .unwrap();
+        assert_eq!(your_ip(&offer), Ipv4Addr::new(192, 168, 70, 3));
+
+        let nak = server
+            .handle(&request(OTHER_MAC, reserved), Duration::ZERO)
+            .unwrap();
+        assert_eq!(message_type(&nak), DHCPNAK);
+
+        // The reserved client gets it, whatever it asks for.
+        let offer = server
+            .handle(
+                &packet(
+                    DHCPDISCOVER,
+                    MAC,
+                    &[(OPTION_REQUESTED_IP, &[192, 168, 70, 50])],
+                ),
+                Duration::ZERO,
+            )
+            .unwrap();
+        assert_eq!(your_ip(&offer), reserved);
+        assert_eq!(lease(&mut server, MAC, Duration::ZERO), reserved);
+    }
+
+    #[test]
+    fn foreign_requests_are_refused() {
+        let mut server = DhcpServer::new(AP_IP, 24, Vec::new());
+
+        // Address of another network, e.g. after roaming.
+        let nak = server
+            .handle(&request(MAC, Ipv4Addr::new(10, 0, 0, 5)), Duration::ZERO)
+            .unwrap();
+        assert_eq!(message_type(&nak), DHCPNAK);
+        assert_eq!(your_ip(&nak), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(nak.assigned, None);
+        assert_eq!(option(&nak.packet, OPTION_LEASE_TIME), None);
+
+        // Address leased to another client.
+        let ip = lease(&mut server, OTHER_MAC, Duration::ZERO);
+        let nak = server.handle(&request(MAC, ip), Duration::ZERO).unwrap();
+        assert_eq!(message_type(&nak), DHCPNAK);
+    }
+
+    #[test]
+    fn offer_of_another_server_taken_frees_ours() {
+        let mut server = DhcpServer::new(AP_IP, 24, Vec::new());
+        let offered = your_ip(&server.handle(&discover(MAC), Duration::ZERO).unwrap());
+
+        let other_server = packet(
+            DHCPREQUEST,
+            MAC,
+            &[
+                (OPTION_REQUESTED_IP, &[192, 168, 70, 9]),
+                (OPTION_SERVER_ID, &[192, 168, 70, 254]),
+            ],
+        );
+        assert_eq!(server.handle(&other_server, Duration::ZERO), None);
+
+        assert_eq!(lease(&mut server, OTHER_MAC, Duration::ZERO), offered);
+    }
+
+    #[test]
+    fn expired_and_released_leases_are_reused() {
+        // A /30 has a single free address.
+        let mut server = DhcpServer::new(Ipv4Addr::new(10, 0, 0, 1), 30, Vec::new());
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        let request = |mac| request_to(Ipv4Addr::new(10, 0, 0, 1), mac, ip);
+
+        // An offer holds the address until it times out.
+        assert!(server.handle(&discover(MAC), Duration::ZERO).is_some());
+        assert_eq!(server.handle(&discover(OTHER_MAC), Duration::ZERO), None);
+        assert!(server.handle(&discover(OTHER_MAC), OFFER_TIME).is_some());
+
+        let ack = server.handle(&request(OTHER_MAC), OFFER_TIME).unwrap();
+        assert_eq!(ack.assigned, Some((OTHER_MAC, ip)));
+        assert_eq!(server.handle(&discover(MAC), LEASE_TIME), None);
+
+        // Expired.
+        let later = OFFER_TIME + LEASE_TIME;
+        assert_eq!(your_ip(&server.handle(&discover(MAC), later).unwrap()), ip);
+
+        // Released.
+        assert!(server
+            .handle(&request(MAC), later)
+            .unwrap()
+            .assigned
+            .is_some());
+        assert_eq!(server.handle(&packet(DHCPRELEASE, MAC, &[]), later), None);
+        assert_eq!(
+            your_ip(&server.handle(&discover(OTHER_MAC), later).unwrap()),
+            ip
+        );
+    }
+
+    #[test]
+    fn pool_wraps_when_the_ap_is_last() {
+        let mut server = DhcpServer::new(Ipv4Addr::new(192, 168, 70, 254), 24, Vec::new());
+        assert_eq!(
+            lease(&mut server, MAC, Duration::ZERO),
+            Ipv4Addr::new(192, 168, 70, 1)
+        );
+
+        let mut server = DhcpServer::new(Ipv4Addr::new(10, 0, 0, 2), 30, Vec::new());
+        assert_eq!(
+            lease(&mut server, MAC, Duration::ZERO),
+            Ipv4Addr::new(10, 0, 0, 1)
+        );
+        assert_eq!(server.handle(&discover(OTHER_MAC), Duration::ZERO), None);
+    }
+
+    #[test]
+    fn pool_is_bounded() {
+        let server = DhcpServer::new(Ipv4Addr::new(10, 0, 0, 1), 8, Vec::new());
+
+        assert_eq!(server.pool().count(), MAX_POOL_SIZE);
+        assert_eq!(server.pool().last(), Some(Ipv4Addr::new(10, 0, 0, 101)));
+        assert_eq!(server.broadcast_address(), Ipv4Addr::new(10, 255, 255, 255));
+    }
+
+    #[test]
+    fn malformed_packets_are_ignored() {
+        let mut server = DhcpServer::new(AP_IP, 24, Vec::new());
+        let valid = discover(MAC);
+        let options = HEADER_LEN + 4;
+
+        let mut reply = valid.clone();
+        reply[0] = BOOTREPLY;
+        let mut token_ring = valid.clone();
+        token_ring[1] = 6;
+        let mut cookie = valid.clone();
+        cookie[HEADER_LEN] = 0;
+        let mut overlong = valid[..options].to_vec();
+        overlong.extend_from_slice(&[OPTION_MESSAGE_TYPE, 200, DHCPDISCOVER]);
+        let mut empty_type = valid[..options].to_vec();
+        empty_type.extend_from_slice(&[OPTION_MESSAGE_TYPE, 0, OPTION_END]);
+
+        for (name, packet) in [
+            ("empty", Vec::new()),
+            ("truncated header", valid[..100].to_vec()),
+            ("no cookie", valid[..HEADER_LEN].to_vec()),
+            ("no message type", valid[..options].to_vec()),
+            ("truncated option", valid[..options + 2].to_vec()),
+            ("reply", reply),
+            ("not ethernet", token_ring),
+            ("bad cookie", cookie),
+            ("overlong option", overlong),
+            ("empty message type", empty_type),
+        ] {
+            assert_eq!(server.handle(&packet, Duration::ZERO), None, "{}", name);
+            assert_eq!(client_mac(&packet), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn padding_unknown_and_trailing_bytes_are_skipped() {
+        let mut server = DhcpServer::new(AP_IP, 24, Vec::new());
+        let mut packet = discover(MAC)[..HEADER_LEN + 4].to_vec();
+        packet.extend_from_slice(&[OPTION_PAD, OPTION_PAD, 12, 3, b'a', b'b', b'c']);
+        // A short requested address is ignored.
+        packet.extend_from_slice(&[OPTION_REQUESTED_IP, 2, 192, 168]);
+        packet.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER, OPTION_END]);
+        packet.extend_from_slice(&[0xff; 300]);
+
+        let offer = server.handle(&packet, Duration::ZERO).unwrap();
+        assert_eq!(your_ip(&offer), Ipv4Addr::new(192, 168, 70, 2));
+        assert_eq!(client_mac(&packet), Some(MAC));
+    }
+
+    #[test]
+    fn mac_addresses() {
+        assert_eq!(parse_mac("24:0A:c4:00:00:01"), Some(MAC));
+        assert_eq!(parse_mac(" 24-0a-c4-00-00-01 "), Some(MAC));
+        assert_eq!(parse_mac("24:0a:c4:00:00"), None);
+        assert_eq!(parse_mac("24:0a:c4:00:00:01:02"), None);
+        assert_eq!(parse_mac("240a:c4:00:00:01"), None);
+        assert_eq!(parse_mac("24:0a:c4:00:00:0g"), None);
+    }
+
+    #[test]
+    fn reservations_round_trip_as_json() {
+        let reservations = vec![DhcpReservation::new(MAC, Ipv4Addr::new(192, 168, 70, 2))];
+
+        assert_eq!(
+            reservations_from_json(&reservations_to_json(&reservations)),
+            Ok(reservations)
+        );
+        assert_eq!(
+            reservations_from_json(r#"[{"mac":"24:0a:c4","ip":"192.168.70.2"}]"#),
+            Err(StringError(
+                "Reservation MAC must look like aa:bb:cc:dd:ee:ff"
+            ))
+        );
+        assert_eq!(
+            reservations_from_json(r#"[{"mac":"24:0a:c4:00:00:01"}]"#),
+            Err(StringError("Reservation IP must be an IPv4 address"))
+        );
+    }
+}
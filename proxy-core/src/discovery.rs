@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Map, Value};
+
+use crate::outbox::OutboxMessage;
+use crate::sensor_route::SensorRoute;
+use crate::string_error::StringError;
+
+/// Topic root Home Assistant listens to, it is not affected by the topic prefix.
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Beyond this, the known sensors are forgotten and announced again on their
+/// next reading. Config messages are retained, so announcing twice is harmless.
+const MAX_KNOWN_SENSORS: usize = 64;
+
+/// One Home Assistant sensor entity, fed by a key of the published payload.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DiscoveryEntity {
+    /// Published key, after renaming.
+    pub key: String,
+    pub name: String,
+    pub device_class: Option<String>,
+    pub unit: Option<String>,
+    /// Shown in the diagnostic section of the device page.
+    pub diagnostic: bool,
+}
+
+impl DiscoveryEntity {
+    pub fn new(key: &str, name: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            name: name.to_string(),
+            device_class: None,
+            unit: None,
+            diagnostic: false,
+        }
+    }
+
+    pub fn with_device_class(mut self, device_class: &str) -> Self {
+        self.device_class = Some(device_class.to_string());
+        self
+    }
+
+    pub fn with_unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn diagnostic(mut self) -> Self {
+        self.diagnostic = true;
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+
+        obj.insert("key".to_string(), self.key.clone().into());
+        obj.insert("name".to_string(), self.name.clone().into());
+        if let Some(device_class) = &self.device_class {
+            obj.insert("device_class".to_string(), device_class.clone().into());
+        }
+        if let Some(unit) = &self.unit {
+            obj.insert("unit".to_string(), unit.clone().into());
+        }
+        if self.diagnostic {
+            obj.insert("diagnostic".to_string(), true.into());
+        }
+
+        Value::Object(obj)
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, StringError> {
+        let obj = value
+            .as_object()
+            .ok_or(StringError("Discovery entity must be an object"))?;
+
+        let key = obj
+            .get("key")
+            .and_then(Value::as_str)
+            .filter(|k| !k.is_empty())
+            .ok_or(StringError("Discovery entity without key"))?;
+
+        let name = obj.get("name").and_then(Value::as_str).unwrap_or(key);
+
+        let optional_str = |field: &str| match obj.get(field) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => Err(StringError("Discovery entity fields must be strings")),
+        };
+
+        Ok(Self {
+            key: key.to_string(),
+            name: name.to_string(),
+            device_class: optional_str("device_class")?,
+            unit: optional_str("unit")?,
+            diagnostic: obj
+                .get("diagnostic")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Builds the retained Home Assistant discovery config messages and remembers
+/// which sensors were already announced since boot.
+pub struct HomeAssistantDiscovery {
+    client_id: String,
+    availability_topic: String,
+    known: HashSet<(String, String)>,
+}
+
+impl HomeAssistantDiscovery {
+    pub fn new(client_id: &str, availability_topic: &str) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            availability_topic: availability_topic.to_string(),
+            known: HashSet::new(),
+        }
+    }
+
+    pub fn is_known(&self, route: &SensorRoute, id: &str) -> bool {
+        self.known.contains(&(route.path.clone(), id.to_string()))
+    }
+
+    pub fn remember(&mut self, route: &SensorRoute, id: &str) {
+        if self.known.len() >= MAX_KNOWN_SENSORS {
+            self.known.clear();
+        }
+
+        self.known.insert((route.path.clone(), id.to_string()));
+    }
+
+    /// Connectivity entity of the proxy itself, it creates the proxy device
+    /// every sensor device is attached to.
+    pub fn proxy_messages(&self) -> Vec<OutboxMessage> {
+        let node_id = sanitize(&self.client_id);
+        let config = json!({
+            "name": "Connectivity",
+            "unique_id": format!("{}_connectivity", node_id),
+            "device_class": "connectivity",
+            "entity_category": "diagnostic",
+            "state_topic": self.availability_topic,
+            "payload_on": crate::mqtt::AVAILABILITY_ONLINE,
+            "payload_off": crate::mqtt::AVAILABILITY_OFFLINE,
+            "device": self.proxy_device(),
+        });
+
+        vec![OutboxMessage::new(
+            &format!(
+                "{}/binary_sensor/{}/connectivity/config",
+                DISCOVERY_PREFIX, node_id
+            ),
+            1,
+            config.to_string().as_bytes(),
+        )
+        .retained()]
+    }
+
+    /// One config message per discovery entity of the route, `state_topic` is
+    /// the topic the readings of this sensor are published on.
+    pub fn sensor_messages(
+        &self,
+        route: &SensorRoute,
+        id: &str,
+        state_topic: &str,
+    ) -> Vec<OutboxMessage> {
+        let node_id = sanitize(&self.client_id);
+        let kind = route_kind(route);
+        let device_id = format!("{}_{}_{}", node_id, sanitize(&kind), sanitize(id));
+        let device = json!({
+            "identifiers": [device_id],
+            "name": format!("{} {}", kind.replace('_', " "), id),
+            "via_device": node_id,
+        });
+
+        route
+            .discovery
+            .iter()
+            .map(|entity| {
+                let object_id = format!("{}_{}", device_id, sanitize(&entity.key));
+                let mut config = json!({
+                    "name": entity.name,
+                    "unique_id": object_id,
+                    "state_topic": state_topic,
+                    "value_template": value_template(&entity.key),
+                    "state_class": "measurement",
+                    "availability_topic": self.availability_topic,
+                    "device": device,
+                });
+
+                if let Some(device_class) = &entity.device_class {
+                    config["device_class"] = device_class.clone().into();
+                }
+                if let Some(unit) = &entity.unit {
+                    config["unit_of_measurement"] = unit.clone().into();
+                }
+                if entity.diagnostic {
+                    config["entity_category"] = "diagnostic".into();
+                }
+
+                OutboxMessage::new(
+                    &format!(
+                        "{}/sensor/{}/{}/config",
+                        DISCOVERY_PREFIX, node_id, object_id
+                    ),
+                    1,
+                    config.to_string().as_bytes(),
+                )
+                .retained()
+            })
+            .collect()
+    }
+
+    fn proxy_device(&self) -> Value {
+        json!({
+            "identifiers": [sanitize(&self.client_id)],
+            "name": self.client_id,
+            "model": "Sensor WiFi proxy",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        })
+    }
+}
+
+/// `/send_soil_moisture` gives `soil_moisture`.
+fn route_kind(route: &SensorRoute) -> String {
+    let path = route.path.trim_start_matches('/');
+
+    path.strip_prefix("send_").unwrap_or(path).replace('/', "_")
+}
+
+/// Subscript form, so keys that are not identifiers still work.
+fn value_template(key: &str) -> String {
+    let key = key.replace('\\', "\\\\").replace('\'', "\\'");
+
+    format!("{{{{ value_json['{}'] }}}}", key)
+}
+
+/// Home Assistant only accepts `[a-zA-Z0-9_-]` in node and object IDs.
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::{ConfigStore, MemoryConfigStore};
+
+    fn soil_route() -> SensorRoute {
+        SensorRoute::new("/send_soil_moisture", &["id", "moisture"], "soil/{id}")
+            .with_discovery(
+                DiscoveryEntity::new("moisture", "Moisture")
+                    .with_device_class("moisture")
+                    .with_unit("%"),
+            )
+            .with_discovery(DiscoveryEntity::new("rssi", "Signal").diagnostic())
+    }
+
+    fn config_of(message: &OutboxMessage) -> Value {
+        serde_json::from_slice(&message.payload).unwrap()
+    }
+
+    #[test]
+    fn sensor_topics_follow_the_discovery_layout() {
+        let discovery = HomeAssistantDiscovery::new("proxy-a1b2", "proxy-a1b2/availability");
+        let messages = discovery.sensor_messages(&soil_route(), "bed 1", "soil/bed 1");
+
+        let topics: Vec<_> = messages.iter().map(|m| m.topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/sensor/proxy-a1b2/proxy-a1b2_soil_moisture_bed_1_moisture/config",
+                "homeassistant/sensor/proxy-a1b2/proxy-a1b2_soil_moisture_bed_1_rssi/config",
+            ]
+        );
+        assert!(messages.iter().all(|m| m.retain && m.qos == 1));
+
+        let config = config_of(&messages[0]);
+        assert_eq!(config["state_topic"], "soil/bed 1");
+        assert_eq!(
+            config["unique_id"],
+            "proxy-a1b2_soil_moisture_bed_1_moisture"
+        );
+    }
+
+    #[test]
+    fn entity_fields_are_announced() {
+        let discovery = HomeAssistantDiscovery::new("proxy", "proxy/availability");
+        let messages = discovery.sensor_messages(&soil_route(), "1", "soil/1");
+
+        let moisture = config_of(&messages[0]);
+        assert_eq!(moisture["name"], "Moisture");
+        assert_eq!(moisture["device_class"], "moisture");
+        assert_eq!(moisture["unit_of_measurement"], "%");
+        assert_eq!(moisture["state_class"], "measurement");
+        assert!(moisture.get("entity_category").is_none());
+
+        let rssi = config_of(&messages[1]);
+        assert!(rssi.get("device_class").is_none());
+        assert!(rssi.get("unit_of_measurement").is_none());
+        assert_eq!(rssi["entity_category"], "diagnostic");
+    }
+
+    #[test]
+    fn sensor_devices_hang_off_the_proxy_device() {
+        let discovery = HomeAssistantDiscovery::new("proxy.1", "proxy.1/availability");
+        let sensor = config_of(&discovery.sensor_messages(&soil_route(), "7", "soil/7")[0]);
+        let proxy = config_of(&discovery.proxy_messages()[0]);
+
+        assert_eq!(sensor["device"]["via_device"], "proxy_1");
+        assert_eq!(proxy["device"]["identifiers"][0], "proxy_1");
+        assert_eq!(
+            sensor["device"]["identifiers"][0],
+            "proxy_1_soil_moisture_7"
+        );
+        assert_eq!(sensor["device"]["name"], "soil moisture 7");
+    }
+
+    #[test]
+    fn availability_topic_matches_the_last_will() {
+        let mut config = MemoryConfigStore::new();
+        config.set_mqtt_topic_prefix("home/").unwrap();
+        let topic = crate::mqtt::availability_topic(&config, "proxy");
+
+        let discovery = HomeAssistantDiscovery::new("proxy", &topic);
+        let proxy = config_of(&discovery.proxy_messages()[0]);
+        let sensor = config_of(&discovery.sensor_messages(&soil_route(), "1", "soil/1")[0]);
+
+        assert_eq!(
+            discovery.proxy_messages()[0].topic,
+            "homeassistant/binary_sensor/proxy/connectivity/config"
+        );
+        assert_eq!(proxy["state_topic"], "home/proxy/availability");
+        assert_eq!(proxy["payload_on"], crate::mqtt::AVAILABILITY_ONLINE);
+        assert_eq!(proxy["payload_off"], crate::mqtt::AVAILABILITY_OFFLINE);
+        assert_eq!(sensor["availability_topic"], "home/proxy/availability");
+    }
+
+    #[test]
+    fn value_template_subscripts_the_key() {
+        assert_eq!(value_template("moisture"), "{{ value_json['moisture'] }}");
+        assert_eq!(value_template("soil-1"), "{{ value_json['soil-1'] }}");
+        assert_eq!(value_template("it's"), "{{ value_json['it\\'s'] }}");
+        assert_eq!(value_template("a\\b"), "{{ value_json['a\\\\b'] }}");
+    }
+
+    #[test]
+    fn known_sensors_are_remembered_per_route() {
+        let mut discovery = HomeAssistantDiscovery::new("proxy", "proxy/availability");
+        let route = soil_route();
+        let other = SensorRoute::new("/send_temperature", &["id"], "temp/{id}");
+
+        discovery.remember(&route, "1");
+        assert!(discovery.is_known(&route, "1"));
+        assert!(!discovery.is_known(&route, "2"));
+        assert!(!discovery.is_known(&other, "1"));
+    }
+
+    #[test]
+    fn entities_round_trip_as_json() {
+        let entity = DiscoveryEntity::new("moisture", "Moisture")
+            .with_device_class("moisture")
+            .with_unit("%")
+            .diagnostic();
+
+        assert_eq!(DiscoveryEntity::from_json(&entity.to_json()), Ok(entity));
+        assert_eq!(
+            DiscoveryEntity::from_json(&json!({"name": "x"})),
+            Err(StringError("Discovery entity without key"))
+        );
+        assert_eq!(
+            DiscoveryEntity::from_json(&json!({"key": "x", "unit": 1})),
+            Err(StringError("Discovery entity fields must be strings"))
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::mqtt::MqttPublisher;
+use crate::outbox::{Outbox, OutboxMessage, SpillStorage};
+use crate::string_error::StringError;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ForwardStatus {
+    Published,
+    Queued,
+}
+
+/// Publishes readings right away when the broker is reachable, queues them in
+/// the outbox otherwise.
+pub struct Forwarder<P: MqttPublisher, S: SpillStorage> {
+    publisher: P,
+    outbox: Outbox<S>,
+}
+
+impl<P: MqttPublisher, S: SpillStorage> Forwarder<P, S> {
+    pub fn new(publisher: P, outbox: Outbox<S>) -> Self {
+        Self { publisher, outbox }
+    }
+
+    /// Gives the queue back, so it survives the publisher being replaced.
+    pub fn into_outbox(self) -> Outbox<S> {
+        self.outbox
+    }
+
+    pub fn publisher_mut(&mut self) -> &mut P {
+        &mut self.publisher
+    }
+
+    pub fn outbox_mut(&mut self) -> &mut Outbox<S> {
+        &mut self.outbox
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.publisher.is_connected()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.outbox.len()
+    }
+
+    pub fn forward(&mut self, message: OutboxMessage) -> Result<ForwardStatus, StringError> {
+        self.flush();
+
+        if self.outbox.is_empty() && self.publisher.is_connected() {
+            match self.publisher.publish(&message) {
+                Ok(_) => return Ok(ForwardStatus::Published),
+                Err(e) => log::warn!(
+                    "Failed to publish on {} (Error: {}), queue it.",
+                    message.topic,
+                    e
+                ),
+            }
+        }
+
+        self.outbox.push(message)?;
+        Ok(ForwardStatus::Queued)
+    }
+
+    pub fn flush(&mut self) {
+        if !self.publisher.is_connected() || self.outbox.is_empty() {
+            return;
+        }
+
+        let publisher = &mut self.publisher;
+        let result = self
+            .outbox
+            .drain(|message| publisher.publish(message).is_ok());
+
+        match result {
+            Ok(sent) if sent > 0 => log::info!("{} queued message(s) sent.", sent),
+            Ok(_) => (),
+            Err(e) => log::error!("Failed to drain outbox ({})", e),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+/// What a placeholder is replaced with.
+#[derive(Clone, Debug)]
+enum TemplateValue {
+    Text(String),
+    Json(Value),
+    /// Trusted markup, built by the caller from escaped or constant parts.
+    Markup(String),
+}
+
+/// Portal pages with `{NAME}` placeholders, replaced in a single pass so a
+/// value is never read as a placeholder. The context picks the escaping:
+///
+/// - `{NAME}`: HTML text or quoted attribute value,
+/// - `{NAME|js}`: inside a quoted JavaScript string,
+/// - `{NAME|json}`: a JavaScript expression, the value as JSON.
+///
+/// Placeholders without a value are left as is.
+#[derive(Clone, Debug)]
+pub struct HtmlTemplate<'a> {
+    source: &'a str,
+    values: HashMap<String, TemplateValue>,
+}
+
+impl<'a> HtmlTemplate<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn text(&mut self, name: &str, value: &str) -> &mut Self {
+        self.values
+            .insert(name.to_string(), TemplateValue::Text(value.to_string()));
+        self
+    }
+
+    /// Pretty printed in `{NAME}`, e.g. a textarea.
+    pub fn json(&mut self, name: &str, value: Value) -> &mut Self {
+        self.values
+            .insert(name.to_string(), TemplateValue::Json(value));
+        self
+    }
+
+    /// `checked` or nothing, for a checkbox.
+    pub fn checked(&mut self, name: &str, checked: bool) -> &mut Self {
+        self.text(name, if checked { "checked" } else { "" })
+    }
+
+    /// Inserted as is, in every context.
+    pub fn markup(&mut self, name: &str, markup: String) -> &mut Self {
+        self.values
+            .insert(name.to_string(), TemplateValue::Markup(markup));
+        self
+    }
+
+    pub fn render(&self) -> String {
+        let mut result = String::with_capacity(self.source.len());
+        let mut rest = self.source;
+
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            match placeholder(rest).and_then(|(len, name, context)| {
+                self.values
+                    .get(name)
+                    .map(|value| (len, render_value(value, context)))
+            }) {
+                Some((len, rendered)) => {
+                    result.push_str(&rendered);
+                    rest = &rest[len..];
+                }
+                None => {
+                    result.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        result.push_str(rest);
+        result
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Context {
+    Html,
+    JsString,
+    Json,
+}
+
+/// Length, name and context of the placeholder `s` starts with. Names are
+/// uppercase so the CSS and script blocks never match.
+fn placeholder(s: &str) -> Option<(usize, &str, Context)> {
+    let end = s.find('}')?;
+    let inner = &s[1..end];
+    let (name, context) = match inner.split_once('|') {
+        None => (inner, Context::Html),
+        Some((name, "js")) => (name, Context::JsString),
+        Some((name, "json")) => (name, Context::Json),
+        Some(_) => return None,
+    };
+
+    let is_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_');
+
+    is_name.then_some((end + 1, name, context))
+}
+
+fn render_value(value: &TemplateValue, context: Context) -> String {
+    match (value, context) {
+        (TemplateValue::Markup(markup), _) => markup.clone(),
+        (TemplateValue::Text(text), Context::Html) => escape_html(text),
+        (TemplateValue::Text(text), Context::JsString) => escape_js_string(text),
+        (TemplateValue::Text(text), Context::Json) => to_script_json(&Value::from(text.as_str())),
+        (TemplateValue::Json(json), Context::Html) => {
+            escape_html(&serde_json::to_string_pretty(json).unwrap_or_default())
+        }
+        (TemplateValue::Json(json), Context::JsString) => escape_js_string(&json.to_string()),
+        (TemplateValue::Json(json), Context::Json) => to_script_json(json),
+    }
+}
+
+/// For text and quoted attribute values alike.
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// For a single or double quoted string in a script block: the quotes and
+/// backslashes cannot end the string, and `<`, `>` and `&` cannot end the
+/// script block.
+pub fn escape_js_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\'' => escaped.push_str("\\'"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// JSON that can sit in a script block. `<`, `>` and `&` only appear in
+/// strings, where their `\u` escape means the same.
+pub fn to_script_json(value: &Value) -> String {
+    let json = value.to_string();
+    let mut escaped = String::with_capacity(json.len());
+
+    for c in json.chars() {
+        match c {
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE_SSIDS: [&str; 6] = [
+        "Quote\" onmouseover=\"alert(1)",
+        "It's'); alert(1); ('",
+        "</script><script>alert(1)</script>",
+        "Line\u{2028}Separator\u{2029}",
+        "{APSSID}",
+        "Back\\slash & <b>",
+    ];
+
+    fn unescape_html(s: &str) -> String {
+        s.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&amp;", "&")
+    }
+
+    /// Decodes the body of a quoted JavaScript string, panics on a quote or
+    /// a line break that would end it.
+    fn unescape_js_string(s: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' | '\'' => panic!("Unescaped quote in {:?}", s),
+                '\u{2028}' | '\u{2029}' | '\n' | '\r' => panic!("Line break in {:?}", s),
+                '\\' => match chars.next().unwrap() {
+                    'n' => unescaped.push('\n'),
+                    'r' => unescaped.push('\r'),
+                    't' => unescaped.push('\t'),
+                    'u' => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        let code = u32::from_str_radix(&code, 16).unwrap();
+                        unescaped.push(char::from_u32(code).unwrap());
+                    }
+                    c => unescaped.push(c),
+                },
+                c => unescaped.push(c),
+            }
+        }
+
+        unescaped
+    }
+
+    fn render_ssid(source: &str, ssid: &str) -> String {
+        HtmlTemplate::new(source)
+            .text("STASSID", ssid)
+            .text("APSSID", "Proxy")
+            .render()
+    }
+
+    /// No closing tag, and `{APSSID}` in a value is not expanded.
+    fn assert_inert(rendered: &str) {
+        assert!(!rendered.contains("</script"));
+        assert!(!rendered.contains("Proxy"));
+    }
+
+    /// The line separators are plain text in HTML, but end a script line.
+    fn assert_no_line_separator(rendered: &str) {
+        assert!(!rendered.contains(['\u{2028}', '\u{2029}']));
+    }
+
+    #[test]
+    fn text_context_is_escaped_once() {
+        for ssid in HOSTILE_SSIDS {
+            let rendered = render_ssid("{STASSID}", ssid);
+
+            assert_inert(&rendered);
+            assert!(!rendered.contains(['<', '>', '"', '\'']));
+            assert_eq!(unescape_html(&rendered), ssid);
+        }
+    }
+
+    #[test]
+    fn js_context_stays_in_the_string() {
+        for ssid in HOSTILE_SSIDS {
+            let rendered = render_ssid("{STASSID|js}", ssid);
+
+            assert_inert(&rendered);
+            assert_no_line_separator(&rendered);
+            assert!(!rendered.contains(['<', '>', '&']));
+            assert_eq!(unescape_js_string(&rendered), ssid);
+        }
+    }
+
+    #[test]
+    fn json_context_is_a_single_value() {
+        for ssid in HOSTILE_SSIDS {
+            let rendered = render_ssid("{STASSID|json}", ssid);
+
+            assert_inert(&rendered);
+            assert_no_line_separator(&rendered);
+            assert!(!rendered.contains(['<', '>', '&']));
+            assert_eq!(
+                serde_json::from_str::<Value>(&rendered).unwrap(),
+                Value::from(ssid)
+            );
+        }
+    }
+
+    #[test]
+    fn json_values_are_escaped_in_every_context() {
+        let list = Value::from(HOSTILE_SSIDS.to_vec());
+        // Pretty printed in HTML, so last.
+        let rendered = HtmlTemplate::new("{AP_LIST|json}\n\"{AP_LIST|js}\"\n{AP_LIST}")
+            .json("AP_LIST", list.clone())
+            .text("APSSID", "Proxy")
+            .render();
+        let [script, js, html] = rendered.splitn(3, '\n').collect::<Vec<_>>()[..] else {
+            panic!("Line break in {:?}", rendered);
+        };
+
+        assert_inert(&rendered);
+        assert_no_line_separator(script);
+        assert_eq!(serde_json::from_str::<Value>(script).unwrap(), list);
+
+        let js = unescape_js_string(&js[1..js.len() - 1]);
+        assert_eq!(serde_json::from_str::<Value>(&js).unwrap(), list);
+
+        assert!(!html.contains(['<', '>', '"', '\'']));
+        assert_eq!(
+            serde_json::from_str::<Value>(&unescape_html(html)).unwrap(),
+            list
+        );
+    }
+
+    #[test]
+    fn placeholders_are_replaced_in_a_single_pass() {
+        let rendered = HtmlTemplate::new("{STASSID} {APSSID} {MISSING} {lower} {STASSID|css}")
+            .text("STASSID", "{APSSID}")
+            .text("APSSID", "Proxy")
+            .render();
+
+        assert_eq!(rendered, "{APSSID} Proxy {MISSING} {lower} {STASSID|css}");
+    }
+
+    #[test]
+    fn markup_is_inserted_as_is() {
+        let rendered = HtmlTemplate::new("<p>{FIELD}</p>{CHECKED}")
+            .markup("FIELD", "<b>bold</b>".to_string())
+            .checked("CHECKED", true)
+            .render();
+
+        assert_eq!(rendered, "<p><b>bold</b></p>checked");
+    }
+}
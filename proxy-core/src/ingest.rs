@@ -0,0 +1,141 @@
+use serde_json::{json, Map, Value};
+
+use crate::discovery::HomeAssistantDiscovery;
+use crate::forwarder::{ForwardStatus, Forwarder};
+use crate::mode::Mode;
+use crate::mqtt::MqttPublisher;
+use crate::outbox::SpillStorage;
+use crate::sensor_allowlist::SensorAllowlist;
+use crate::sensor_auth::{ReadingHeaders, SensorAuth};
+use crate::sensor_route::SensorRoute;
+use crate::wifi_status::WifiStatus;
+
+pub const MAX_JSON_BODY_LEN: usize = 255;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct IngestResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl IngestResponse {
+    pub fn new(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            body: body.to_string(),
+        }
+    }
+}
+
+pub fn check_content_length(len_body: usize, max_len: usize) -> Result<(), &'static str> {
+    if len_body == 0 {
+        Err("No body or no content-length")
+    } else if len_body > max_len {
+        Err("Content-length too long.")
+    } else {
+        Ok(())
+    }
+}
+
+pub fn parse_json_object(body: &str) -> Result<Map<String, Value>, &'static str> {
+    match serde_json::from_str::<Value>(body) {
+        Ok(json_value) => match json_value {
+            Value::Object(obj) => Ok(obj),
+            _ => Err("Invalid JSON (no object)"),
+        },
+        Err(e) => {
+            log::error!("Invalid JSON (Error: {}).", e);
+            Err("Invalid JSON")
+        }
+    }
+}
+
+/// Unauthenticated readings are refused with 401, see `SensorAuth`, the ones
+/// of sensors not allowed with 403, see `SensorAllowlist`.
+pub fn handle_reading<P: MqttPublisher, S: SpillStorage>(
+    route: &SensorRoute,
+    body: &str,
+    headers: &ReadingHeaders,
+    sensor_auth: &mut SensorAuth,
+    allowlist: &mut SensorAllowlist,
+    forwarder: &mut Forwarder<P, S>,
+    discovery: Option<&mut HomeAssistantDiscovery>,
+) -> IngestResponse {
+    let json = match parse_json_object(body) {
+        Ok(json) => json,
+        Err(e) => return IngestResponse::new(400, e),
+    };
+
+    let id = json.get("id").and_then(Value::as_str);
+    if let Err(e) = sensor_auth.check(id, body, headers) {
+        log::warn!("Reading of {} refused ({})", id.unwrap_or("?"), e);
+        return IngestResponse::new(401, e);
+    }
+
+    if let Err(e) = allowlist.check(id, &route.path) {
+        log::warn!("Reading of {} refused ({})", id.unwrap_or("?"), e);
+        return IngestResponse::new(403, e);
+    }
+
+    let message = match route.build_message(&json) {
+        Ok(message) => message,
+        Err(e) => return IngestResponse::new(400, e),
+    };
+
+    if let (Some(discovery), Some(id)) = (discovery, json["id"].as_str()) {
+        announce_sensor(discovery, route, id, &message.topic, forwarder);
+    }
+
+    match forwarder.forward(message) {
+        Ok(ForwardStatus::Published) => IngestResponse::new(200, ""),
+        Ok(ForwardStatus::Queued) => IngestResponse::new(202, ""),
+        Err(e) => {
+            log::error!("Failed to forward reading ({})", e);
+            IngestResponse::new(503, e.0)
+        }
+    }
+}
+
+/// Queues the discovery config ahead of the first reading of a sensor. On
+/// failure the sensor stays unknown and is announced again next time.
+fn announce_sensor<P: MqttPublisher, S: SpillStorage>(
+    discovery: &mut HomeAssistantDiscovery,
+    route: &SensorRoute,
+    id: &str,
+    state_topic: &str,
+    forwarder: &mut Forwarder<P, S>,
+) {
+    if discovery.is_known(route, id) {
+        return;
+    }
+
+    let result = discovery
+        .sensor_messages(route, id, state_topic)
+        .into_iter()
+        .try_for_each(|message| forwarder.forward(message).map(|_| ()));
+
+    match result {
+        Ok(()) => discovery.remember(route, id),
+        Err(e) => log::warn!("Failed to announce sensor {} ({})", id, e),
+    }
+}
+
+pub fn status_json<P: MqttPublisher, S: SpillStorage>(
+    mode: Mode,
+    wifi: &impl WifiStatus,
+    forwarder: &Forwarder<P, S>,
+) -> String {
+    json!({
+        "mode": mode.as_str(),
+        "wifi": {
+            "connected": wifi.is_sta_connected(),
+            "link": wifi.sta_link_state().as_str(),
+            "ip": wifi.sta_ip().map(|ip| ip.to_string()),
+        },
+        "mqtt": {
+            "connected": forwarder.is_connected(),
+            "queued": forwarder.queued(),
+        }
+    })
+    .to_string()
+}
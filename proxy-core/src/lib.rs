@@ -0,0 +1,33 @@
+//! Platform independent logic of the sensor WiFi proxy.
+//!
+//! Everything that touches the hardware (NVS, MQTT client, WiFi driver) is
+//! hidden behind the traits of this crate, the ESP-IDF implementations live in
+//! the firmware crate and the host ones in `proxy-sim`.
+
+pub mod admin_auth;
+pub mod ap_clients;
+pub mod auth_mode;
+pub mod boot_guard;
+pub mod config;
+pub mod config_journal;
+pub mod dhcp_server;
+pub mod discovery;
+pub mod forwarder;
+pub mod hex;
+pub mod html_template;
+pub mod ingest;
+pub mod link_supervisor;
+pub mod migration;
+pub mod mode;
+pub mod mqtt;
+pub mod outbox;
+pub mod port_forward;
+pub mod proxy_config;
+pub mod regulatory;
+pub mod sensor_allowlist;
+pub mod sensor_auth;
+pub mod sensor_route;
+pub mod sta_ip;
+pub mod sta_network;
+pub mod string_error;
+pub mod wifi_status;
@@ -0,0 +1,259 @@
+//! Station link supervision.
+//!
+//! Like every state machine of this crate, it does not read a clock: `now`
+//! arguments are uptimes, the time elapsed since boot, given by the caller.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum LinkState {
+    /// A connection attempt is in progress.
+    Connecting,
+    Connected,
+    /// Waiting before the next attempt.
+    Backoff,
+}
+
+impl LinkState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkState::Connecting => "connecting",
+            LinkState::Connected => "connected",
+            LinkState::Backoff => "backoff",
+        }
+    }
+}
+
+/// Doubles the delay after each failure, up to `max`.
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl ExponentialBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+        }
+    }
+
+    /// Delay to wait before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Connecting { since: Duration },
+    Connected,
+    Backoff { until: Duration },
+}
+
+/// Decides when the station must reconnect. It does not touch the driver:
+/// the caller reports link events and asks `poll` whether to start an attempt.
+#[derive(Clone, Debug)]
+pub struct LinkSupervisor {
+    state: State,
+    backoff: ExponentialBackoff,
+    attempt_timeout: Duration,
+    failures: u32,
+}
+
+impl LinkSupervisor {
+    /// Starts in `Connecting`, the first attempt being made by the caller.
+    pub fn new(backoff: ExponentialBackoff, attempt_timeout: Duration, now: Duration) -> Self {
+        Self {
+            state: State::Connecting { since: now },
+            backoff,
+            attempt_timeout,
+            failures: 0,
+        }
+    }
+
+    /// Starts in `Backoff` with no delay, the first `poll` asks for an attempt.
+    pub fn new_idle(backoff: ExponentialBackoff, attempt_timeout: Duration, now: Duration) -> Self {
+        Self {
+            state: State::Backoff { until: now },
+            backoff,
+            attempt_timeout,
+            failures: 0,
+        }
+    }
+
+    pub fn state(&self) -> LinkState {
+        match self.state {
+            State::Connecting { .. } => LinkState::Connecting,
+            State::Connected => LinkState::Connected,
+            State::Backoff { .. } => LinkState::Backoff,
+        }
+    }
+
+    /// Failed attempts since the last successful connection.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn on_link_up(&mut self) {
+        if !matches!(self.state, State::Connected) {
+            log::info!("Station link up after {} failed attempt(s).", self.failures);
+        }
+
+        self.state = State::Connected;
+        self.failures = 0;
+        self.backoff.reset();
+    }
+
+    pub fn on_link_down(&mut self, now: Duration) {
+        match self.state {
+            State::Connected => {
+                log::warn!("Station link lost.");
+                self.wait(now);
+            }
+            State::Connecting { .. } => {
+                self.failures += 1;
+                self.wait(now);
+            }
+            State::Backoff { .. } => (),
+        }
+    }
+
+    /// Returns true when a connection attempt must be started now.
+    pub fn poll(&mut self, now: Duration) -> bool {
+        match self.state {
+            State::Backoff { until } if now >= until => {
+                self.state = State::Connecting { since: now };
+                true
+            }
+            State::Connecting { since } if now.saturating_sub(since) >= self.attempt_timeout => {
+                self.failures += 1;
+                self.wait(now);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn wait(&mut self, now: Duration) {
+        let delay = self.backoff.next_delay();
+        log::info!("Reconnect station in {} s.", delay.as_secs());
+
+        self.state = State::Backoff { until: now + delay };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn backoff() -> ExponentialBackoff {
+        ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(8))
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = backoff();
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay().as_secs()).collect();
+
+        assert_eq!(delays, [1, 2, 4, 8, 8, 8]);
+    }
+
+    #[test]
+    fn backoff_resets() {
+        let mut backoff = backoff();
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn failed_attempts_back_off_then_retry() {
+        let mut link = LinkSupervisor::new(backoff(), TIMEOUT, Duration::from_secs(0));
+
+        link.on_link_down(Duration::from_secs(1));
+        assert_eq!(link.state(), LinkState::Backoff);
+        assert_eq!(link.failures(), 1);
+
+        // 1 s delay.
+        assert!(!link.poll(Duration::from_secs(1)));
+        assert!(link.poll(Duration::from_secs(2)));
+        assert_eq!(link.state(), LinkState::Connecting);
+
+        // 2 s delay.
+        link.on_link_down(Duration::from_secs(3));
+        assert!(!link.poll(Duration::from_secs(4)));
+        assert!(link.poll(Duration::from_secs(5)));
+        assert_eq!(link.failures(), 2);
+    }
+
+    #[test]
+    fn attempt_times_out() {
+        let mut link = LinkSupervisor::new(backoff(), TIMEOUT, Duration::from_secs(0));
+
+        assert!(!link.poll(Duration::from_secs(9)));
+        assert_eq!(link.state(), LinkState::Connecting);
+
+        assert!(!link.poll(Duration::from_secs(10)));
+        assert_eq!(link.state(), LinkState::Backoff);
+        assert_eq!(link.failures(), 1);
+
+        assert!(link.poll(Duration::from_secs(11)));
+        assert_eq!(link.state(), LinkState::Connecting);
+    }
+
+    #[test]
+    fn success_resets_failures_and_backoff() {
+        let mut link = LinkSupervisor::new(backoff(), TIMEOUT, Duration::from_secs(0));
+
+        for n in 0..4 {
+            link.on_link_down(Duration::from_secs(n * 100));
+            assert!(link.poll(Duration::from_secs(n * 100 + 50)));
+        }
+        assert_eq!(link.failures(), 4);
+
+        link.on_link_up();
+        assert_eq!(link.state(), LinkState::Connected);
+        assert_eq!(link.failures(), 0);
+        assert!(!link.poll(Duration::from_secs(1000)));
+
+        // Link lost: the first delay is the initial one again, and the
+        // failure count is not increased.
+        link.on_link_down(Duration::from_secs(1000));
+        assert_eq!(link.failures(), 0);
+        assert!(!link.poll(Duration::from_secs(1000)));
+        assert!(link.poll(Duration::from_secs(1001)));
+    }
+
+    #[test]
+    fn idle_start_asks_for_an_attempt() {
+        let mut link = LinkSupervisor::new_idle(backoff(), TIMEOUT, Duration::from_secs(5));
+
+        assert_eq!(link.state(), LinkState::Backoff);
+        assert!(link.poll(Duration::from_secs(5)));
+        assert_eq!(link.state(), LinkState::Connecting);
+    }
+
+    #[test]
+    fn link_down_while_waiting_is_ignored() {
+        let mut link = LinkSupervisor::new(backoff(), TIMEOUT, Duration::from_secs(0));
+        link.on_link_down(Duration::from_secs(0));
+        link.on_link_down(Duration::from_secs(0));
+
+        assert_eq!(link.failures(), 1);
+        assert!(link.poll(Duration::from_secs(1)));
+    }
+}
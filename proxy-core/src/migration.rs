@@ -0,0 +1,353 @@
+use crate::config::{
+    ConfigStore, KEY_AP_PASSPHRASE, KEY_AP_SSID, KEY_MQTT_SERVER, KEY_SENSOR_ROUTES,
+    KEY_STA_PASSPHRASE, KEY_STA_SSID,
+};
+use crate::sensor_allowlist::MAX_ALLOWED_SENSORS;
+use crate::sensor_route::default_routes;
+use crate::sta_network::StaNetwork;
+
+pub const KEY_SCHEMA_VERSION: &str = "SCHEMAVER";
+
+/// Version written by this firmware. Bump it and add a step in
+/// `apply_migration` for every change of key or encoding.
+pub const SCHEMA_VERSION: u8 = 4;
+
+/// Strings used to be padded up to their max length with this character.
+const LEGACY_PAD_CHAR: char = 0x03 as char;
+const LEGACY_PADDED_KEYS: &[&str] = &[
+    KEY_STA_SSID,
+    KEY_STA_PASSPHRASE,
+    KEY_AP_SSID,
+    KEY_AP_PASSPHRASE,
+    KEY_MQTT_SERVER,
+];
+
+/// Brings the store up to `SCHEMA_VERSION`, one step at a time. The version is
+/// saved after each step, so an interrupted migration resumes where it stopped.
+/// Returns the version the store ends up at.
+pub fn migrate<S: ConfigStore>(store: &mut S) -> Result<u8, S::Error> {
+    let mut version = schema_version(store);
+
+    if version > SCHEMA_VERSION {
+        log::warn!(
+            "Configuration schema v{} is newer than supported v{}, leave it untouched.",
+            version,
+            SCHEMA_VERSION
+        );
+        return Ok(version);
+    }
+
+    while version < SCHEMA_VERSION {
+        log::info!(
+            "Migrate configuration schema v{} -> v{}",
+            version,
+            version + 1
+        );
+
+        apply_migration(store, version)?;
+        version += 1;
+        store.save_u8(KEY_SCHEMA_VERSION, version)?;
+    }
+
+    Ok(version)
+}
+
+pub fn schema_version(store: &impl ConfigStore) -> u8 {
+    store.load_u8(KEY_SCHEMA_VERSION).unwrap_or(0)
+}
+
+fn apply_migration<S: ConfigStore>(store: &mut S, from_version: u8) -> Result<(), S::Error> {
+    match from_version {
+        0 => unpad_legacy_strings(store),
+        1 => add_default_discovery(store),
+        2 => move_station_to_networks(store),
+        3 => seed_sensor_allowlist(store),
+        _ => Ok(()),
+    }
+}
+
+/// v0 -> v1: padded strings become exact-length values.
+fn unpad_legacy_strings<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    for &key in LEGACY_PADDED_KEYS {
+        if let Some(value) = store.load_str(key) {
+            let (unpadded, _) = value.split_once(LEGACY_PAD_CHAR).unwrap_or((&value, ""));
+
+            if unpadded.len() != value.len() {
+                store.save_str(key, unpadded)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// v1 -> v2: stored routes predate Home Assistant discovery. Routes matching a
+/// default one by path get its entities, as long as they still publish the
+/// keys the entities read.
+fn add_default_discovery<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    if store
+        .load_blob(KEY_SENSOR_ROUTES)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        return Ok(());
+    }
+
+    let defaults = default_routes();
+    let mut routes = store.get_sensor_routes();
+    let mut changed = false;
+
+    for route in routes.iter_mut().filter(|r| r.discovery.is_empty()) {
+        let Some(default) = defaults.iter().find(|d| d.path == route.path) else {
+            continue;
+        };
+
+        let mut candidate = route.clone();
+        candidate.discovery = default.discovery.clone();
+
+        if candidate.is_valid() {
+            *route = candidate;
+            changed = true;
+        }
+    }
+
+    if changed {
+        store.set_sensor_routes(&routes)?;
+    }
+
+    Ok(())
+}
+
+/// v2 -> v3: the single station network becomes the first of the ranked list.
+/// The old keys are emptied so the passphrase is not left behind.
+fn move_station_to_networks<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    let Some(ssid) = store.load_str(KEY_STA_SSID) else {
+        return Ok(());
+    };
+    let passphrase = store.load_str(KEY_STA_PASSPHRASE).unwrap_or_default();
+
+    if !ssid.is_empty() && store.get_sta_networks().is_empty() {
+        store.set_sta_networks(&[StaNetwork::new(&ssid, &passphrase)])?;
+    }
+
+    store.save_str(KEY_STA_SSID, "")?;
+    store.save_str(KEY_STA_PASSPHRASE, "")
+}
+
+/// v3 -> v4: an empty sensor allowlist used to allow every sensor, it now
+/// refuses them. The sensors with a credential are allowed, the others were
+/// refused by the credential check already. Without credentials, every sensor
+/// stays allowed until "accept every sensor" is unchecked in the portal.
+fn seed_sensor_allowlist<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    if !store.get_sensor_allowlist().is_empty() {
+        return Ok(());
+    }
+
+    let ids: Vec<String> = store
+        .get_sensor_credentials()
+        .into_iter()
+        .map(|credential| credential.id)
+        .take(MAX_ALLOWED_SENSORS)
+        .collect();
+
+    if ids.is_empty() {
+        log::info!("No sensor credentials, every sensor stays allowed.");
+        return store.set_sensor_allow_all(true);
+    }
+
+    store.set_sensor_allowlist(&ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::{FailingStore, MemoryConfigStore, KEY_SENSOR_ALLOWLIST, KEY_STA_NETWORKS};
+    use crate::forwarder::Forwarder;
+    use crate::ingest::handle_reading;
+    use crate::mqtt::OfflinePublisher;
+    use crate::outbox::{MemorySpillStorage, Outbox};
+    use crate::sensor_allowlist::SensorAllowlist;
+    use crate::sensor_auth::{ReadingHeaders, SensorAuth, SensorCredential};
+    use crate::sensor_route::{routes_to_json, SensorRoute};
+
+    fn store_at(version: u8) -> MemoryConfigStore {
+        let mut store = MemoryConfigStore::new();
+        store.save_u8(KEY_SCHEMA_VERSION, version).unwrap();
+        store
+    }
+
+    #[test]
+    fn v0_padded_strings_become_exact_length() {
+        let mut store = MemoryConfigStore::new();
+        store
+            .save_str(KEY_AP_SSID, "Sensors\u{3}\u{3}\u{3}")
+            .unwrap();
+        store.save_str(KEY_MQTT_SERVER, "broker\u{3}").unwrap();
+        store.save_str(KEY_AP_PASSPHRASE, "unpadded").unwrap();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.get_ap_ssid(), "Sensors");
+        assert_eq!(store.get_mqtt_server(), "broker");
+        assert_eq!(store.get_ap_passphrase(), "unpadded");
+    }
+
+    #[test]
+    fn v1_routes_get_default_discovery() {
+        let mut store = store_at(1);
+        let mut routes = default_routes();
+        for route in &mut routes {
+            route.discovery.clear();
+        }
+        store.set_sensor_routes(&routes).unwrap();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.get_sensor_routes(), default_routes());
+    }
+
+    #[test]
+    fn v1_without_stored_routes_keeps_defaults() {
+        let mut store = store_at(1);
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert!(store.load_blob(KEY_SENSOR_ROUTES).is_none());
+    }
+
+    #[test]
+    fn v2_station_becomes_first_network() {
+        let mut store = store_at(2);
+        store.save_str(KEY_STA_SSID, "home").unwrap();
+        store.save_str(KEY_STA_PASSPHRASE, "passphrase").unwrap();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(
+            store.get_sta_networks(),
+            [StaNetwork::new("home", "passphrase")]
+        );
+        assert_eq!(store.load_str(KEY_STA_SSID).as_deref(), Some(""));
+        assert_eq!(store.load_str(KEY_STA_PASSPHRASE).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn v3_allowlist_is_seeded_with_credential_ids() {
+        let mut store = store_at(3);
+        store
+            .set_sensor_credentials(&[
+                SensorCredential::new("garden", "0123456789abcdef"),
+                SensorCredential::new("attic", "fedcba9876543210"),
+            ])
+            .unwrap();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.get_sensor_allowlist(), ["garden", "attic"]);
+    }
+
+    #[test]
+    fn v3_allowlist_is_kept_when_filled() {
+        let mut store = store_at(3);
+        store
+            .set_sensor_credentials(&[SensorCredential::new("garden", "0123456789abcdef")])
+            .unwrap();
+        store.set_sensor_allowlist(&["attic".to_string()]).unwrap();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.get_sensor_allowlist(), ["attic"]);
+    }
+
+    #[test]
+    fn v3_without_credentials_allows_every_sensor() {
+        let mut store = store_at(3);
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert!(store.load_blob(KEY_SENSOR_ALLOWLIST).is_none());
+        assert!(store.get_sensor_allow_all());
+    }
+
+    #[test]
+    fn upgraded_store_still_forwards_readings() {
+        let mut store = store_at(3);
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+
+        let route = SensorRoute::new("/garden", &["t"], "sensors/garden");
+        let mut auth = SensorAuth::new(store.get_sensor_credentials(), &HashMap::new());
+        let mut allowlist =
+            SensorAllowlist::new(store.get_sensor_allowlist(), store.get_sensor_allow_all());
+        let mut forwarder =
+            Forwarder::new(OfflinePublisher, Outbox::new(4, MemorySpillStorage::new(4)));
+
+        let response = handle_reading(
+            &route,
+            r#"{"id":"garden","t":21.5}"#,
+            &ReadingHeaders::default(),
+            &mut auth,
+            &mut allowlist,
+            &mut forwarder,
+            None,
+        );
+
+        assert_eq!(response.status, 202);
+        assert_eq!(forwarder.queued(), 1);
+    }
+
+    #[test]
+    fn interrupted_step_resumes() {
+        let mut store = store_at(2);
+        store.save_str(KEY_STA_SSID, "home").unwrap();
+        store.save_str(KEY_STA_PASSPHRASE, "passphrase").unwrap();
+
+        // The networks are written, then the power is lost before the old
+        // keys are emptied.
+        let mut failing = FailingStore::new(store, 1);
+        assert!(migrate(&mut failing).is_err());
+        assert_eq!(schema_version(&failing), 2);
+        assert!(failing.store.load_blob(KEY_STA_NETWORKS).is_some());
+
+        let mut store = failing.store;
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(
+            store.get_sta_networks(),
+            [StaNetwork::new("home", "passphrase")]
+        );
+        assert_eq!(store.load_str(KEY_STA_PASSPHRASE).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn interrupted_migration_keeps_completed_steps() {
+        let mut store = MemoryConfigStore::new();
+        store.save_str(KEY_AP_SSID, "Sensors\u{3}").unwrap();
+
+        // v0 -> v1 and its version are written, v1 -> v2 has nothing to do,
+        // the v2 version write fails.
+        let mut failing = FailingStore::new(store, 2);
+        assert!(migrate(&mut failing).is_err());
+        assert_eq!(schema_version(&failing), 1);
+
+        let mut store = failing.store;
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.get_ap_ssid(), "Sensors");
+    }
+
+    #[test]
+    fn newer_schema_is_left_untouched() {
+        let mut store = store_at(SCHEMA_VERSION + 1);
+        store.save_str(KEY_STA_SSID, "home").unwrap();
+        store
+            .save_blob(KEY_SENSOR_ROUTES, routes_to_json(&[]).as_bytes())
+            .unwrap();
+        let before = store.as_json().clone();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION + 1));
+        assert_eq!(store.as_json(), &before);
+    }
+
+    #[test]
+    fn current_schema_is_a_no_op() {
+        let mut store = store_at(SCHEMA_VERSION);
+        store.save_str(KEY_STA_SSID, "home").unwrap();
+        let before = store.as_json().clone();
+
+        assert_eq!(migrate(&mut store), Ok(SCHEMA_VERSION));
+        assert_eq!(store.as_json(), &before);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::ingest::IngestResponse;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Mode {
+    /// Nothing started yet.
+    Booting,
+    /// Forwarding readings, WiFi and MQTT up.
+    Proxy,
+    /// Configuration portal only.
+    Config,
+    /// Proxy services started but WiFi or MQTT is down.
+    Degraded,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Booting => "booting",
+            Mode::Proxy => "proxy",
+            Mode::Config => "config",
+            Mode::Degraded => "degraded",
+        }
+    }
+
+    /// Only the modes that can be requested are parsed.
+    pub fn from_request(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "proxy" => Some(Mode::Proxy),
+            "config" => Some(Mode::Config),
+            _ => None,
+        }
+    }
+}
+
+/// What requested a mode.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Trigger {
+    /// Decided at boot.
+    Boot,
+    /// Settings button, at boot or later.
+    Button,
+    /// MQTT command or the portal.
+    Remote,
+    /// Failed proxy mode start-ups, see `boot_guard`.
+    Fallback,
+}
+
+/// Current runtime mode and the switch requested by the button, an MQTT
+/// command or the REST API. The switch itself is made by the main loop, which
+/// takes the request, tears the running services down and starts the others.
+///
+/// The sensor enrolment window is requested the same way, and opened by the
+/// proxy services.
+#[derive(Clone, Debug)]
+pub struct ModeMachine {
+    mode: Mode,
+    /// What requested the running mode.
+    trigger: Trigger,
+    requested: Option<(Mode, Trigger)>,
+    is_enrolment_requested: bool,
+}
+
+impl Default for ModeMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModeMachine {
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Booting,
+            trigger: Trigger::Boot,
+            requested: None,
+            is_enrolment_requested: false,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+
+    /// Request from the MQTT command or the portal.
+    pub fn request(&mut self, target: Mode) -> Result<(), &'static str> {
+        self.request_by(target, Trigger::Remote)
+    }
+
+    /// Proxy mode can be requested from degraded mode to restart the services,
+    /// a request for the running mode is ignored.
+    pub fn request_by(&mut self, target: Mode, trigger: Trigger) -> Result<(), &'static str> {
+        match (self.mode, target) {
+            (_, Mode::Booting | Mode::Degraded) => Err("This mode cannot be requested"),
+            (Mode::Degraded, Mode::Config | Mode::Proxy) => {
+                self.requested = Some((target, trigger));
+                Ok(())
+            }
+            (current, target) if current == target => Ok(()),
+            (_, target) => {
+                self.requested = Some((target, trigger));
+                Ok(())
+            }
+        }
+    }
+
+    /// Opposite of the running mode, for a single button gesture.
+    pub fn request_toggle(&mut self) {
+        let target = match self.mode {
+            Mode::Config => Mode::Proxy,
+            _ => Mode::Config,
+        };
+
+        let _ = self.request_by(target, Trigger::Button);
+    }
+
+    /// The request becomes the trigger of the mode about to be entered.
+    pub fn take_request(&mut self) -> Option<Mode> {
+        let (target, trigger) = self.requested.take()?;
+        self.trigger = trigger;
+        Some(target)
+    }
+
+    /// From the configuration mode, proxy mode is requested as well.
+    pub fn request_enrolment(&mut self) {
+        self.is_enrolment_requested = true;
+
+        if self.mode == Mode::Config {
+            let _ = self.request(Mode::Proxy);
+        }
+    }
+
+    pub fn take_enrolment_request(&mut self) -> bool {
+        std::mem::take(&mut self.is_enrolment_requested)
+    }
+
+    /// Called once the services of `mode` are running.
+    pub fn entered(&mut self, mode: Mode) {
+        log::info!("Mode {} -> {}", self.mode.as_str(), mode.as_str());
+        self.mode = mode;
+    }
+
+    /// Moves between proxy and degraded mode following the links health.
+    pub fn on_health(&mut self, healthy: bool) {
+        match (self.mode, healthy) {
+            (Mode::Proxy, false) => self.entered(Mode::Degraded),
+            (Mode::Degraded, true) => self.entered(Mode::Proxy),
+            _ => (),
+        }
+    }
+}
+
+/// Body of a mode change request: `proxy` or `config`.
+pub fn handle_mode_request(modes: &mut ModeMachine, body: &str) -> IngestResponse {
+    let Some(target) = Mode::from_request(body) else {
+        return IngestResponse::new(400, "Unknown mode, expected proxy or config");
+    };
+
+    match modes.request(target) {
+        Ok(()) => IngestResponse::new(202, target.as_str()),
+        Err(e) => IngestResponse::new(400, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_request_sets_the_trigger() {
+        let mut modes = ModeMachine::new();
+        modes.request_by(Mode::Config, Trigger::Fallback).unwrap();
+        assert_eq!(modes.trigger(), Trigger::Boot);
+
+        assert_eq!(modes.take_request(), Some(Mode::Config));
+        modes.entered(Mode::Config);
+        assert_eq!(modes.trigger(), Trigger::Fallback);
+
+        modes.request_toggle();
+        assert_eq!(modes.take_request(), Some(Mode::Proxy));
+        assert_eq!(modes.trigger(), Trigger::Button);
+
+        modes.entered(Mode::Proxy);
+        assert_eq!(handle_mode_request(&mut modes, "config").status, 202);
+        assert_eq!(modes.take_request(), Some(Mode::Config));
+        assert_eq!(modes.trigger(), Trigger::Remote);
+    }
+
+    #[test]
+    fn health_keeps_the_trigger() {
+        let mut modes = ModeMachine::new();
+        modes.request_by(Mode::Proxy, Trigger::Fallback).unwrap();
+        modes.take_request();
+        modes.entered(Mode::Proxy);
+
+        modes.on_health(false);
+        assert_eq!(modes.mode(), Mode::Degraded);
+        assert_eq!(modes.trigger(), Trigger::Fallback);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+use crate::config::ConfigStore;
+use crate::outbox::OutboxMessage;
+
+pub const AVAILABILITY_ONLINE: &str = "online";
+pub const AVAILABILITY_OFFLINE: &str = "offline";
+
+pub trait MqttPublisher {
+    type Error: fmt::Display;
+
+    fn is_connected(&self) -> bool;
+    fn publish(&mut self, message: &OutboxMessage) -> Result<(), Self::Error>;
+}
+
+pub fn make_mqtt_url(config: &impl ConfigStore) -> String {
+    format!(
+        "{}://{}:{}",
+        if config.get_mqtt_tls() {
+            "mqtts"
+        } else {
+            "mqtt"
+        },
+        config.get_mqtt_server(),
+        config.get_mqtt_port()
+    )
+}
+
+/// MQTT client ID, the configured one or `wifi-proxy-` followed by the end of
+/// the station MAC address so that several proxies can share a broker.
+pub fn client_id(config: &impl ConfigStore, mac: &[u8; 6]) -> String {
+    let client_id = config.get_mqtt_client_id();
+
+    if !client_id.is_empty() {
+        return client_id;
+    }
+
+    format!("wifi-proxy-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
+
+/// Retained `online` / `offline` state of the proxy, `offline` being also the
+/// last will.
+pub fn availability_topic(config: &impl ConfigStore, client_id: &str) -> String {
+    format!(
+        "{}{}/availability",
+        config.get_mqtt_topic_prefix(),
+        client_id
+    )
+}
+
+/// Receives `proxy` or `config` to switch the runtime mode.
+pub fn mode_command_topic(config: &impl ConfigStore, client_id: &str) -> String {
+    format!("{}{}/mode/set", config.get_mqtt_topic_prefix(), client_id)
+}
+
+/// Join / leave events of the AP clients, when enabled.
+pub fn ap_clients_topic(config: &impl ConfigStore, client_id: &str) -> String {
+    format!("{}{}/clients", config.get_mqtt_topic_prefix(), client_id)
+}
+
+/// Readings refused because their sensor is not allowed.
+pub fn diagnostics_topic(config: &impl ConfigStore, client_id: &str) -> String {
+    format!(
+        "{}{}/diagnostics",
+        config.get_mqtt_topic_prefix(),
+        client_id
+    )
+}
+
+pub fn availability_message(topic: &str, online: bool) -> OutboxMessage {
+    let state = if online {
+        AVAILABILITY_ONLINE
+    } else {
+        AVAILABILITY_OFFLINE
+    };
+
+    OutboxMessage::new(topic, 1, state.as_bytes()).retained()
+}
+
+/// Never connected, the readings stay in the outbox.
+#[cfg(test)]
+pub(crate) struct OfflinePublisher;
+
+#[cfg(test)]
+impl MqttPublisher for OfflinePublisher {
+    type Error = crate::string_error::StringError;
+
+    fn is_connected(&self) -> bool {
+        false
+    }
+
+    fn publish(&mut self, _message: &OutboxMessage) -> Result<(), Self::Error> {
+        Err(crate::string_error::StringError("Offline"))
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::VecDeque;
+
+use crate::string_error::StringError;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OutboxMessage {
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+const RETAIN_FLAG: u8 = 0x80;
+const QOS_MASK: u8 = 0x03;
+
+impl OutboxMessage {
+    pub fn new(topic: &str, qos: u8, payload: &[u8]) -> Self {
+        Self {
+            topic: topic.to_string(),
+            qos,
+            retain: false,
+            payload: payload.to_vec(),
+        }
+    }
+
+    pub fn retained(mut self) -> Self {
+        self.retain = true;
+        self
+    }
+
+    /// Serialized layout: QoS (u8, bit 7 is the retain flag), topic length
+    /// (u16, little endian), topic, payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let topic = self.topic.as_bytes();
+        let mut bytes = Vec::with_capacity(3 + topic.len() + self.payload.len());
+
+        bytes.push(self.qos | if self.retain { RETAIN_FLAG } else { 0 });
+        bytes.extend_from_slice(&(topic.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(topic);
+        bytes.extend_from_slice(&self.payload);
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StringError> {
+        if bytes.len() < 3 {
+            return Err(StringError("Outbox message too short"));
+        }
+
+        let qos = bytes[0] & QOS_MASK;
+        let retain = bytes[0] & RETAIN_FLAG != 0;
+        let topic_len = u16::from_le_bytes([bytes[1], bytes[2]]) as usize;
+
+        if bytes.len() < 3 + topic_len {
+            return Err(StringError("Outbox message truncated"));
+        }
+
+        let topic = std::str::from_utf8(&bytes[3..3 + topic_len])
+            .map_err(|_| StringError("Outbox message topic is not UTF-8"))?;
+
+        let message = Self::new(topic, qos, &bytes[3 + topic_len..]);
+
+        Ok(if retain { message.retained() } else { message })
+    }
+}
+
+/// Persistent FIFO used once the RAM queue is full.
+pub trait SpillStorage {
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn push_back(&mut self, message: &OutboxMessage) -> Result<(), StringError>;
+    fn front(&self) -> Result<Option<OutboxMessage>, StringError>;
+    fn pop_front(&mut self) -> Result<(), StringError>;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len() >= self.capacity()
+    }
+}
+
+/// Bounded store-and-forward queue.
+///
+/// Messages stay in RAM until `ram_capacity` is reached, then go to the spill
+/// storage. As soon as the spill storage holds something, every new message is
+/// appended to it, so the RAM queue always holds the oldest messages and the
+/// drain order is the arrival order.
+pub struct Outbox<S: SpillStorage> {
+    ram: VecDeque<OutboxMessage>,
+    ram_capacity: usize,
+    spill: S,
+}
+
+impl<S: SpillStorage> Outbox<S> {
+    pub fn new(ram_capacity: usize, spill: S) -> Self {
+        Self {
+            ram: VecDeque::with_capacity(ram_capacity),
+            ram_capacity,
+            spill,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ram.len() + self.spill.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ram.is_empty() && self.spill.is_empty()
+    }
+
+    pub fn push(&mut self, message: OutboxMessage) -> Result<(), StringError> {
+        if self.spill.is_empty() && self.ram.len() < self.ram_capacity {
+            self.ram.push_back(message);
+            return Ok(());
+        }
+
+        if self.spill.is_full() {
+            return Err(StringError("Outbox is full"));
+        }
+
+        self.spill.push_back(&message)
+    }
+
+    pub fn front(&self) -> Result<Option<OutboxMessage>, StringError> {
+        match self.ram.front() {
+            Some(message) => Ok(Some(message.clone())),
+            None => self.spill.front(),
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Result<(), StringError> {
+        if self.ram.pop_front().is_none() {
+            self.spill.pop_front()?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the RAM queue to the spill storage ahead of its messages, before
+    /// a restart. When the spill storage cannot take them all, the oldest are
+    /// dropped. Returns the number of messages moved.
+    pub fn spill_ram(&mut self) -> Result<usize, StringError> {
+        let spilled = self.spill.len();
+        let room = self.spill.capacity().saturating_sub(spilled);
+        let dropped = self.ram.len().saturating_sub(room);
+
+        if dropped > 0 {
+            log::warn!("Spill storage full, drop {} queued message(s)", dropped);
+            self.ram.drain(..dropped);
+        }
+
+        let moved = self.ram.len();
+
+        while let Some(message) = self.ram.front() {
+            self.spill.push_back(message)?;
+            self.ram.pop_front();
+        }
+
+        // The RAM messages are the oldest, the spilled ones go behind them.
+        for _ in 0..spilled {
+            let Some(message) = self.spill.front()? else {
+                break;
+            };
+
+            self.spill.pop_front()?;
+            self.spill.push_back(&message)?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Sends queued messages in order until `send` fails or the queue is empty.
+    /// Returns the number of messages sent.
+    pub fn drain<F>(&mut self, mut send: F) -> Result<usize, StringError>
+    where
+        F: FnMut(&OutboxMessage) -> bool,
+    {
+        let mut sent = 0;
+
+        loop {
+            let message = match self.front() {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("Drop unreadable outbox message ({})", e);
+                    self.pop_front()?;
+                    continue;
+                }
+            };
+
+            if !send(&message) {
+                break;
+            }
+
+            self.pop_front()?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+}
+
+/// Spill storage kept in RAM, for hosts without a flash partition.
+pub struct MemorySpillStorage {
+    messages: VecDeque<OutboxMessage>,
+    capacity: usize,
+}
+
+impl MemorySpillStorage {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            capacity,
+        }
+    }
+}
+
+impl SpillStorage for MemorySpillStorage {
+    fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn push_back(&mut self, message: &OutboxMessage) -> Result<(), StringError> {
+        self.messages.push_back(message.clone());
+        Ok(())
+    }
+
+    fn front(&self) -> Result<Option<OutboxMessage>, StringError> {
+        Ok(self.messages.front().cloned())
+    }
+
+    fn pop_front(&mut self) -> Result<(), StringError> {
+        self.messages.pop_front();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(n: usize) -> OutboxMessage {
+        OutboxMessage::new("sensor/test", 1, n.to_string().as_bytes())
+    }
+
+    fn drain_all<S: SpillStorage>(outbox: &mut Outbox<S>) -> Vec<String> {
+        let mut payloads = Vec::new();
+
+        outbox
+            .drain(|message| {
+                payloads.push(String::from_utf8(message.payload.clone()).unwrap());
+                true
+            })
+            .unwrap();
+
+        payloads
+    }
+
+    #[test]
+    fn message_bytes_round_trip() {
+        let message = message(7);
+
+        assert_eq!(OutboxMessage::from_bytes(&message.to_bytes()), Ok(message));
+        assert!(OutboxMessage::from_bytes(&[1, 10, 0, b'a']).is_err());
+    }
+
+    #[test]
+    fn spills_once_ram_is_full() {
+        let mut outbox = Outbox::new(2, MemorySpillStorage::new(4));
+
+        for n in 0..3 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        assert_eq!(outbox.ram.len(), 2);
+        assert_eq!(outbox.spill.len(), 1);
+        assert_eq!(outbox.len(), 3);
+    }
+
+    #[test]
+    fn keeps_spilling_until_spill_is_empty() {
+        let mut outbox = Outbox::new(2, MemorySpillStorage::new(4));
+
+        for n in 0..3 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        // Room in RAM again, but a new message must not overtake the spilled one.
+        outbox.pop_front().unwrap();
+        outbox.push(message(3)).unwrap();
+
+        assert_eq!(outbox.ram.len(), 1);
+        assert_eq!(outbox.spill.len(), 2);
+        assert_eq!(drain_all(&mut outbox), ["1", "2", "3"]);
+    }
+
+    #[test]
+    fn refuses_messages_when_full() {
+        let mut outbox = Outbox::new(1, MemorySpillStorage::new(2));
+
+        for n in 0..3 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        assert_eq!(outbox.push(message(3)), Err(StringError("Outbox is full")));
+        assert_eq!(drain_all(&mut outbox), ["0", "1", "2"]);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn spilling_ram_keeps_the_arrival_order() {
+        let mut outbox = Outbox::new(2, MemorySpillStorage::new(4));
+
+        for n in 0..4 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        assert_eq!(outbox.spill_ram(), Ok(2));
+        assert!(outbox.ram.is_empty());
+        assert_eq!(outbox.spill.len(), 4);
+        assert_eq!(drain_all(&mut outbox), ["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn spilling_ram_drops_the_oldest_that_do_not_fit() {
+        let mut outbox = Outbox::new(3, MemorySpillStorage::new(3));
+
+        for n in 0..5 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        assert_eq!(outbox.spill_ram(), Ok(1));
+        assert_eq!(drain_all(&mut outbox), ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn drains_in_arrival_order_until_send_fails() {
+        let mut outbox = Outbox::new(2, MemorySpillStorage::new(4));
+
+        for n in 0..5 {
+            outbox.push(message(n)).unwrap();
+        }
+
+        let mut sent = Vec::new();
+        let count = outbox
+            .drain(|message| {
+                if sent.len() == 3 {
+                    return false;
+                }
+
+                sent.push(String::from_utf8(message.payload.clone()).unwrap());
+                true
+            })
+            .unwrap();
+
+        assert_eq!(count, 3);
+        assert_eq!(sent, ["0", "1", "2"]);
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(drain_all(&mut outbox), ["3", "4"]);
+    }
+}
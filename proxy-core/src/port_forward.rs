@@ -0,0 +1,185 @@
+use std::net::Ipv4Addr;
+
+use serde_json::{json, Value};
+
+use crate::string_error::StringError;
+
+pub const MAX_PORT_FORWARDS: usize = 16;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tcp" => Some(Protocol::Tcp),
+            "udp" => Some(Protocol::Udp),
+            _ => None,
+        }
+    }
+}
+
+/// Connections to `port` on the station address are sent to `to:to_port`, a
+/// client of the AP. Only used when NAPT is enabled.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PortForward {
+    pub protocol: Protocol,
+    pub port: u16,
+    pub to: Ipv4Addr,
+    pub to_port: u16,
+}
+
+impl PortForward {
+    pub fn new(protocol: Protocol, port: u16, to: Ipv4Addr, to_port: u16) -> Self {
+        Self {
+            protocol,
+            port,
+            to,
+            to_port,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "proto": self.protocol.as_str(),
+            "port": self.port,
+            "to": self.to.to_string(),
+            "to_port": self.to_port,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, StringError> {
+        let protocol = value
+            .get("proto")
+            .and_then(Value::as_str)
+            .and_then(Protocol::from_name)
+            .ok_or(StringError("Port forward protocol must be tcp or udp"))?;
+
+        let port = value
+            .get("port")
+            .and_then(Value::as_u64)
+            .and_then(|port| u16::try_from(port).ok())
+            .filter(|&port| port != 0)
+            .ok_or(StringError("Port forward port must be between 1 and 65535"))?;
+
+        let to = value
+            .get("to")
+            .and_then(Value::as_str)
+            .and_then(|to| to.parse().ok())
+            .ok_or(StringError("Port forward target must be an IPv4 address"))?;
+
+        // The target port defaults to the forwarded one.
+        let to_port = match value.get("to_port") {
+            None => port,
+            Some(to_port) => to_port
+                .as_u64()
+                .and_then(|to_port| u16::try_from(to_port).ok())
+                .filter(|&to_port| to_port != 0)
+                .ok_or(StringError(
+                    "Port forward target port must be between 1 and 65535",
+                ))?,
+        };
+
+        Ok(Self::new(protocol, port, to, to_port))
+    }
+}
+
+pub fn port_forwards_from_json(s: &str) -> Result<Vec<PortForward>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Port forwards are not valid JSON"))?;
+
+    let forwards = value
+        .as_array()
+        .ok_or(StringError("Port forwards must be a JSON array"))?
+        .iter()
+        .map(PortForward::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if forwards.len() > MAX_PORT_FORWARDS {
+        return Err(StringError("Too many port forwards (16 max)"));
+    }
+
+    Ok(forwards)
+}
+
+pub fn port_forwards_to_json(forwards: &[PortForward]) -> String {
+    Value::Array(forwards.iter().map(PortForward::to_json).collect()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_round_trip_as_json() {
+        let forwards = [
+            PortForward::new(Protocol::Tcp, 2222, Ipv4Addr::new(192, 168, 70, 2), 22),
+            PortForward::new(Protocol::Udp, 53, Ipv4Addr::new(192, 168, 70, 3), 53),
+        ];
+
+        assert_eq!(
+            port_forwards_from_json(&port_forwards_to_json(&forwards)).as_deref(),
+            Ok(&forwards[..])
+        );
+    }
+
+    #[test]
+    fn target_port_defaults_to_the_port() {
+        assert_eq!(
+            port_forwards_from_json(r#"[{"proto":"udp","port":53,"to":"10.0.0.2"}]"#),
+            Ok(vec![PortForward::new(
+                Protocol::Udp,
+                53,
+                Ipv4Addr::new(10, 0, 0, 2),
+                53
+            )])
+        );
+    }
+
+    #[test]
+    fn invalid_forwards_are_refused() {
+        let too_many = format!(
+            "[{}]",
+            vec![r#"{"proto":"tcp","port":1,"to":"10.0.0.2"}"#; MAX_PORT_FORWARDS + 1].join(",")
+        );
+
+        for (json, error) in [
+            ("[", "Port forwards are not valid JSON"),
+            ("{}", "Port forwards must be a JSON array"),
+            (
+                r#"[{"port":1,"to":"10.0.0.2"}]"#,
+                "Port forward protocol must be tcp or udp",
+            ),
+            (
+                r#"[{"proto":"tcp","port":65536,"to":"10.0.0.2"}]"#,
+                "Port forward port must be between 1 and 65535",
+            ),
+            (
+                r#"[{"proto":"tcp","port":1,"to":"10.0.0"}]"#,
+                "Port forward target must be an IPv4 address",
+            ),
+            (
+                r#"[{"proto":"tcp","port":1,"to":"10.0.0.2","to_port":0}]"#,
+                "Port forward target port must be between 1 and 65535",
+            ),
+            (too_many.as_str(), "Too many port forwards (16 max)"),
+        ] {
+            assert_eq!(
+                port_forwards_from_json(json),
+                Err(StringError(error)),
+                "{}",
+                json
+            );
+        }
+    }
+}
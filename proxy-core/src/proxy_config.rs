@@ -0,0 +1,1524 @@
+use std::net::Ipv4Addr;
+
+use crate::auth_mode::AuthMode;
+use crate::config::{
+    ConfigStore, KEY_DHCP_RESERVATIONS, KEY_MQTT_CA_CERT, KEY_PORT_FORWARDS, KEY_SENSOR_ALLOWLIST,
+    KEY_SENSOR_CREDENTIALS, KEY_SENSOR_ROUTES,
+};
+use crate::config_journal::{self, nvs_blob_size, StagedWrites};
+use crate::dhcp_server::{reservations_from_json, DhcpReservation};
+use crate::port_forward::{port_forwards_from_json, PortForward, Protocol};
+use crate::regulatory::find_country;
+use crate::sensor_allowlist::{
+    allowlist_from_json, validate_sensor_allowlist, MAX_ALLOWLIST_JSON_LEN,
+};
+use crate::sensor_auth::{credentials_from_form, validate_sensor_credentials, SensorCredential};
+use crate::sensor_route::{routes_from_json, SensorRoute};
+use crate::sta_ip::{
+    mask_prefix_len, parse_optional_ipv4, same_subnet, StaIpConfig, MAX_DHCP_HOSTNAME_LEN,
+};
+use crate::sta_network::{StaNetwork, MAX_STA_NETWORKS};
+
+/// Form fields of the ranked station networks, the first one keeps the names
+/// of the single network era.
+pub const FIELD_STA_SSIDS: [&str; MAX_STA_NETWORKS] = ["stassid", "stassid1", "stassid2"];
+pub const FIELD_STA_PASSPHRASES: [&str; MAX_STA_NETWORKS] = ["stapass", "stapass1", "stapass2"];
+pub const FIELD_STA_PASSPHRASES_CLEAR: [&str; MAX_STA_NETWORKS] =
+    ["stapassclr", "stapass1clr", "stapass2clr"];
+pub const FIELD_STA_AUTH_MODE: &str = "staauth";
+pub const FIELD_STA_STATIC_IP: &str = "stastatic";
+pub const FIELD_STA_IP: &str = "staip";
+pub const FIELD_STA_MASK: &str = "stamask";
+pub const FIELD_STA_GATEWAY: &str = "stagw";
+pub const FIELD_STA_DNS: &str = "stadns1";
+pub const FIELD_STA_SECONDARY_DNS: &str = "stadns2";
+pub const FIELD_STA_HOSTNAME: &str = "stahost";
+pub const FIELD_AP_SSID: &str = "apssid";
+pub const FIELD_AP_PASSPHRASE: &str = "appass";
+pub const FIELD_AP_PASSPHRASE_CLEAR: &str = "appassclr";
+pub const FIELD_AP_AUTH_MODE: &str = "apauth";
+pub const FIELD_AP_SSID_HIDDEN: &str = "apishidden";
+pub const FIELD_AP_IP: &str = "apip";
+pub const FIELD_AP_MASK: &str = "apmask";
+pub const FIELD_AP_CHANNEL: &str = "apchan";
+pub const FIELD_AP_COUNTRY: &str = "apcc";
+pub const FIELD_AP_MAX_CLIENTS: &str = "apmaxcl";
+pub const FIELD_AP_CLIENT_EVENTS: &str = "apclievt";
+pub const FIELD_DHCP_RESERVATIONS: &str = "dhcpres";
+pub const FIELD_NAPT: &str = "napt";
+pub const FIELD_PORT_FORWARDS: &str = "portfwd";
+pub const FIELD_MQTT_SERVER: &str = "mqttsrv";
+pub const FIELD_MQTT_PORT: &str = "mqttprt";
+pub const FIELD_MQTT_USERNAME: &str = "mqttuser";
+pub const FIELD_MQTT_PASSWORD: &str = "mqttpass";
+pub const FIELD_MQTT_PASSWORD_CLEAR: &str = "mqttpassclr";
+pub const FIELD_MQTT_TLS: &str = "mqtttls";
+pub const FIELD_MQTT_CA_CERT: &str = "mqttca";
+pub const FIELD_MQTT_CLIENT_ID: &str = "mqttclid";
+pub const FIELD_MQTT_TOPIC_PREFIX: &str = "mqttpfx";
+pub const FIELD_HA_DISCOVERY: &str = "hadisco";
+pub const FIELD_SENSOR_ROUTES: &str = "routes";
+pub const FIELD_SENSOR_CREDENTIALS: &str = "senscred";
+pub const FIELD_SENSOR_ALLOWLIST: &str = "sensallow";
+pub const FIELD_SENSOR_ALLOW_ALL: &str = "sensallall";
+
+pub const FORM_FIELDS: &[&str] = &[
+    FIELD_STA_SSIDS[0],
+    FIELD_STA_PASSPHRASES[0],
+    FIELD_STA_PASSPHRASES_CLEAR[0],
+    FIELD_STA_SSIDS[1],
+    FIELD_STA_PASSPHRASES[1],
+    FIELD_STA_PASSPHRASES_CLEAR[1],
+    FIELD_STA_SSIDS[2],
+    FIELD_STA_PASSPHRASES[2],
+    FIELD_STA_PASSPHRASES_CLEAR[2],
+    FIELD_STA_AUTH_MODE,
+    FIELD_STA_STATIC_IP,
+    FIELD_STA_IP,
+    FIELD_STA_MASK,
+    FIELD_STA_GATEWAY,
+    FIELD_STA_DNS,
+    FIELD_STA_SECONDARY_DNS,
+    FIELD_STA_HOSTNAME,
+    FIELD_AP_SSID,
+    FIELD_AP_PASSPHRASE,
+    FIELD_AP_PASSPHRASE_CLEAR,
+    FIELD_AP_AUTH_MODE,
+    FIELD_AP_SSID_HIDDEN,
+    FIELD_AP_IP,
+    FIELD_AP_MASK,
+    FIELD_AP_CHANNEL,
+    FIELD_AP_COUNTRY,
+    FIELD_AP_MAX_CLIENTS,
+    FIELD_AP_CLIENT_EVENTS,
+    FIELD_DHCP_RESERVATIONS,
+    FIELD_NAPT,
+    FIELD_PORT_FORWARDS,
+    FIELD_MQTT_SERVER,
+    FIELD_MQTT_PORT,
+    FIELD_MQTT_USERNAME,
+    FIELD_MQTT_PASSWORD,
+    FIELD_MQTT_PASSWORD_CLEAR,
+    FIELD_MQTT_TLS,
+    FIELD_MQTT_CA_CERT,
+    FIELD_MQTT_CLIENT_ID,
+    FIELD_MQTT_TOPIC_PREFIX,
+    FIELD_HA_DISCOVERY,
+    FIELD_SENSOR_ROUTES,
+    FIELD_SENSOR_CREDENTIALS,
+    FIELD_SENSOR_ALLOWLIST,
+    FIELD_SENSOR_ALLOW_ALL,
+];
+
+const MAX_SSID_LEN: usize = 32;
+const MIN_PASSPHRASE_LEN: usize = 8;
+const MAX_PASSPHRASE_LEN: usize = 63;
+const MAX_HOSTNAME_LEN: usize = 128;
+/// Station limit of the ESP32-C3 soft AP.
+const MAX_AP_CLIENTS: u8 = 10;
+const MAX_MQTT_CREDENTIAL_LEN: usize = 64;
+const MAX_CA_CERT_LEN: usize = 4000;
+const MAX_CLIENT_ID_LEN: usize = 64;
+const MAX_TOPIC_PREFIX_LEN: usize = 64;
+/// NVS space of the settings and of the journal committing them, the rest of
+/// the `config` partition (see `custom_partitions.csv`) holds the runtime
+/// keys and the page kept free by NVS.
+pub const MAX_STORED_SIZE: usize = 48 * 1024;
+
+/// Settings that can be shortened when the stored size is over, by key.
+const VARIABLE_SIZE_FIELDS: [(&str, &str); 6] = [
+    (KEY_MQTT_CA_CERT, FIELD_MQTT_CA_CERT),
+    (KEY_SENSOR_ROUTES, FIELD_SENSOR_ROUTES),
+    (KEY_SENSOR_CREDENTIALS, FIELD_SENSOR_CREDENTIALS),
+    (KEY_SENSOR_ALLOWLIST, FIELD_SENSOR_ALLOWLIST),
+    (KEY_DHCP_RESERVATIONS, FIELD_DHCP_RESERVATIONS),
+    (KEY_PORT_FORWARDS, FIELD_PORT_FORWARDS),
+];
+
+/// Port of the proxy HTTP server, on both interfaces.
+const HTTP_PORT: u16 = 80;
+
+const PEM_CERT_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERT_END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: &'static str,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: &'static str) -> Self {
+        Self { field, message }
+    }
+}
+
+/// Snapshot of every user editable setting.
+///
+/// The portal builds one from the stored values, applies the submitted form
+/// on it, validates the whole and only then commits it to the store.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ProxyConfig {
+    pub sta_networks: Vec<StaNetwork>,
+    pub sta_auth_mode: AuthMode,
+    pub sta_ip: StaIpConfig,
+    pub ap_ssid: String,
+    pub ap_passphrase: String,
+    pub ap_auth_mode: AuthMode,
+    pub ap_hidden_ssid: bool,
+    pub ap_ip: Ipv4Addr,
+    pub ap_mask: Ipv4Addr,
+    pub ap_channel: u8,
+    pub ap_country: String,
+    pub ap_max_clients: u8,
+    pub ap_client_events: bool,
+    pub dhcp_reservations: Vec<DhcpReservation>,
+    pub napt: bool,
+    pub port_forwards: Vec<PortForward>,
+    pub mqtt_server: String,
+    pub mqtt_port: u16,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    pub mqtt_tls: bool,
+    pub mqtt_ca_cert: String,
+    pub mqtt_client_id: String,
+    pub mqtt_topic_prefix: String,
+    pub ha_discovery: bool,
+    pub sensor_routes: Vec<SensorRoute>,
+    pub sensor_credentials: Vec<SensorCredential>,
+    pub sensor_allowlist: Vec<String>,
+    pub sensor_allow_all: bool,
+}
+
+impl ProxyConfig {
+    pub fn load(store: &impl ConfigStore) -> Self {
+        Self {
+            sta_networks: store.get_sta_networks(),
+            sta_auth_mode: store.get_sta_auth_mode(),
+            sta_ip: store.get_sta_ip_config(),
+            ap_ssid: store.get_ap_ssid(),
+            ap_passphrase: store.get_ap_passphrase(),
+            ap_auth_mode: store.get_ap_auth_mode(),
+            ap_hidden_ssid: store.get_ap_hidden_ssid(),
+            ap_ip: store.get_ap_ip(),
+            ap_mask: store.get_ap_mask(),
+            ap_channel: store.get_ap_channel(),
+            ap_country: store.get_ap_country(),
+            ap_max_clients: store.get_ap_max_clients(),
+            ap_client_events: store.get_ap_client_events(),
+            dhcp_reservations: store.get_dhcp_reservations(),
+            napt: store.get_napt(),
+            port_forwards: store.get_port_forwards(),
+            mqtt_server: store.get_mqtt_server(),
+            mqtt_port: store.get_mqtt_port(),
+            mqtt_username: store.get_mqtt_username(),
+            mqtt_password: store.get_mqtt_password(),
+            mqtt_tls: store.get_mqtt_tls(),
+            mqtt_ca_cert: store.get_mqtt_ca_cert(),
+            mqtt_client_id: store.get_mqtt_client_id(),
+            mqtt_topic_prefix: store.get_mqtt_topic_prefix(),
+            ha_discovery: store.get_ha_discovery(),
+            sensor_routes: store.get_sensor_routes(),
+            sensor_credentials: store.get_sensor_credentials(),
+            sensor_allowlist: store.get_sensor_allowlist(),
+            sensor_allow_all: store.get_sensor_allow_all(),
+        }
+    }
+
+    /// Overwrites the settings present in the form. Fields that cannot be
+    /// parsed keep their current value and are reported. Secrets are never
+    /// sent back to the portal, see `apply_secret`.
+    pub fn apply_form<F>(&mut self, field: F) -> Vec<FieldError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut errors = Vec::new();
+
+        if FIELD_STA_SSIDS.iter().any(|name| field(name).is_some()) {
+            // Empty slots are dropped, the following networks move up. A
+            // network keeps its passphrase while its SSID is unchanged.
+            self.sta_networks = (0..MAX_STA_NETWORKS)
+                .filter_map(|i| {
+                    let ssid = field(FIELD_STA_SSIDS[i]).unwrap_or_default();
+
+                    if ssid.is_empty() {
+                        return None;
+                    }
+
+                    let mut passphrase = self
+                        .sta_networks
+                        .iter()
+                        .find(|network| network.ssid == ssid)
+                        .map(|network| network.passphrase.clone())
+                        .unwrap_or_default();
+
+                    apply_secret(
+                        &mut passphrase,
+                        field(FIELD_STA_PASSPHRASES[i]),
+                        field(FIELD_STA_PASSPHRASES_CLEAR[i]).is_some(),
+                    );
+
+                    Some(StaNetwork::new(&ssid, &passphrase))
+                })
+                .collect();
+        }
+
+        for (name, auth_mode) in [
+            (FIELD_STA_AUTH_MODE, &mut self.sta_auth_mode),
+            (FIELD_AP_AUTH_MODE, &mut self.ap_auth_mode),
+        ] {
+            if let Some(value) = field(name) {
+                match AuthMode::from_name(&value) {
+                    Some(value) => *auth_mode = value,
+                    None => errors.push(FieldError::new(name, "Unknown security mode")),
+                }
+            }
+        }
+
+        self.sta_ip.static_ip = field(FIELD_STA_STATIC_IP).is_some();
+
+        for (name, address) in [
+            (FIELD_STA_IP, &mut self.sta_ip.ip),
+            (FIELD_STA_MASK, &mut self.sta_ip.mask),
+            (FIELD_STA_GATEWAY, &mut self.sta_ip.gateway),
+            (FIELD_STA_DNS, &mut self.sta_ip.dns),
+            (FIELD_STA_SECONDARY_DNS, &mut self.sta_ip.secondary_dns),
+        ] {
+            if let Some(value) = field(name) {
+                match parse_optional_ipv4(&value) {
+                    Ok(value) => *address = value,
+                    Err(message) => errors.push(FieldError::new(name, message)),
+                }
+            }
+        }
+
+        if let Some(value) = field(FIELD_STA_HOSTNAME) {
+            self.sta_ip.hostname = value.trim().to_string();
+        }
+
+        if let Some(value) = field(FIELD_AP_SSID) {
+            self.ap_ssid = value;
+        }
+
+        apply_secret(
+            &mut self.ap_passphrase,
+            field(FIELD_AP_PASSPHRASE),
+            field(FIELD_AP_PASSPHRASE_CLEAR).is_some(),
+        );
+
+        self.ap_hidden_ssid = field(FIELD_AP_SSID_HIDDEN).is_some();
+
+        for (name, address) in [
+            (FIELD_AP_IP, &mut self.ap_ip),
+            (FIELD_AP_MASK, &mut self.ap_mask),
+        ] {
+            if let Some(value) = field(name) {
+                match parse_optional_ipv4(&value) {
+                    Ok(Some(value)) => *address = value,
+                    Ok(None) => errors.push(FieldError::new(name, "The address is required")),
+                    Err(message) => errors.push(FieldError::new(name, message)),
+                }
+            }
+        }
+
+        if let Some(value) = field(FIELD_AP_CHANNEL) {
+            match value.trim().parse::<u8>() {
+                Ok(channel) => self.ap_channel = channel,
+                Err(_) => errors.push(FieldError::new(
+                    FIELD_AP_CHANNEL,
+                    "The channel must be a number",
+                )),
+            }
+        }
+
+        if let Some(value) = field(FIELD_AP_COUNTRY) {
+            self.ap_country = value.trim().to_ascii_uppercase();
+        }
+
+        if let Some(value) = field(FIELD_AP_MAX_CLIENTS) {
+            match value.trim().parse::<u8>() {
+                Ok(max_clients) => self.ap_max_clients = max_clients,
+                Err(_) => errors.push(FieldError::new(
+                    FIELD_AP_MAX_CLIENTS,
+                    "The client limit must be between 1 and 10",
+                )),
+            }
+        }
+
+        self.ap_client_events = field(FIELD_AP_CLIENT_EVENTS).is_some();
+        if let Some(value) = field(FIELD_DHCP_RESERVATIONS) {
+            let value = value.trim();
+
+            match reservations_from_json(if value.is_empty() { "[]" } else { value }) {
+                Ok(reservations) => self.dhcp_reservations = reservations,
+                Err(e) => errors.push(FieldError::new(FIELD_DHCP_RESERVATIONS, e.0)),
+            }
+        }
+
+        self.napt = field(FIELD_NAPT).is_some();
+
+        if let Some(value) = field(FIELD_PORT_FORWARDS) {
+            let value = value.trim();
+
+            match port_forwards_from_json(if value.is_empty() { "[]" } else { value }) {
+                Ok(forwards) => self.port_forwards = forwards,
+                Err(e) => errors.push(FieldError::new(FIELD_PORT_FORWARDS, e.0)),
+            }
+        }
+
+        if let Some(value) = field(FIELD_MQTT_SERVER) {
+            self.mqtt_server = value.trim().to_string();
+        }
+
+        if let Some(value) = field(FIELD_MQTT_PORT) {
+            match value.trim().parse::<u16>() {
+                Ok(port) => self.mqtt_port = port,
+                Err(_) => errors.push(FieldError::new(
+                    FIELD_MQTT_PORT,
+                    "The port must be a number between 1 and 65535",
+                )),
+            }
+        }
+
+        if let Some(value) = field(FIELD_MQTT_USERNAME) {
+            self.mqtt_username = value;
+        }
+
+        apply_secret(
+            &mut self.mqtt_password,
+            field(FIELD_MQTT_PASSWORD),
+            field(FIELD_MQTT_PASSWORD_CLEAR).is_some(),
+        );
+
+        self.mqtt_tls = field(FIELD_MQTT_TLS).is_some();
+
+        if let Some(value) = field(FIELD_MQTT_CA_CERT) {
+            self.mqtt_ca_cert = value.trim().replace("\r\n", "\n");
+        }
+
+        if let Some(value) = field(FIELD_MQTT_CLIENT_ID) {
+            self.mqtt_client_id = value.trim().to_string();
+        }
+
+        if let Some(value) = field(FIELD_MQTT_TOPIC_PREFIX) {
+            self.mqtt_topic_prefix = value.trim().to_string();
+        }
+
+        self.ha_discovery = field(FIELD_HA_DISCOVERY).is_some();
+
+        if let Some(value) = field(FIELD_SENSOR_ROUTES) {
+            let value = value.trim();
+
+            match routes_from_json(if value.is_empty() { "[]" } else { value }) {
+                Ok(routes) => self.sensor_routes = routes,
+                Err(e) => errors.push(FieldError::new(FIELD_SENSOR_ROUTES, e.0)),
+            }
+        }
+
+        if let Some(value) = field(FIELD_SENSOR_CREDENTIALS) {
+            let value = value.trim();
+
+            match credentials_from_form(
+                if value.is_empty() { "[]" } else { value },
+                &self.sensor_credentials,
+            ) {
+                Ok(credentials) => self.sensor_credentials = credentials,
+                Err(e) => errors.push(FieldError::new(FIELD_SENSOR_CREDENTIALS, e.0)),
+            }
+        }
+
+        if let Some(value) = field(FIELD_SENSOR_ALLOWLIST) {
+            let value = value.trim();
+
+            match allowlist_from_json(if value.is_empty() { "[]" } else { value }) {
+                Ok(ids) => self.sensor_allowlist = ids,
+                Err(e) => errors.push(FieldError::new(FIELD_SENSOR_ALLOWLIST, e.0)),
+            }
+        }
+        self.sensor_allow_all = field(FIELD_SENSOR_ALLOW_ALL).is_some();
+
+        errors
+    }
+
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        if self.sta_networks.len() > MAX_STA_NETWORKS {
+            errors.push(FieldError::new(
+                FIELD_STA_SSIDS[0],
+                "Too many station networks",
+            ));
+        }
+
+        for (i, network) in self.sta_networks.iter().enumerate().take(MAX_STA_NETWORKS) {
+            if network.ssid.len() > MAX_SSID_LEN {
+                errors.push(FieldError::new(
+                    FIELD_STA_SSIDS[i],
+                    "The SSID maximum length is 32 bytes",
+                ));
+            } else if self.sta_networks[..i]
+                .iter()
+                .any(|n| n.ssid == network.ssid)
+            {
+                errors.push(FieldError::new(
+                    FIELD_STA_SSIDS[i],
+                    "This network is already in the list",
+                ));
+            }
+
+            if let Err(message) = validate_passphrase(&network.passphrase)
+                .and_then(|_| self.sta_auth_mode.check_passphrase(&network.passphrase))
+            {
+                errors.push(FieldError::new(FIELD_STA_PASSPHRASES[i], message));
+            }
+        }
+
+        if self.sta_ip.static_ip {
+            errors.extend(self.validate_static_ip());
+        }
+
+        if let Err(message) = validate_dhcp_hostname(&self.sta_ip.hostname) {
+            errors.push(FieldError::new(FIELD_STA_HOSTNAME, message));
+        }
+
+        if self.ap_ssid.is_empty() || self.ap_ssid.len() > MAX_SSID_LEN {
+            errors.push(FieldError::new(
+                FIELD_AP_SSID,
+                "The SSID length must be between 1 and 32 bytes",
+            ));
+        }
+
+        if let Err(message) = validate_passphrase(&self.ap_passphrase) {
+            errors.push(FieldError::new(FIELD_AP_PASSPHRASE, message));
+        }
+
+        if let Err(message) = self.ap_auth_mode.check_passphrase(&self.ap_passphrase) {
+            errors.push(FieldError::new(FIELD_AP_AUTH_MODE, message));
+        }
+
+        errors.extend(self.validate_ap_radio());
+        errors.extend(self.validate_ap_subnet());
+
+        if let Err(message) = self.validate_dhcp_reservations() {
+            errors.push(FieldError::new(FIELD_DHCP_RESERVATIONS, message));
+        }
+
+        if let Err(message) = self.validate_port_forwards() {
+            errors.push(FieldError::new(FIELD_PORT_FORWARDS, message));
+        }
+
+        if let Err(message) = validate_hostname(&self.mqtt_server) {
+            errors.push(FieldError::new(FIELD_MQTT_SERVER, message));
+        }
+
+        if self.mqtt_port == 0 {
+            errors.push(FieldError::new(
+                FIELD_MQTT_PORT,
+                "The port must be a number between 1 and 65535",
+            ));
+        }
+
+        if self.mqtt_username.len() > MAX_MQTT_CREDENTIAL_LEN {
+            errors.push(FieldError::new(
+                FIELD_MQTT_USERNAME,
+                "The user name maximum length is 64 bytes",
+            ));
+        }
+
+        if self.mqtt_password.len() > MAX_MQTT_CREDENTIAL_LEN {
+            errors.push(FieldError::new(
+                FIELD_MQTT_PASSWORD,
+                "The password maximum length is 64 bytes",
+            ));
+        }
+
+        if let Err(message) = validate_pem_certificate(&self.mqtt_ca_cert) {
+            errors.push(FieldError::new(FIELD_MQTT_CA_CERT, message));
+        }
+
+        if let Err(message) = validate_client_id(&self.mqtt_client_id) {
+            errors.push(FieldError::new(FIELD_MQTT_CLIENT_ID, message));
+        }
+
+        if let Err(message) = validate_topic_prefix(&self.mqtt_topic_prefix) {
+            errors.push(FieldError::new(FIELD_MQTT_TOPIC_PREFIX, message));
+        }
+
+        if let Err(message) = validate_sensor_credentials(&self.sensor_credentials) {
+            errors.push(FieldError::new(FIELD_SENSOR_CREDENTIALS, message));
+        }
+
+        if let Err(message) = validate_sensor_allowlist(&self.sensor_allowlist) {
+            errors.push(FieldError::new(FIELD_SENSOR_ALLOWLIST, message));
+        }
+
+        if let Some(field) = self.oversized_field() {
+            errors.push(FieldError::new(
+                field,
+                "The settings do not fit in the storage, shorten this one",
+            ));
+        }
+
+        errors
+    }
+
+    /// Applies the form and commits the result when every setting is valid.
+    /// Returns the field errors, nothing is written when there are some.
+    pub fn save_form<S, F>(&mut self, store: &mut S, field: F) -> Result<Vec<FieldError>, S::Error>
+    where
+        S: ConfigStore,
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut errors = self.apply_form(field);
+
+        // A field that could not be parsed kept its old value, which is not
+        // worth a second message.
+        for error in self.validate() {
+            if !errors.iter().any(|e| e.field == error.field) {
+                errors.push(error);
+            }
+        }
+
+        if errors.is_empty() {
+            self.commit(store)?;
+        }
+
+        Ok(errors)
+    }
+
+    /// The largest variable setting when the stored size, the allowlist
+    /// counted full as the enrolment fills it, is over `MAX_STORED_SIZE`.
+    fn oversized_field(&self) -> Option<&'static str> {
+        let mut staged = StagedWrites::new();
+        self.write(&mut staged).unwrap_or_else(|e| match e {});
+
+        let size = staged
+            .nvs_sizes()
+            .map(|(key, size)| match key {
+                KEY_SENSOR_ALLOWLIST => size.max(nvs_blob_size(MAX_ALLOWLIST_JSON_LEN)),
+                _ => size,
+            })
+            .sum::<usize>()
+            + staged.journal_nvs_size();
+
+        if size <= MAX_STORED_SIZE {
+            return None;
+        }
+
+        staged
+            .nvs_sizes()
+            .filter_map(|(key, size)| {
+                let (_, field) = VARIABLE_SIZE_FIELDS.iter().find(|(k, _)| *k == key)?;
+                Some((size, *field))
+            })
+            .max_by_key(|(size, _)| *size)
+            .map(|(_, field)| field)
+    }
+
+    fn validate_ap_radio(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        match find_country(&self.ap_country) {
+            None => errors.push(FieldError::new(FIELD_AP_COUNTRY, "Unknown country code")),
+            Some(country) if !country.allows_channel(self.ap_channel) => {
+                errors.push(FieldError::new(
+                    FIELD_AP_CHANNEL,
+                    "The channel is not allowed in the selected country",
+                ))
+            }
+            _ => (),
+        }
+
+        if !(1..=MAX_AP_CLIENTS).contains(&self.ap_max_clients) {
+            errors.push(FieldError::new(
+                FIELD_AP_MAX_CLIENTS,
+                "The client limit must be between 1 and 10",
+            ));
+        }
+
+        errors
+    }
+
+    /// The DHCP server leases addresses next to the AP one, the subnet is
+    /// kept to /24 or larger so the pool fits.
+    fn validate_ap_subnet(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+
+        let Some(prefix_len) = mask_prefix_len(self.ap_mask).filter(|len| (8..=24).contains(len))
+        else {
+            errors.push(FieldError::new(
+                FIELD_AP_MASK,
+                "The subnet mask must be between 255.0.0.0 and 255.255.255.0",
+            ));
+            return errors;
+        };
+
+        if let Err(message) = validate_host_address(self.ap_ip, prefix_len) {
+            errors.push(FieldError::new(FIELD_AP_IP, message));
+            return errors;
+        }
+
+        let sta_subnet = match (
+            self.sta_ip.static_ip,
+            self.sta_ip.ip,
+            self.sta_ip.prefix_len(),
+        ) {
+            (true, Some(ip), Some(sta_prefix_len)) => Some((ip, sta_prefix_len)),
+            _ => None,
+        };
+
+        if let Some((sta_ip, sta_prefix_len)) = sta_subnet {
+            if same_subnet(self.ap_ip, sta_ip, prefix_len.min(sta_prefix_len)) {
+                errors.push(FieldError::new(
+                    FIELD_AP_IP,
+                    "The AP subnet overlaps the station subnet",
+                ));
+            }
+        }
+
+        errors
+    }
+
+    fn validate_dhcp_reservations(&self) -> Result<(), &'static str> {
+        let prefix_len = mask_prefix_len(self.ap_mask).unwrap_or(24);
+
+        for (i, reservation) in self.dhcp_reservations.iter().enumerate() {
+            if reservation.mac[0] & 0x01 != 0 || reservation.mac == [0; 6] {
+                return Err("Reserved MAC addresses must be unicast addresses");
+            }
+
+            if reservation.ip == self.ap_ip
+                || !same_subnet(reservation.ip, self.ap_ip, prefix_len)
+                || validate_host_address(reservation.ip, prefix_len).is_err()
+            {
+                return Err("Reserved addresses must be free host addresses of the AP subnet");
+            }
+
+            let previous = &self.dhcp_reservations[..i];
+
+            if previous.iter().any(|r| r.mac == reservation.mac) {
+                return Err("A MAC address is reserved twice");
+            }
+
+            if previous.iter().any(|r| r.ip == reservation.ip) {
+                return Err("An address is reserved twice");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Targets are AP clients, and the HTTP port of the proxy stays reachable.
+    fn validate_port_forwards(&self) -> Result<(), &'static str> {
+        let prefix_len = mask_prefix_len(self.ap_mask).unwrap_or(24);
+
+        for (i, forward) in self.port_forwards.iter().enumerate() {
+            if forward.to == self.ap_ip
+                || !same_subnet(forward.to, self.ap_ip, prefix_len)
+                || validate_host_address(forward.to, prefix_len).is_err()
+            {
+                return Err("Port forward targets must be AP clients, in the AP subnet");
+            }
+
+            if forward.protocol == Protocol::Tcp && forward.port == HTTP_PORT {
+                return Err("TCP port 80 is used by the proxy and cannot be forwarded");
+            }
+
+            if self.port_forwards[..i]
+                .iter()
+                .any(|f| f.protocol == forward.protocol && f.port == forward.port)
+            {
+                return Err("A port is forwarded twice");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_static_ip(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let sta_ip = &self.sta_ip;
+
+        let Some(prefix_len) = sta_ip.prefix_len().filter(|len| (1..=30).contains(len)) else {
+            errors.push(FieldError::new(
+                FIELD_STA_MASK,
+                "A subnet mask between 128.0.0.0 and 255.255.255.252 is required",
+            ));
+            return errors;
+        };
+
+        match sta_ip.ip {
+            None => errors.push(FieldError::new(
+                FIELD_STA_IP,
+                "The address is required with a static configuration",
+            )),
+            Some(ip) => {
+                if let Err(message) = validate_host_address(ip, prefix_len) {
+                    errors.push(FieldError::new(FIELD_STA_IP, message));
+                }
+            }
+        }
+
+        match (sta_ip.gateway, sta_ip.ip) {
+            (None, _) => errors.push(FieldError::new(
+                FIELD_STA_GATEWAY,
+                "The gateway is required with a static configuration",
+            )),
+            (Some(gateway), Some(ip)) if gateway == ip => errors.push(FieldError::new(
+                FIELD_STA_GATEWAY,
+                "The gateway must differ from the address",
+            )),
+            (Some(gateway), Some(ip)) if !same_subnet(gateway, ip, prefix_len) => {
+                errors.push(FieldError::new(
+                    FIELD_STA_GATEWAY,
+                    "The gateway must be in the subnet of the address",
+                ))
+            }
+            _ => (),
+        }
+
+        for (name, dns) in [
+            (FIELD_STA_DNS, sta_ip.dns),
+            (FIELD_STA_SECONDARY_DNS, sta_ip.secondary_dns),
+        ] {
+            if dns.is_some_and(|dns| !is_unicast(dns)) {
+                errors.push(FieldError::new(
+                    name,
+                    "The DNS server must be a unicast address",
+                ));
+            }
+        }
+
+        if sta_ip.dns.is_none() && self.mqtt_server.parse::<Ipv4Addr>().is_err() {
+            errors.push(FieldError::new(
+                FIELD_STA_DNS,
+                "A DNS server is required to resolve the MQTT server name",
+            ));
+        }
+
+        errors
+    }
+
+    /// Writes the changed settings as a single transaction, see
+    /// `config_journal::commit`: the device is never left half configured.
+    pub fn commit<S: ConfigStore>(&self, store: &mut S) -> Result<(), S::Error> {
+        let mut staged = StagedWrites::new();
+        self.write(&mut staged).unwrap_or_else(|e| match e {});
+
+        config_journal::commit(store, staged)
+    }
+
+    fn write<S: ConfigStore>(&self, store: &mut S) -> Result<(), S::Error> {
+        store.set_sta_networks(&self.sta_networks)?;
+        store.set_sta_auth_mode(self.sta_auth_mode)?;
+        store.set_sta_ip_config(&self.sta_ip)?;
+        store.set_ap_ssid(&self.ap_ssid)?;
+        store.set_ap_passphrase(&self.ap_passphrase)?;
+        store.set_ap_auth_mode(self.ap_auth_mode)?;
+        store.set_ap_hidden_ssid(self.ap_hidden_ssid)?;
+        store.set_ap_ip(self.ap_ip)?;
+        store.set_ap_mask(self.ap_mask)?;
+        store.set_ap_channel(self.ap_channel)?;
+        store.set_ap_country(&self.ap_country)?;
+        store.set_ap_max_clients(self.ap_max_clients)?;
+        store.set_ap_client_events(self.ap_client_events)?;
+        store.set_dhcp_reservations(&self.dhcp_reservations)?;
+        store.set_napt(self.napt)?;
+        store.set_port_forwards(&self.port_forwards)?;
+        store.set_mqtt_server(&self.mqtt_server)?;
+        store.set_mqtt_port(self.mqtt_port)?;
+        store.set_mqtt_username(&self.mqtt_username)?;
+        store.set_mqtt_password(&self.mqtt_password)?;
+        store.set_mqtt_tls(self.mqtt_tls)?;
+        store.set_mqtt_ca_cert(&self.mqtt_ca_cert)?;
+        store.set_mqtt_client_id(&self.mqtt_client_id)?;
+        store.set_mqtt_topic_prefix(&self.mqtt_topic_prefix)?;
+        store.set_ha_discovery(self.ha_discovery)?;
+        store.set_sensor_routes(&self.sensor_routes)?;
+        store.set_sensor_credentials(&self.sensor_credentials)?;
+        store.set_sensor_allowlist(&self.sensor_allowlist)?;
+        store.set_sensor_allow_all(self.sensor_allow_all)
+    }
+}
+
+/// Write-only field: the portal only shows whether the secret is set, so an
+/// empty value keeps it and the clear checkbox removes it.
+fn apply_secret(secret: &mut String, value: Option<String>, clear: bool) {
+    if clear {
+        secret.clear();
+    } else if let Some(value) = value.filter(|value| !value.is_empty()) {
+        *secret = value;
+    }
+}
+
+/// WPA2 passphrase: empty (open network) or 8 to 63 printable ASCII characters.
+pub fn validate_passphrase(pass: &str) -> Result<(), &'static str> {
+    if pass.is_empty() {
+        return Ok(());
+    }
+
+    if pass.len() < MIN_PASSPHRASE_LEN || pass.len() > MAX_PASSPHRASE_LEN {
+        return Err("The passphrase length must be between 8 and 63 characters");
+    }
+
+    if !pass.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        return Err("The passphrase must only contain printable ASCII characters");
+    }
+
+    Ok(())
+}
+
+/// Accepts an IPv4 address or a RFC 1123 host name.
+pub fn validate_hostname(host: &str) -> Result<(), &'static str> {
+    if host.is_empty() {
+        return Err("The server address is required");
+    }
+
+    if host.len() > MAX_HOSTNAME_LEN {
+        return Err("The server address is too long");
+    }
+
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return Ok(());
+    }
+
+    let valid_labels = host.trim_end_matches('.').split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    if !valid_labels {
+        return Err("The server address is not a valid host name or IPv4 address");
+    }
+
+    Ok(())
+}
+
+/// Empty (IDF default) or a single RFC 1123 label of up to 30 characters.
+pub fn validate_dhcp_hostname(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Ok(());
+    }
+
+    if name.len() > MAX_DHCP_HOSTNAME_LEN {
+        return Err("The host name maximum length is 30 characters");
+    }
+
+    if name.starts_with('-')
+        || name.ends_with('-')
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err("The host name must only contain letters, digits and inner '-'");
+    }
+
+    Ok(())
+}
+
+/// A unicast address which is neither the network nor the broadcast address
+/// of its `prefix_len` subnet.
+pub fn validate_host_address(ip: Ipv4Addr, prefix_len: u8) -> Result<(), &'static str> {
+    if !is_unicast(ip) {
+        return Err("The address must be a unicast address");
+    }
+
+    let host_mask = u32::MAX.checked_shr(prefix_len as u32).unwrap_or(0);
+
+    if [0, host_mask].contains(&(u32::from(ip) & host_mask)) {
+        return Err("The address is the network or broadcast address of the subnet");
+    }
+
+    Ok(())
+}
+
+fn is_unicast(ip: Ipv4Addr) -> bool {
+    !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || ip.is_loopback())
+}
+
+/// Empty (use the default CA bundle) or one or more PEM certificates.
+pub fn validate_pem_certificate(cert: &str) -> Result<(), &'static str> {
+    if cert.is_empty() {
+        return Ok(());
+    }
+
+    if cert.len() > MAX_CA_CERT_LEN {
+        return Err("The certificate is too large (4000 bytes max)");
+    }
+
+    if !cert.starts_with(PEM_CERT_BEGIN) || !cert.ends_with(PEM_CERT_END) {
+        return Err("The certificate must be PEM encoded");
+    }
+
+    Ok(())
+}
+
+/// Empty (MAC derived default) or up to 64 printable ASCII characters. The ID
+/// is also used in topics, so MQTT separators and wildcards are refused.
+pub fn validate_client_id(id: &str) -> Result<(), &'static str> {
+    if id.len() > MAX_CLIENT_ID_LEN {
+        return Err("The client ID maximum length is 64 characters");
+    }
+
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_graphic() && !matches!(c, '/' | '+' | '#'))
+    {
+        return Err("The client ID must be printable ASCII without spaces, '/', '+' or '#'");
+    }
+
+    Ok(())
+}
+
+/// Empty or a topic start without wildcards, e.g. `site/greenhouse/`.
+pub fn validate_topic_prefix(prefix: &str) -> Result<(), &'static str> {
+    if prefix.len() > MAX_TOPIC_PREFIX_LEN {
+        return Err("The topic prefix maximum length is 64 bytes");
+    }
+
+    if prefix.contains(['+', '#', '\0']) {
+        return Err("The topic prefix must not contain '+' or '#'");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::{FailingStore, MemoryConfigStore, KEY_SENSOR_ROUTES};
+    use crate::dhcp_server::MAX_DHCP_RESERVATIONS;
+    use crate::port_forward::MAX_PORT_FORWARDS;
+    use crate::sensor_allowlist::MAX_ALLOWED_SENSORS;
+    use crate::sensor_auth::{MAX_SENSOR_CREDENTIALS, MAX_SENSOR_SECRET_LEN};
+    use crate::sensor_route::default_routes;
+
+    fn valid_form() -> HashMap<&'static str, String> {
+        HashMap::from([
+            (FIELD_STA_SSIDS[0], "home".to_string()),
+            (FIELD_STA_PASSPHRASES[0], "home passphrase".to_string()),
+            (FIELD_AP_SSID, "Sensors".to_string()),
+            (FIELD_AP_PASSPHRASE, "".to_string()),
+            (FIELD_MQTT_SERVER, " broker.local ".to_string()),
+            (FIELD_MQTT_PORT, "1884".to_string()),
+        ])
+    }
+
+    /// Saves `form` on an empty store, returns the errors and the number of
+    /// writes.
+    fn save(form: &HashMap<&'static str, String>) -> (Vec<FieldError>, usize) {
+        let mut store = FailingStore::new(MemoryConfigStore::new(), usize::MAX);
+        let mut config = ProxyConfig::load(&store);
+        let errors = config
+            .save_form(&mut store, |field| form.get(field).cloned())
+            .unwrap();
+
+        (errors, store.writes)
+    }
+
+    #[test]
+    fn valid_form_is_committed() {
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+        let form = valid_form();
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert_eq!(ProxyConfig::load(&store), config);
+        assert_eq!(store.get_mqtt_server(), "broker.local");
+        assert_eq!(store.get_mqtt_port(), 1884);
+        assert_eq!(store.get_sensor_routes(), default_routes());
+    }
+
+    #[test]
+    fn invalid_fields_are_reported_and_nothing_is_saved() {
+        let cases = [
+            (FIELD_STA_SSIDS[0], "s".repeat(33)),
+            (FIELD_STA_PASSPHRASES[0], "short".to_string()),
+            (FIELD_STA_PASSPHRASES[0], "p".repeat(64)),
+            (FIELD_STA_PASSPHRASES[0], "pass\u{7f}phrase".to_string()),
+            (FIELD_STA_PASSPHRASES[0], "passphrasé".to_string()),
+            (FIELD_AP_SSID, String::new()),
+            (FIELD_AP_SSID, "s".repeat(33)),
+            (FIELD_AP_PASSPHRASE, "short".to_string()),
+            (FIELD_MQTT_SERVER, String::new()),
+            (FIELD_MQTT_SERVER, "-broker.local".to_string()),
+            (FIELD_MQTT_SERVER, "broker..local".to_string()),
+            (FIELD_MQTT_SERVER, "broker_1.local".to_string()),
+            (FIELD_MQTT_SERVER, format!("{}.local", "b".repeat(64))),
+            (FIELD_MQTT_SERVER, "b.".repeat(65)),
+            (FIELD_MQTT_PORT, "0".to_string()),
+            (FIELD_MQTT_PORT, "65536".to_string()),
+            (FIELD_MQTT_PORT, "port".to_string()),
+            (FIELD_SENSOR_ROUTES, "[{".to_string()),
+            (FIELD_SENSOR_ROUTES, "{}".to_string()),
+            (
+                FIELD_SENSOR_ROUTES,
+                r#"[{"path":"x","topic":"t"}]"#.to_string(),
+            ),
+        ];
+
+        for (field, value) in cases {
+            let mut form = valid_form();
+            form.insert(field, value.clone());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [field],
+                "{} = {:?}",
+                field,
+                value
+            );
+            assert_eq!(writes, 0, "{} = {:?}", field, value);
+        }
+    }
+
+    #[test]
+    fn every_error_is_reported_at_once() {
+        let mut form = valid_form();
+        form.insert(FIELD_AP_SSID, String::new());
+        form.insert(FIELD_MQTT_PORT, "port".to_string());
+
+        let (errors, writes) = save(&form);
+
+        assert_eq!(
+            errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+            [FIELD_MQTT_PORT, FIELD_AP_SSID]
+        );
+        assert_eq!(writes, 0);
+    }
+
+    #[test]
+    fn valid_server_addresses() {
+        for host in [
+            "192.168.1.10",
+            "broker",
+            "broker.local.",
+            "mqtt-1.example.org",
+        ] {
+            assert_eq!(validate_hostname(host), Ok(()), "{}", host);
+        }
+    }
+
+    #[test]
+    fn empty_routes_mean_no_route() {
+        let mut form = valid_form();
+        form.insert(FIELD_SENSOR_ROUTES, " \n".to_string());
+
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert!(config.sensor_routes.is_empty());
+        assert_eq!(
+            store.load_blob(KEY_SENSOR_ROUTES).as_deref(),
+            Some(&b"[]"[..])
+        );
+    }
+
+    #[test]
+    fn empty_network_slots_are_dropped() {
+        let mut form = valid_form();
+        form.insert(FIELD_STA_SSIDS[0], String::new());
+        form.insert(FIELD_STA_SSIDS[2], "garage".to_string());
+        form.insert(FIELD_STA_PASSPHRASES[2], "garage passphrase".to_string());
+
+        let mut config = ProxyConfig::load(&MemoryConfigStore::new());
+        assert!(config
+            .apply_form(|field| form.get(field).cloned())
+            .is_empty());
+
+        assert_eq!(
+            config.sta_networks,
+            [StaNetwork::new("garage", "garage passphrase")]
+        );
+    }
+
+    #[test]
+    fn duplicate_networks_are_refused() {
+        let mut form = valid_form();
+        form.insert(FIELD_STA_SSIDS[1], "home".to_string());
+        form.insert(FIELD_STA_PASSPHRASES[1], "other passphrase".to_string());
+
+        let (errors, writes) = save(&form);
+
+        assert_eq!(
+            errors,
+            [FieldError::new(
+                FIELD_STA_SSIDS[1],
+                "This network is already in the list"
+            )]
+        );
+        assert_eq!(writes, 0);
+    }
+
+    fn static_form() -> HashMap<&'static str, String> {
+        let mut form = valid_form();
+        form.extend([
+            (FIELD_STA_STATIC_IP, "on".to_string()),
+            (FIELD_STA_IP, "192.168.1.20".to_string()),
+            (FIELD_STA_MASK, "255.255.255.0".to_string()),
+            (FIELD_STA_GATEWAY, "192.168.1.1".to_string()),
+            (FIELD_STA_DNS, "192.168.1.1".to_string()),
+        ]);
+        form
+    }
+
+    #[test]
+    fn static_addressing_is_committed() {
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+        let form = static_form();
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert_eq!(store.get_sta_ip_config(), config.sta_ip);
+        assert!(config.sta_ip.static_ip);
+        assert_eq!(config.sta_ip.ip, Some(Ipv4Addr::new(192, 168, 1, 20)));
+    }
+
+    #[test]
+    fn invalid_static_addressing_is_refused() {
+        let cases = [
+            (FIELD_STA_IP, ""),
+            (FIELD_STA_IP, "192.168.1"),
+            (FIELD_STA_IP, "192.168.1.0"),
+            (FIELD_STA_IP, "192.168.1.255"),
+            (FIELD_STA_MASK, ""),
+            (FIELD_STA_MASK, "255.0.255.0"),
+            (FIELD_STA_MASK, "255.255.255.254"),
+            (FIELD_STA_GATEWAY, ""),
+            (FIELD_STA_GATEWAY, "192.168.1.20"),
+            (FIELD_STA_GATEWAY, "192.168.2.1"),
+            (FIELD_STA_DNS, ""),
+            (FIELD_STA_DNS, "255.255.255.255"),
+            (FIELD_STA_SECONDARY_DNS, "127.0.0.1"),
+            (FIELD_STA_HOSTNAME, "-proxy"),
+            (FIELD_STA_HOSTNAME, "proxy.local"),
+            (FIELD_STA_HOSTNAME, "p123456789012345678901234567890"),
+        ];
+
+        for (field, value) in cases {
+            let mut form = static_form();
+            form.insert(field, value.to_string());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [field],
+                "{} = {:?}",
+                field,
+                value
+            );
+            assert_eq!(writes, 0, "{} = {:?}", field, value);
+        }
+    }
+
+    #[test]
+    fn static_address_must_be_unicast() {
+        let mut form = static_form();
+        form.insert(FIELD_STA_IP, "224.0.0.1".to_string());
+        form.insert(FIELD_STA_MASK, "240.0.0.0".to_string());
+        form.insert(FIELD_STA_GATEWAY, "224.0.0.2".to_string());
+
+        assert_eq!(
+            save(&form).0,
+            [FieldError::new(
+                FIELD_STA_IP,
+                "The address must be a unicast address"
+            )]
+        );
+    }
+
+    #[test]
+    fn numeric_server_needs_no_dns() {
+        let mut form = static_form();
+        form.insert(FIELD_STA_DNS, String::new());
+        form.insert(FIELD_MQTT_SERVER, "192.168.1.10".to_string());
+
+        assert_eq!(save(&form).0, []);
+    }
+
+    #[test]
+    fn static_addresses_are_ignored_with_dhcp() {
+        let mut form = static_form();
+        form.remove(FIELD_STA_STATIC_IP);
+        form.insert(FIELD_STA_GATEWAY, "10.0.0.1".to_string());
+
+        assert_eq!(save(&form).0, []);
+    }
+
+    #[test]
+    fn invalid_ap_radio_and_subnet_are_refused() {
+        let cases = [
+            (FIELD_AP_COUNTRY, "XX"),
+            (FIELD_AP_CHANNEL, "12"),
+            (FIELD_AP_CHANNEL, "0"),
+            (FIELD_AP_CHANNEL, "one"),
+            (FIELD_AP_MAX_CLIENTS, "0"),
+            (FIELD_AP_MAX_CLIENTS, "11"),
+            (FIELD_AP_IP, ""),
+            (FIELD_AP_IP, "192.168.70.0"),
+            (FIELD_AP_IP, "192.168.70.255"),
+            (FIELD_AP_IP, "127.0.0.1"),
+            (FIELD_AP_IP, "192.168.1.1"),
+            (FIELD_AP_MASK, "255.255.255.128"),
+            (FIELD_AP_MASK, "254.0.0.0"),
+            (FIELD_AP_MASK, "255.0.255.0"),
+        ];
+
+        for (field, value) in cases {
+            let mut form = static_form();
+            form.insert(FIELD_AP_COUNTRY, "US".to_string());
+            form.insert(field, value.to_string());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [field],
+                "{} = {:?}",
+                field,
+                value
+            );
+            assert_eq!(writes, 0, "{} = {:?}", field, value);
+        }
+    }
+
+    #[test]
+    fn ap_settings_are_committed() {
+        let mut form = valid_form();
+        form.extend([
+            (FIELD_AP_IP, "10.1.0.1".to_string()),
+            (FIELD_AP_MASK, "255.255.0.0".to_string()),
+            (FIELD_AP_CHANNEL, "13".to_string()),
+            (FIELD_AP_COUNTRY, " de ".to_string()),
+            (FIELD_AP_MAX_CLIENTS, "4".to_string()),
+        ]);
+
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert_eq!(store.get_ap_ip(), Ipv4Addr::new(10, 1, 0, 1));
+        assert_eq!(store.get_ap_mask(), Ipv4Addr::new(255, 255, 0, 0));
+        assert_eq!(store.get_ap_channel(), 13);
+        assert_eq!(store.get_ap_country(), "DE");
+        assert_eq!(store.get_ap_max_clients(), 4);
+    }
+
+    #[test]
+    fn auth_modes_are_checked_against_the_passphrases() {
+        let cases = [
+            (FIELD_STA_AUTH_MODE, "wep", FIELD_STA_AUTH_MODE),
+            (FIELD_STA_AUTH_MODE, "open", FIELD_STA_PASSPHRASES[0]),
+            (FIELD_AP_AUTH_MODE, "wpa3", FIELD_AP_AUTH_MODE),
+        ];
+
+        for (field, value, error_field) in cases {
+            let mut form = valid_form();
+            form.insert(field, value.to_string());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [error_field],
+                "{} = {:?}",
+                field,
+                value
+            );
+            assert_eq!(writes, 0);
+        }
+
+        let mut form = valid_form();
+        form.insert(FIELD_STA_AUTH_MODE, "wpa2wpa3".to_string());
+        form.insert(FIELD_AP_AUTH_MODE, "open".to_string());
+        assert_eq!(save(&form).0, []);
+    }
+
+    #[test]
+    fn invalid_port_forwards_are_refused() {
+        for forwards in [
+            r#"[{"proto":"tcp","port":8080,"to":"192.168.70.1"}]"#,
+            r#"[{"proto":"tcp","port":8080,"to":"192.168.71.2"}]"#,
+            r#"[{"proto":"tcp","port":8080,"to":"192.168.70.255"}]"#,
+            r#"[{"proto":"tcp","port":80,"to":"192.168.70.2"}]"#,
+            r#"[{"proto":"udp","port":53,"to":"192.168.70.2"},
+                {"proto":"udp","port":53,"to":"192.168.70.3"}]"#,
+            r#"[{"proto":"icmp","port":1,"to":"192.168.70.2"}]"#,
+            r#"[{"proto":"tcp","port":0,"to":"192.168.70.2"}]"#,
+            r#"[{"proto":"tcp","port":22,"to":"192.168.70.2","to_port":70000}]"#,
+            r#"[{"proto":"tcp","port":22,"to":"client"}]"#,
+            "{}",
+        ] {
+            let mut form = valid_form();
+            form.insert(FIELD_PORT_FORWARDS, forwards.to_string());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [FIELD_PORT_FORWARDS],
+                "{}",
+                forwards
+            );
+            assert_eq!(writes, 0, "{}", forwards);
+        }
+    }
+
+    #[test]
+    fn port_forwards_are_committed() {
+        let mut form = valid_form();
+        form.insert(FIELD_NAPT, "on".to_string());
+        form.insert(
+            FIELD_PORT_FORWARDS,
+            r#"[{"proto":"udp","port":80,"to":"192.168.70.2"},
+                {"proto":"TCP","port":2222,"to":"192.168.70.3","to_port":22}]"#
+                .to_string(),
+        );
+
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert!(store.get_napt());
+        assert_eq!(
+            store.get_port_forwards(),
+            [
+                PortForward::new(Protocol::Udp, 80, Ipv4Addr::new(192, 168, 70, 2), 80),
+                PortForward::new(Protocol::Tcp, 2222, Ipv4Addr::new(192, 168, 70, 3), 22),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_dhcp_reservations_are_refused() {
+        for reservations in [
+            r#"[{"mac":"01:00:5e:00:00:01","ip":"192.168.70.2"}]"#,
+            r#"[{"mac":"00:00:00:00:00:00","ip":"192.168.70.2"}]"#,
+            r#"[{"mac":"24:0a:c4:00:00:01","ip":"192.168.70.1"}]"#,
+            r#"[{"mac":"24:0a:c4:00:00:01","ip":"192.168.70.255"}]"#,
+            r#"[{"mac":"24:0a:c4:00:00:01","ip":"192.168.71.2"}]"#,
+            r#"[{"mac":"24:0a:c4:00:00:01","ip":"192.168.70.2"},
+                {"mac":"24:0a:c4:00:00:01","ip":"192.168.70.3"}]"#,
+            r#"[{"mac":"24:0a:c4:00:00:01","ip":"192.168.70.2"},
+                {"mac":"24:0a:c4:00:00:02","ip":"192.168.70.2"}]"#,
+            r#"[{"mac":"24:0a:c4","ip":"192.168.70.2"}]"#,
+        ] {
+            let mut form = valid_form();
+            form.insert(FIELD_DHCP_RESERVATIONS, reservations.to_string());
+
+            let (errors, writes) = save(&form);
+
+            assert_eq!(
+                errors.iter().map(|e| e.field).collect::<Vec<_>>(),
+                [FIELD_DHCP_RESERVATIONS],
+                "{}",
+                reservations
+            );
+            assert_eq!(writes, 0, "{}", reservations);
+        }
+    }
+
+    #[test]
+    fn dhcp_reservations_are_committed() {
+        let mut form = valid_form();
+        form.insert(
+            FIELD_DHCP_RESERVATIONS,
+            r#"[{"mac":"24-0A-C4-00-00-01","ip":"192.168.70.20"}]"#.to_string(),
+        );
+
+        let mut store = MemoryConfigStore::new();
+        let mut config = ProxyConfig::load(&store);
+
+        assert_eq!(
+            config.save_form(&mut store, |field| form.get(field).cloned()),
+            Ok(Vec::new())
+        );
+        assert_eq!(
+            store.get_dhcp_reservations(),
+            [DhcpReservation::new(
+                [0x24, 0x0a, 0xc4, 0, 0, 1],
+                Ipv4Addr::new(192, 168, 70, 20)
+            )]
+        );
+    }
+
+    /// Every list full and every value at its longest.
+    fn largest_config() -> ProxyConfig {
+        let mut config = ProxyConfig::load(&MemoryConfigStore::new());
+        let id = |i: usize| format!("{:0>64}", i);
+
+        config.sta_networks = (0..MAX_STA_NETWORKS)
+            .map(|i| StaNetwork::new(&format!("{:0>32}", i), &"p".repeat(MAX_PASSPHRASE_LEN)))
+            .collect();
+        config.mqtt_server = "mqtt.example.org".to_string();
+        config.mqtt_ca_cert = format!(
+            "{}\n{}\n{}",
+            PEM_CERT_BEGIN,
+            "A".repeat(MAX_CA_CERT_LEN - PEM_CERT_BEGIN.len() - PEM_CERT_END.len() - 2),
+            PEM_CERT_END
+        );
+        config.sensor_credentials = (0..MAX_SENSOR_CREDENTIALS)
+            .map(|i| SensorCredential::new(&id(i), &"s".repeat(MAX_SENSOR_SECRET_LEN)))
+            .collect();
+        config.sensor_allowlist = (0..MAX_ALLOWED_SENSORS).map(id).collect();
+        config.dhcp_reservations = (0..MAX_DHCP_RESERVATIONS as u8)
+            .map(|i| DhcpReservation::new([2, 0, 0, 0, 0, i], Ipv4Addr::new(192, 168, 70, 10 + i)))
+            .collect();
+        config.port_forwards = (0..MAX_PORT_FORWARDS as u16)
+            .map(|i| PortForward::new(Protocol::Tcp, 8000 + i, Ipv4Addr::new(192, 168, 70, 10), 80))
+            .collect();
+        config
+    }
+
+    #[test]
+    fn largest_config_fits_the_storage() {
+        let config = largest_config();
+
+        assert_eq!(config.validate(), []);
+    }
+
+    #[test]
+    fn oversized_config_points_at_the_largest_setting() {
+        let mut config = largest_config();
+        config.sensor_routes = Vec::new();
+        config.mqtt_ca_cert = "A".repeat(MAX_STORED_SIZE / 2);
+
+        assert_eq!(config.oversized_field(), Some(FIELD_MQTT_CA_CERT));
+        assert!(config.validate().contains(&FieldError::new(
+            FIELD_MQTT_CA_CERT,
+            "The settings do not fit in the storage, shorten this one"
+        )));
+    }
+
+    #[test]
+    fn allowlist_is_counted_full() {
+        let mut config = largest_config();
+        config.sensor_allowlist.clear();
+        // Fits as stored, not once enrolment has filled the allowlist.
+        config.mqtt_ca_cert = "A".repeat(13_000);
+
+        assert_eq!(config.oversized_field(), Some(FIELD_MQTT_CA_CERT));
+    }
+}
@@ -0,0 +1,107 @@
+/// 2.4 GHz channels allowed in a regulatory domain.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Country {
+    /// ISO 3166-1 alpha-2 code, `01` being the world safe mode.
+    pub code: &'static str,
+    pub name: &'static str,
+    pub first_channel: u8,
+    pub channel_count: u8,
+}
+
+impl Country {
+    const fn new(code: &'static str, name: &'static str, channel_count: u8) -> Self {
+        Self {
+            code,
+            name,
+            first_channel: 1,
+            channel_count,
+        }
+    }
+
+    pub fn last_channel(&self) -> u8 {
+        self.first_channel + self.channel_count - 1
+    }
+
+    pub fn allows_channel(&self, channel: u8) -> bool {
+        (self.first_channel..=self.last_channel()).contains(&channel)
+    }
+}
+
+/// Countries the AP can be configured for. Channel 14 is only allowed in
+/// Japan, and only for 802.11b, so it is left out everywhere.
+pub const COUNTRIES: &[Country] = &[
+    Country::new("01", "World (safe mode)", 11),
+    Country::new("AT", "Austria", 13),
+    Country::new("AU", "Australia", 13),
+    Country::new("BE", "Belgium", 13),
+    Country::new("BR", "Brazil", 13),
+    Country::new("CA", "Canada", 11),
+    Country::new("CH", "Switzerland", 13),
+    Country::new("CN", "China", 13),
+    Country::new("DE", "Germany", 13),
+    Country::new("DK", "Denmark", 13),
+    Country::new("ES", "Spain", 13),
+    Country::new("FI", "Finland", 13),
+    Country::new("FR", "France", 13),
+    Country::new("GB", "United Kingdom", 13),
+    Country::new("IE", "Ireland", 13),
+    Country::new("IN", "India", 13),
+    Country::new("IT", "Italy", 13),
+    Country::new("JP", "Japan", 13),
+    Country::new("KR", "South Korea", 13),
+    Country::new("LU", "Luxembourg", 13),
+    Country::new("MX", "Mexico", 11),
+    Country::new("NL", "Netherlands", 13),
+    Country::new("NO", "Norway", 13),
+    Country::new("NZ", "New Zealand", 13),
+    Country::new("PL", "Poland", 13),
+    Country::new("PT", "Portugal", 13),
+    Country::new("SE", "Sweden", 13),
+    Country::new("TW", "Taiwan", 11),
+    Country::new("US", "United States", 11),
+    Country::new("ZA", "South Africa", 13),
+];
+
+pub const DEFAULT_COUNTRY: &str = "FR";
+
+pub fn find_country(code: &str) -> Option<&'static Country> {
+    COUNTRIES.iter().find(|country| country.code == code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_follow_the_country() {
+        let us = find_country("US").unwrap();
+        assert!(us.allows_channel(1));
+        assert!(us.allows_channel(11));
+        assert!(!us.allows_channel(12));
+        assert!(!us.allows_channel(0));
+
+        let fr = find_country("FR").unwrap();
+        assert_eq!(fr.last_channel(), 13);
+        assert!(!fr.allows_channel(14));
+    }
+
+    #[test]
+    fn country_table_is_consistent() {
+        assert!(find_country(DEFAULT_COUNTRY).is_some());
+        assert_eq!(find_country("fr"), None);
+        assert_eq!(find_country("XX"), None);
+
+        for (i, country) in COUNTRIES.iter().enumerate() {
+            assert!(
+                COUNTRIES[..i].iter().all(|c| c.code != country.code),
+                "{}",
+                country.code
+            );
+            assert!(
+                (11..=13).contains(&country.last_channel()),
+                "{}",
+                country.code
+            );
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::outbox::OutboxMessage;
+use crate::sensor_auth::{validate_sensor_id, MAX_SENSOR_ID_LEN};
+use crate::string_error::StringError;
+
+pub const MAX_ALLOWED_SENSORS: usize = 64;
+/// Stored size of a full list of plain ids, the enrolment stops there.
+pub const MAX_ALLOWLIST_JSON_LEN: usize = 2 + MAX_ALLOWED_SENSORS * (MAX_SENSOR_ID_LEN + 3);
+/// Opened from the portal or with a short press on the settings button.
+pub const ENROLMENT_TIME: Duration = Duration::from_secs(5 * 60);
+/// An unknown sensor is reported at most once per interval.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Refused readings waiting for the next poll, the others are dropped.
+const MAX_PENDING_REJECTIONS: usize = 16;
+
+pub fn allowlist_from_json(s: &str) -> Result<Vec<String>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Sensor allowlist is not valid JSON"))?;
+
+    let ids = value
+        .as_array()
+        .ok_or(StringError("Sensor allowlist must be a JSON array"))?
+        .iter()
+        .map(|id| {
+            id.as_str()
+                .map(str::to_string)
+                .ok_or(StringError("Sensor ids must be strings"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if ids.len() > MAX_ALLOWED_SENSORS {
+        return Err(StringError("Too many allowed sensors (64 max)"));
+    }
+
+    Ok(ids)
+}
+
+pub fn allowlist_to_json(ids: &[String]) -> String {
+    Value::from(ids).to_string()
+}
+
+pub fn validate_sensor_allowlist(ids: &[String]) -> Result<(), &'static str> {
+    for (i, id) in ids.iter().enumerate() {
+        validate_sensor_id(id)?;
+
+        if ids[..i].contains(id) {
+            return Err("A sensor id is listed twice");
+        }
+    }
+
+    if allowlist_to_json(ids).len() > MAX_ALLOWLIST_JSON_LEN {
+        return Err("Sensor allowlist is too long");
+    }
+
+    Ok(())
+}
+
+/// Reading refused because its sensor is not allowed.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SensorRejection {
+    /// `None` for a reading without `id`.
+    pub id: Option<String>,
+    pub path: String,
+}
+
+impl SensorRejection {
+    /// Not retained, QoS 0, as the AP client events.
+    pub fn to_message(&self, topic: &str) -> OutboxMessage {
+        let payload = json!({
+            "event": "unknown_sensor",
+            "id": self.id,
+            "path": self.path,
+        });
+
+        OutboxMessage::new(topic, 0, payload.to_string().as_bytes())
+    }
+}
+
+/// Sensor ids whose readings are forwarded. An empty list refuses every
+/// sensor until an enrolment fills it, unless `allow_all` is set: then every
+/// sensor is forwarded and the list only grows. Configurations older than the
+/// list are seeded with the sensor credential ids, or allow all when there
+/// are none, see `migration`.
+///
+/// While the enrolment window is open, the readings of unknown sensors are
+/// accepted and their ids added; the caller saves the list, see
+/// `take_enrolled`. Outside, they are refused and queued for the
+/// diagnostics topic, see `take_rejections`.
+#[derive(Clone, Default, Debug)]
+pub struct SensorAllowlist {
+    ids: Vec<String>,
+    allow_all: bool,
+    enrol_until: Option<Duration>,
+    is_changed: bool,
+    rejections: Vec<SensorRejection>,
+    /// Last report of each unknown sensor.
+    reported: HashMap<Option<String>, Duration>,
+}
+
+impl SensorAllowlist {
+    pub fn new(ids: Vec<String>, allow_all: bool) -> Self {
+        Self {
+            ids,
+            allow_all,
+            ..Default::default()
+        }
+    }
+
+    /// No sensor is forwarded until an enrolment.
+    pub fn refuses_all(&self) -> bool {
+        self.ids.is_empty() && !self.allow_all
+    }
+
+    pub fn is_enrolling(&self) -> bool {
+        self.enrol_until.is_some()
+    }
+
+    /// Opens the window, or extends it when already open.
+    pub fn start_enrolment(&mut self, now: Duration) {
+        log::info!("Sensor enrolment open for {}s", ENROLMENT_TIME.as_secs());
+        self.enrol_until = Some(now + ENROLMENT_TIME);
+    }
+
+    /// Closes the window once its time is over.
+    pub fn expire_enrolment(&mut self, now: Duration) {
+        if self.enrol_until.is_some_and(|until| until <= now) {
+            log::info!(
+                "Sensor enrolment closed, {} sensor(s) allowed",
+                self.ids.len()
+            );
+            self.enrol_until = None;
+        }
+    }
+
+    /// `id` is the one of the reading body, `path` the route it was sent to.
+    pub fn check(&mut self, id: Option<&str>, path: &str) -> Result<(), &'static str> {
+        if id.is_some_and(|id| self.ids.iter().any(|allowed| allowed == id)) {
+            return Ok(());
+        }
+
+        match id {
+            Some(id) if self.is_enrolling() => {
+                validate_sensor_id(id)?;
+
+                let mut ids = self.ids.clone();
+                ids.push(id.to_string());
+
+                if ids.len() > MAX_ALLOWED_SENSORS
+                    || allowlist_to_json(&ids).len() > MAX_ALLOWLIST_JSON_LEN
+                {
+                    return Err("Sensor allowlist is full");
+                }
+
+                log::info!("Sensor {} enrolled", id);
+                self.ids = ids;
+                self.is_changed = true;
+                Ok(())
+            }
+            _ if self.allow_all => Ok(()),
+            _ => {
+                let rejection = SensorRejection {
+                    id: id.map(str::to_string),
+                    path: path.to_string(),
+                };
+
+                if self.rejections.len() < MAX_PENDING_REJECTIONS
+                    && !self.rejections.contains(&rejection)
+                {
+                    self.rejections.push(rejection);
+                }
+
+                Err("Unknown sensor")
+            }
+        }
+    }
+
+    /// The whole list to save, when sensors were enrolled since the last
+    /// call.
+    pub fn take_enrolled(&mut self) -> Option<Vec<String>> {
+        std::mem::take(&mut self.is_changed).then(|| self.ids.clone())
+    }
+
+    /// Refused readings to report, at most one per sensor and interval.
+    pub fn take_rejections(&mut self, now: Duration) -> Vec<SensorRejection> {
+        self.reported
+            .retain(|_, reported| now < *reported + REPORT_INTERVAL);
+
+        let mut rejections = std::mem::take(&mut self.rejections);
+        rejections.retain(|rejection| {
+            if self.reported.contains_key(&rejection.id)
+                || self.reported.len() >= MAX_ALLOWED_SENSORS
+            {
+                return false;
+            }
+
+            self.reported.insert(rejection.id.clone(), now);
+            true
+        });
+
+        rejections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_list_refuses_unknown_sensors() {
+        let mut allowlist = SensorAllowlist::new(Vec::new(), false);
+
+        assert!(allowlist.refuses_all());
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Err("Unknown sensor"));
+        assert_eq!(allowlist.check(None, "/t"), Err("Unknown sensor"));
+        assert_eq!(allowlist.take_enrolled(), None);
+    }
+
+    #[test]
+    fn listed_sensors_are_accepted() {
+        let mut allowlist = SensorAllowlist::new(vec!["garden".to_string()], false);
+
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Ok(()));
+        assert_eq!(allowlist.check(Some("attic"), "/t"), Err("Unknown sensor"));
+    }
+
+    #[test]
+    fn allow_all_accepts_unknown_sensors() {
+        let mut allowlist = SensorAllowlist::new(Vec::new(), true);
+
+        assert!(!allowlist.refuses_all());
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Ok(()));
+        assert_eq!(allowlist.check(None, "/t"), Ok(()));
+        assert_eq!(allowlist.take_enrolled(), None);
+        assert!(allowlist
+            .take_rejections(Duration::from_secs(10))
+            .is_empty());
+
+        // The enrolment still fills the list, for when `allow_all` is unset.
+        allowlist.start_enrolment(Duration::from_secs(100));
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Ok(()));
+        assert_eq!(allowlist.take_enrolled(), Some(vec!["garden".to_string()]));
+    }
+
+    #[test]
+    fn enrolment_adds_new_sensors_until_it_expires() {
+        let mut allowlist = SensorAllowlist::new(Vec::new(), false);
+        allowlist.start_enrolment(Duration::from_secs(100));
+
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Ok(()));
+        assert_eq!(allowlist.check(None, "/t"), Err("Unknown sensor"));
+        assert_eq!(allowlist.take_enrolled(), Some(vec!["garden".to_string()]));
+
+        allowlist.expire_enrolment(Duration::from_secs(100) + ENROLMENT_TIME);
+
+        assert!(!allowlist.is_enrolling());
+        assert_eq!(allowlist.check(Some("garden"), "/t"), Ok(()));
+        assert_eq!(allowlist.check(Some("attic"), "/t"), Err("Unknown sensor"));
+    }
+
+    #[test]
+    fn rejections_are_reported_once_per_interval() {
+        let mut allowlist = SensorAllowlist::new(Vec::new(), false);
+        let _ = allowlist.check(Some("attic"), "/t");
+        let _ = allowlist.check(Some("attic"), "/t");
+
+        assert_eq!(allowlist.take_rejections(Duration::from_secs(10)).len(), 1);
+
+        let _ = allowlist.check(Some("attic"), "/t");
+        assert!(allowlist
+            .take_rejections(Duration::from_secs(20))
+            .is_empty());
+
+        let _ = allowlist.check(Some("attic"), "/t");
+        assert_eq!(
+            allowlist
+                .take_rejections(Duration::from_secs(10) + REPORT_INTERVAL)
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+
+use crate::config::ConfigStore;
+use crate::hex::{constant_time_eq, from_hex, to_hex};
+use crate::string_error::StringError;
+
+/// Last nonce of each signed sensor, by `nonce_key`.
+pub const KEY_SENSOR_NONCES: &str = "SENSNONCE";
+/// The last nonces are saved at most this often, to spare the flash.
+pub const NONCE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub const MAX_SENSOR_CREDENTIALS: usize = 32;
+pub const MIN_SENSOR_SECRET_LEN: usize = 16;
+pub const MAX_SENSOR_SECRET_LEN: usize = 64;
+pub(crate) const MAX_SENSOR_ID_LEN: usize = 64;
+
+pub const HEADER_AUTHORIZATION: &str = "Authorization";
+/// Hex HMAC-SHA256 of `<nonce>\n<body>`, keyed with the sensor secret.
+pub const HEADER_SIGNATURE: &str = "X-Signature";
+/// Increases with each reading of a sensor, e.g. a counter or a timestamp.
+pub const HEADER_NONCE: &str = "X-Nonce";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Shared secret of a sensor, by the `id` of its readings.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SensorCredential {
+    pub id: String,
+    pub secret: String,
+}
+
+impl SensorCredential {
+    pub fn new(id: &str, secret: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "secret": self.secret,
+        })
+    }
+
+    /// Without the secret, for the portal.
+    pub fn to_public_json(&self) -> Value {
+        json!({ "id": self.id })
+    }
+
+    fn from_json(value: &Value, current: &[SensorCredential]) -> Result<Self, StringError> {
+        let id = value
+            .get("id")
+            .and_then(Value::as_str)
+            .ok_or(StringError("Sensor credential without id"))?;
+
+        let secret = match value.get("secret") {
+            Some(secret) => secret
+                .as_str()
+                .ok_or(StringError("Sensor secret must be a string"))?,
+            None => current
+                .iter()
+                .find(|credential| credential.id == id)
+                .map(|credential| credential.secret.as_str())
+                .ok_or(StringError("A new sensor credential needs a secret"))?,
+        };
+
+        Ok(Self::new(id, secret))
+    }
+}
+
+pub fn credentials_from_json(s: &str) -> Result<Vec<SensorCredential>, StringError> {
+    credentials_from_form(s, &[])
+}
+
+/// As shown in the portal, secrets are write-only: an entry without `secret`
+/// keeps the current one, a removed entry removes the credential.
+pub fn credentials_from_form(
+    s: &str,
+    current: &[SensorCredential],
+) -> Result<Vec<SensorCredential>, StringError> {
+    let value: Value = serde_json::from_str(s)
+        .map_err(|_| StringError("Sensor credentials are not valid JSON"))?;
+
+    let credentials = value
+        .as_array()
+        .ok_or(StringError("Sensor credentials must be a JSON array"))?
+        .iter()
+        .map(|value| SensorCredential::from_json(value, current))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if credentials.len() > MAX_SENSOR_CREDENTIALS {
+        return Err(StringError("Too many sensor credentials (32 max)"));
+    }
+
+    Ok(credentials)
+}
+
+pub fn credentials_to_json(credentials: &[SensorCredential]) -> String {
+    Value::Array(credentials.iter().map(SensorCredential::to_json).collect()).to_string()
+}
+
+pub fn validate_sensor_credentials(credentials: &[SensorCredential]) -> Result<(), &'static str> {
+    for (i, credential) in credentials.iter().enumerate() {
+        validate_sensor_id(&credential.id)?;
+
+        if credential.secret.len() < MIN_SENSOR_SECRET_LEN
+            || credential.secret.len() > MAX_SENSOR_SECRET_LEN
+            || !credential
+                .secret
+                .chars()
+                .all(|c| c.is_ascii() && !c.is_ascii_control())
+        {
+            return Err("Sensor secrets must be 16 to 64 printable ASCII characters");
+        }
+
+        if credentials[..i].iter().any(|c| c.id == credential.id) {
+            return Err("A sensor id has two credentials");
+        }
+    }
+
+    Ok(())
+}
+
+/// Also used by the sensor allowlist.
+pub(crate) fn validate_sensor_id(id: &str) -> Result<(), &'static str> {
+    if id.is_empty() || id.len() > MAX_SENSOR_ID_LEN || id.contains(['/', '+', '#']) {
+        Err("Sensor ids must be 1 to 64 characters, without '/', '+' or '#'")
+    } else {
+        Ok(())
+    }
+}
+
+/// Stored last nonces, an unreadable blob reads as none.
+pub fn load_sensor_nonces(store: &impl ConfigStore) -> HashMap<String, u64> {
+    let nonces = store.load_blob(KEY_SENSOR_NONCES).unwrap_or_default();
+
+    if nonces.is_empty() {
+        return HashMap::new();
+    }
+
+    let value = serde_json::from_slice::<Value>(&nonces).ok();
+
+    match value.as_ref().and_then(Value::as_object) {
+        Some(nonces) => nonces
+            .iter()
+            .filter_map(|(key, nonce)| Some((key.clone(), nonce.as_u64()?)))
+            .collect(),
+        None => {
+            log::error!("Invalid stored sensor nonces.");
+            HashMap::new()
+        }
+    }
+}
+
+pub fn save_sensor_nonces<S: ConfigStore>(
+    store: &mut S,
+    nonces: &HashMap<String, u64>,
+) -> Result<(), S::Error> {
+    let nonces: Map<String, Value> = nonces
+        .iter()
+        .map(|(key, nonce)| (key.clone(), Value::from(*nonce)))
+        .collect();
+
+    store.save_blob(
+        KEY_SENSOR_NONCES,
+        Value::Object(nonces).to_string().as_bytes(),
+    )
+}
+
+/// Identifies a credential in the stored nonces without its secret, so a new
+/// secret for the same id starts from no nonce again.
+fn nonce_key(credential: &SensorCredential) -> String {
+    let mut mac = HmacSha256::new_from_slice(credential.secret.as_bytes())
+        .expect("HMAC accepts any key length");
+
+    mac.update(b"nonce\n");
+    mac.update(credential.id.as_bytes());
+    to_hex(&mac.finalize().into_bytes()[..8])
+}
+
+/// Authentication headers of a reading request.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct ReadingHeaders {
+    pub authorization: Option<String>,
+    pub signature: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// Value of the `X-Signature` header, for the sensor side.
+pub fn sign_reading(secret: &str, nonce: u64, body: &str) -> String {
+    to_hex(&reading_mac(secret, nonce, body).finalize().into_bytes())
+}
+
+fn reading_mac(secret: &str, nonce: u64, body: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+
+    mac.update(nonce.to_string().as_bytes());
+    mac.update(b"\n");
+    mac.update(body.as_bytes());
+    mac
+}
+
+/// Checks the readings against the credential table. Without credentials
+/// every reading is accepted, as before the table existed.
+///
+/// A reading carries either `Authorization: Bearer <secret>`, or
+/// `X-Nonce` and `X-Signature`. Signed readings cannot be replayed: the
+/// nonce of a sensor must increase. The caller saves the last nonces, see
+/// `take_nonces`, so they survive a restart. Only the readings of the last
+/// `NONCE_SAVE_INTERVAL` before a power loss can be replayed.
+#[derive(Clone, Default, Debug)]
+pub struct SensorAuth {
+    credentials: Vec<SensorCredential>,
+    /// By sensor id.
+    last_nonces: HashMap<String, u64>,
+    is_nonce_changed: bool,
+    nonces_saved: Option<Duration>,
+}
+
+impl SensorAuth {
+    /// `saved_nonces` as stored, see `load_sensor_nonces`. The ones of removed
+    /// credentials are dropped.
+    pub fn new(credentials: Vec<SensorCredential>, saved_nonces: &HashMap<String, u64>) -> Self {
+        let last_nonces = credentials
+            .iter()
+            .filter_map(|credential| {
+                let nonce = saved_nonces.get(&nonce_key(credential))?;
+                Some((credential.id.clone(), *nonce))
+            })
+            .collect();
+
+        Self {
+            credentials,
+            last_nonces,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.credentials.is_empty()
+    }
+
+    /// `id` is the one of the reading body.
+    pub fn check(
+        &mut self,
+        id: Option<&str>,
+        body: &str,
+        headers: &ReadingHeaders,
+    ) -> Result<(), &'static str> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let credential = id
+            .and_then(|id| self.credentials.iter().find(|c| c.id == id))
+            .ok_or("Unknown sensor")?;
+
+        if let Some(signature) = &headers.signature {
+            let nonce = headers
+                .nonce
+                .as_deref()
+                .and_then(|nonce| nonce.trim().parse::<u64>().ok())
+                .ok_or("Missing or invalid nonce")?;
+
+            let signature = from_hex(signature.trim()).ok_or("Invalid signature")?;
+
+            reading_mac(&credential.secret, nonce, body)
+                .verify_slice(&signature)
+                .map_err(|_| "Invalid signature")?;
+
+            let last_nonce = self.last_nonces.get(&credential.id);
+            if last_nonce.is_some_and(|&last| nonce <= last) {
+                return Err("Replayed reading");
+            }
+
+            self.last_nonces.insert(credential.id.clone(), nonce);
+            self.is_nonce_changed = true;
+            return Ok(());
+        }
+
+        let key = headers
+            .authorization
+            .as_deref()
+            .and_then(|value| value.trim().strip_prefix("Bearer "))
+            .ok_or("Unsigned reading")?;
+
+        if constant_time_eq(key.trim().as_bytes(), credential.secret.as_bytes()) {
+            Ok(())
+        } else {
+            Err("Invalid key")
+        }
+    }
+
+    /// The last nonces to save when they changed, at most once per
+    /// `NONCE_SAVE_INTERVAL`.
+    pub fn take_nonces(&mut self, now: Duration) -> Option<HashMap<String, u64>> {
+        if self
+            .nonces_saved
+            .is_some_and(|saved| now < saved + NONCE_SAVE_INTERVAL)
+        {
+            return None;
+        }
+
+        let nonces = self.take_unsaved_nonces()?;
+        self.nonces_saved = Some(now);
+        Some(nonces)
+    }
+
+    /// The last nonces to save when they changed, before the services stop.
+    pub fn take_unsaved_nonces(&mut self) -> Option<HashMap<String, u64>> {
+        if !std::mem::take(&mut self.is_nonce_changed) {
+            return None;
+        }
+
+        let nonces = self
+            .credentials
+            .iter()
+            .filter_map(|credential| {
+                let nonce = self.last_nonces.get(&credential.id)?;
+                Some((nonce_key(credential), *nonce))
+            })
+            .collect();
+
+        Some(nonces)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryConfigStore;
+    use crate::forwarder::Forwarder;
+    use crate::ingest::handle_reading;
+    use crate::mqtt::OfflinePublisher;
+    use crate::outbox::{MemorySpillStorage, Outbox};
+    use crate::sensor_allowlist::SensorAllowlist;
+    use crate::sensor_route::SensorRoute;
+
+    const SECRET: &str = "0123456789abcdef";
+    const BODY: &str = r#"{"id":"garden","t":21.5}"#;
+
+    fn signed(nonce: u64) -> ReadingHeaders {
+        ReadingHeaders {
+            signature: Some(sign_reading(SECRET, nonce, BODY)),
+            nonce: Some(nonce.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn auth(store: &MemoryConfigStore) -> SensorAuth {
+        SensorAuth::new(
+            vec![SensorCredential::new("garden", SECRET)],
+            &load_sensor_nonces(store),
+        )
+    }
+
+    fn bearer(key: &str) -> ReadingHeaders {
+        ReadingHeaders {
+            authorization: Some(format!("Bearer {}", key)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn readings_are_accepted_without_credentials() {
+        let mut auth = SensorAuth::new(Vec::new(), &HashMap::new());
+
+        assert!(!auth.is_enabled());
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &ReadingHeaders::default()),
+            Ok(())
+        );
+        assert_eq!(auth.check(None, BODY, &ReadingHeaders::default()), Ok(()));
+    }
+
+    #[test]
+    fn bearer_key_must_match() {
+        let mut auth = auth(&MemoryConfigStore::new());
+
+        assert_eq!(auth.check(Some("garden"), BODY, &bearer(SECRET)), Ok(()));
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &bearer(" 0123456789abcdef ")),
+            Ok(())
+        );
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &bearer("0123456789abcdeX")),
+            Err("Invalid key")
+        );
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &bearer("0123456789abcde")),
+            Err("Invalid key")
+        );
+        assert_eq!(
+            auth.check(
+                Some("garden"),
+                BODY,
+                &ReadingHeaders {
+                    authorization: Some(format!("Basic {}", SECRET)),
+                    ..Default::default()
+                }
+            ),
+            Err("Unsigned reading")
+        );
+    }
+
+    #[test]
+    fn unauthenticated_readings_are_refused() {
+        let mut auth = auth(&MemoryConfigStore::new());
+
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &ReadingHeaders::default()),
+            Err("Unsigned reading")
+        );
+        assert_eq!(
+            auth.check(Some("shed"), BODY, &bearer(SECRET)),
+            Err("Unknown sensor")
+        );
+        assert_eq!(
+            auth.check(None, BODY, &bearer(SECRET)),
+            Err("Unknown sensor")
+        );
+    }
+
+    #[test]
+    fn signature_must_match_the_body_and_nonce() {
+        let mut auth = auth(&MemoryConfigStore::new());
+        let mut headers = signed(5);
+
+        assert_eq!(
+            auth.check(Some("garden"), r#"{"id":"garden","t":30}"#, &headers),
+            Err("Invalid signature")
+        );
+
+        headers.nonce = Some("6".to_string());
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &headers),
+            Err("Invalid signature")
+        );
+
+        headers.nonce = None;
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &headers),
+            Err("Missing or invalid nonce")
+        );
+
+        headers.nonce = Some("5".to_string());
+        headers.signature = Some("not hex".to_string());
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &headers),
+            Err("Invalid signature")
+        );
+
+        headers.signature = Some(sign_reading("fedcba9876543210", 5, BODY));
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &headers),
+            Err("Invalid signature")
+        );
+
+        assert_eq!(auth.check(Some("garden"), BODY, &signed(5)), Ok(()));
+    }
+
+    #[test]
+    fn refused_readings_get_401_and_are_not_forwarded() {
+        let route = SensorRoute::new("/garden", &["t"], "sensors/garden");
+        let mut auth = auth(&MemoryConfigStore::new());
+        let mut allowlist = SensorAllowlist::new(vec!["garden".to_string()], false);
+        let mut forwarder =
+            Forwarder::new(OfflinePublisher, Outbox::new(4, MemorySpillStorage::new(4)));
+
+        let response = handle_reading(
+            &route,
+            BODY,
+            &bearer("fedcba9876543210"),
+            &mut auth,
+            &mut allowlist,
+            &mut forwarder,
+            None,
+        );
+        assert_eq!(
+            (response.status, response.body.as_str()),
+            (401, "Invalid key")
+        );
+        assert_eq!(forwarder.queued(), 0);
+
+        let response = handle_reading(
+            &route,
+            BODY,
+            &bearer(SECRET),
+            &mut auth,
+            &mut allowlist,
+            &mut forwarder,
+            None,
+        );
+        assert_eq!(response.status, 202);
+        assert_eq!(forwarder.queued(), 1);
+    }
+
+    #[test]
+    fn signed_readings_cannot_be_replayed() {
+        let mut auth = auth(&MemoryConfigStore::new());
+
+        assert_eq!(auth.check(Some("garden"), BODY, &signed(5)), Ok(()));
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &signed(5)),
+            Err("Replayed reading")
+        );
+        assert_eq!(
+            auth.check(Some("garden"), BODY, &signed(4)),
+            Err("Replayed reading")
+        );
+        assert_eq!(auth.check(Some("garden"), BODY, &signed(6)), Ok(()));
+    }
+
+    #[test]
+    fn saved_nonces_survive_a_restart() {
+        let mut store = MemoryConfigStore::new();
+        let mut before = auth(&store);
+        before.check(Some("garden"), BODY, &signed(5)).unwrap();
+        save_sensor_nonces(&mut store, &before.take_unsaved_nonces().unwrap()).unwrap();
+
+        let mut after = auth(&store);
+
+        assert_eq!(
+            after.check(Some("garden"), BODY, &signed(5)),
+            Err("Replayed reading")
+        );
+        assert_eq!(after.check(Some("garden"), BODY, &signed(6)), Ok(()));
+    }
+
+    #[test]
+    fn new_secret_starts_without_nonce() {
+        let mut store = MemoryConfigStore::new();
+        let mut before = auth(&store);
+        before.check(Some("garden"), BODY, &signed(5)).unwrap();
+        save_sensor_nonces(&mut store, &before.take_unsaved_nonces().unwrap()).unwrap();
+
+        let secret = "fedcba9876543210";
+        let mut after = SensorAuth::new(
+            vec![SensorCredential::new("garden", secret)],
+            &load_sensor_nonces(&store),
+        );
+        let headers = ReadingHeaders {
+            signature: Some(sign_reading(secret, 1, BODY)),
+            nonce: Some("1".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(after.check(Some("garden"), BODY, &headers), Ok(()));
+    }
+
+    #[test]
+    fn nonces_are_saved_at_most_once_per_interval() {
+        let mut auth = auth(&MemoryConfigStore::new());
+        let now = Duration::from_secs(100);
+
+        assert_eq!(auth.take_nonces(now), None);
+
+        auth.check(Some("garden"), BODY, &signed(1)).unwrap();
+        assert!(auth.take_nonces(now).is_some());
+
+        auth.check(Some("garden"), BODY, &signed(2)).unwrap();
+        assert_eq!(auth.take_nonces(now + Duration::from_secs(59)), None);
+        assert!(auth.take_nonces(now + NONCE_SAVE_INTERVAL).is_some());
+        assert_eq!(auth.take_nonces(now + 2 * NONCE_SAVE_INTERVAL), None);
+    }
+
+    #[test]
+    fn invalid_stored_nonces_read_as_none() {
+        let mut store = MemoryConfigStore::new();
+        store.save_blob(KEY_SENSOR_NONCES, b"[1]").unwrap();
+
+        assert!(load_sensor_nonces(&store).is_empty());
+    }
+}
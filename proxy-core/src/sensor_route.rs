@@ -0,0 +1,422 @@
+use serde_json::{json, Map, Value};
+
+use crate::discovery::DiscoveryEntity;
+use crate::outbox::OutboxMessage;
+use crate::string_error::StringError;
+
+pub const MAX_SENSOR_ROUTES: usize = 16;
+
+const JSON_MANDATORY_KEYS: &[&str] = &["id"];
+const TOPIC_ID_PLACEHOLDER: &str = "{id}";
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SensorRoute {
+    pub path: String,
+    pub required_keys: Vec<String>,
+    /// Pairs of (request key, published key).
+    pub renames: Vec<(String, String)>,
+    /// Topic template, `{id}` is replaced by the sensor ID.
+    pub topic: String,
+    pub qos: u8,
+    /// Home Assistant entities announced for each new sensor ID.
+    pub discovery: Vec<DiscoveryEntity>,
+}
+
+impl SensorRoute {
+    pub fn new(path: &str, required_keys: &[&str], topic: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            required_keys: required_keys.iter().map(|k| k.to_string()).collect(),
+            renames: Vec::new(),
+            topic: topic.to_string(),
+            qos: 1,
+            discovery: Vec::new(),
+        }
+    }
+
+    pub fn with_rename(mut self, from: &str, to: &str) -> Self {
+        self.renames.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    pub fn with_discovery(mut self, entity: DiscoveryEntity) -> Self {
+        self.discovery.push(entity);
+        self
+    }
+
+    pub fn with_topic_prefix(mut self, prefix: &str) -> Self {
+        self.topic.insert_str(0, prefix);
+        self
+    }
+
+    pub fn build_message(&self, json: &Map<String, Value>) -> Result<OutboxMessage, &'static str> {
+        if !JSON_MANDATORY_KEYS.iter().all(|&k| json.contains_key(k))
+            || !self.required_keys.iter().all(|k| json.contains_key(k))
+        {
+            return Err("Missing keys");
+        }
+
+        let id = json["id"].as_str().ok_or("Bad ID")?;
+
+        if id.is_empty() || id.contains(['/', '+', '#']) {
+            return Err("Bad ID");
+        }
+
+        let mut payload = Map::new();
+
+        for key in &self.required_keys {
+            payload.insert(self.published_key(key).to_string(), json[key].clone());
+        }
+
+        Ok(OutboxMessage::new(
+            &self.topic.replace(TOPIC_ID_PLACEHOLDER, id),
+            self.qos,
+            Value::Object(payload).to_string().as_bytes(),
+        ))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let renames: Map<String, Value> = self
+            .renames
+            .iter()
+            .map(|(from, to)| (from.clone(), Value::String(to.clone())))
+            .collect();
+
+        json!({
+            "path": self.path,
+            "keys": self.required_keys,
+            "rename": renames,
+            "topic": self.topic,
+            "qos": self.qos,
+            "discovery": self
+                .discovery
+                .iter()
+                .map(DiscoveryEntity::to_json)
+                .collect::<Vec<_>>()
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, StringError> {
+        let obj = value
+            .as_object()
+            .ok_or(StringError("Sensor route must be an object"))?;
+
+        let path = obj
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or(StringError("Sensor route without path"))?;
+
+        let topic = obj
+            .get("topic")
+            .and_then(Value::as_str)
+            .ok_or(StringError("Sensor route without topic"))?;
+
+        let required_keys = match obj.get("keys") {
+            None => Vec::new(),
+            Some(Value::Array(keys)) => keys
+                .iter()
+                .map(|k| k.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(StringError("Sensor route keys must be strings"))?,
+            Some(_) => return Err(StringError("Sensor route keys must be an array")),
+        };
+
+        let renames = match obj.get("rename") {
+            None => Vec::new(),
+            Some(Value::Object(renames)) => renames
+                .iter()
+                .map(|(from, to)| to.as_str().map(|to| (from.clone(), to.to_string())))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(StringError("Sensor route renames must be strings"))?,
+            Some(_) => return Err(StringError("Sensor route rename must be an object")),
+        };
+
+        let qos = match obj.get("qos") {
+            None => 1,
+            Some(qos) => {
+                qos.as_u64()
+                    .filter(|&q| q <= 2)
+                    .ok_or(StringError("Sensor route QoS must be 0, 1 or 2"))? as u8
+            }
+        };
+
+        let discovery = match obj.get("discovery") {
+            None => Vec::new(),
+            Some(Value::Array(entities)) => entities
+                .iter()
+                .map(DiscoveryEntity::from_json)
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(_) => return Err(StringError("Sensor route discovery must be an array")),
+        };
+
+        let route = Self {
+            path: path.to_string(),
+            required_keys,
+            renames,
+            topic: topic.to_string(),
+            qos,
+            discovery,
+        };
+
+        route.validate()?;
+        Ok(route)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    fn validate(&self) -> Result<(), StringError> {
+        if !self.path.starts_with('/') || self.path.len() < 2 || self.path.contains(['?', '#', '*'])
+        {
+            return Err(StringError("Sensor route path must look like /send_xxx"));
+        }
+
+        if self.topic.is_empty() || self.topic.contains(['+', '#']) {
+            return Err(StringError("Sensor route topic is invalid"));
+        }
+
+        if !self.discovery.iter().all(|entity| {
+            self.required_keys
+                .iter()
+                .any(|key| self.published_key(key) == entity.key)
+        }) {
+            return Err(StringError(
+                "Sensor route discovery keys must be published keys",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn published_key<'a>(&'a self, key: &'a str) -> &'a str {
+        self.renames
+            .iter()
+            .find(|(from, _)| from == key)
+            .map(|(_, to)| to.as_str())
+            .unwrap_or(key)
+    }
+}
+
+pub fn default_routes() -> Vec<SensorRoute> {
+    vec![
+        SensorRoute::new(
+            "/send_soil_moisture",
+            &["level", "battery"],
+            "sensor/soil_moisture/{id}",
+        )
+        .with_discovery(
+            DiscoveryEntity::new("level", "Moisture")
+                .with_device_class("moisture")
+                .with_unit("%"),
+        )
+        .with_discovery(battery_entity()),
+        SensorRoute::new(
+            "/send_water_level",
+            &["level", "measure", "battery"],
+            "sensor/water_level/{id}",
+        )
+        .with_rename("measure", "raw")
+        .with_discovery(DiscoveryEntity::new("level", "Level").with_unit("%"))
+        .with_discovery(DiscoveryEntity::new("raw", "Raw measure").diagnostic())
+        .with_discovery(battery_entity()),
+    ]
+}
+
+fn battery_entity() -> DiscoveryEntity {
+    DiscoveryEntity::new("battery", "Battery")
+        .with_device_class("battery")
+        .with_unit("%")
+        .diagnostic()
+}
+
+pub fn routes_from_json(s: &str) -> Result<Vec<SensorRoute>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Sensor routes are not valid JSON"))?;
+
+    let routes = value
+        .as_array()
+        .ok_or(StringError("Sensor routes must be a JSON array"))?
+        .iter()
+        .map(SensorRoute::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if routes.len() > MAX_SENSOR_ROUTES {
+        return Err(StringError("Too many sensor routes"));
+    }
+
+    for (i, route) in routes.iter().enumerate() {
+        if routes[..i].iter().any(|r| r.path == route.path) {
+            return Err(StringError("Duplicate sensor route path"));
+        }
+    }
+
+    Ok(routes)
+}
+
+pub fn routes_to_json(routes: &[SensorRoute]) -> String {
+    Value::Array(routes.iter().map(SensorRoute::to_json).collect()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(s: &str) -> Map<String, Value> {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn route_json(path: &str) -> Value {
+        json!({ "path": path, "keys": ["t"], "topic": "probe/{id}" })
+    }
+
+    #[test]
+    fn route_round_trips_as_json() {
+        let route = SensorRoute::new("/probe", &["t", "h"], "probe/{id}").with_rename("t", "temp");
+
+        assert_eq!(SensorRoute::from_json(&route.to_json()), Ok(route));
+        assert_eq!(
+            routes_from_json(&routes_to_json(&default_routes())),
+            Ok(default_routes())
+        );
+    }
+
+    #[test]
+    fn optional_fields_default() {
+        let route = SensorRoute::from_json(&json!({ "path": "/probe", "topic": "probe" })).unwrap();
+
+        assert!(route.required_keys.is_empty());
+        assert!(route.renames.is_empty());
+        assert_eq!(route.qos, 1);
+    }
+
+    #[test]
+    fn invalid_routes_are_refused() {
+        for (value, error) in [
+            (json!("/probe"), "Sensor route must be an object"),
+            (json!({ "topic": "probe" }), "Sensor route without path"),
+            (json!({ "path": "/probe" }), "Sensor route without topic"),
+            (
+                json!({ "path": "probe", "topic": "probe" }),
+                "Sensor route path must look like /send_xxx",
+            ),
+            (
+                json!({ "path": "/", "topic": "probe" }),
+                "Sensor route path must look like /send_xxx",
+            ),
+            (
+                json!({ "path": "/probe?x", "topic": "probe" }),
+                "Sensor route path must look like /send_xxx",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "" }),
+                "Sensor route topic is invalid",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe/#" }),
+                "Sensor route topic is invalid",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "keys": "t" }),
+                "Sensor route keys must be an array",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "keys": [1] }),
+                "Sensor route keys must be strings",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "rename": ["t"] }),
+                "Sensor route rename must be an object",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "rename": { "t": 1 } }),
+                "Sensor route renames must be strings",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "qos": 3 }),
+                "Sensor route QoS must be 0, 1 or 2",
+            ),
+            (
+                json!({ "path": "/probe", "topic": "probe", "qos": "1" }),
+                "Sensor route QoS must be 0, 1 or 2",
+            ),
+        ] {
+            assert_eq!(
+                SensorRoute::from_json(&value),
+                Err(StringError(error)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_route_tables_are_refused() {
+        let duplicates = Value::from(vec![route_json("/probe"), route_json("/probe")]);
+        let too_many = Value::from(
+            (0..=MAX_SENSOR_ROUTES)
+                .map(|i| route_json(&format!("/probe{}", i)))
+                .collect::<Vec<_>>(),
+        );
+        let full = Value::from(
+            (0..MAX_SENSOR_ROUTES)
+                .map(|i| route_json(&format!("/probe{}", i)))
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!(
+            routes_from_json(&duplicates.to_string()),
+            Err(StringError("Duplicate sensor route path"))
+        );
+        assert_eq!(
+            routes_from_json(&too_many.to_string()),
+            Err(StringError("Too many sensor routes"))
+        );
+        assert_eq!(
+            routes_from_json(&full.to_string()).unwrap().len(),
+            MAX_SENSOR_ROUTES
+        );
+        assert_eq!(
+            routes_from_json("{}"),
+            Err(StringError("Sensor routes must be a JSON array"))
+        );
+        assert_eq!(
+            routes_from_json("[{"),
+            Err(StringError("Sensor routes are not valid JSON"))
+        );
+    }
+
+    #[test]
+    fn message_publishes_the_renamed_required_keys() {
+        let route = SensorRoute::new("/probe", &["t", "h"], "probe/{id}").with_rename("t", "temp");
+        let message = route
+            .build_message(&reading(r#"{"id":"garden","t":21.5,"h":40,"extra":1}"#))
+            .unwrap();
+        let payload: Value = serde_json::from_slice(&message.payload).unwrap();
+
+        assert_eq!(message.topic, "probe/garden");
+        assert_eq!(message.qos, 1);
+        assert_eq!(payload, json!({ "temp": 21.5, "h": 40 }));
+    }
+
+    #[test]
+    fn message_needs_the_keys_and_a_valid_id() {
+        let route = SensorRoute::new("/probe", &["t"], "probe/{id}");
+
+        for (body, error) in [
+            (r#"{"t":21.5}"#, "Missing keys"),
+            (r#"{"id":"garden"}"#, "Missing keys"),
+            (r#"{"id":7,"t":21.5}"#, "Bad ID"),
+            (r#"{"id":"","t":21.5}"#, "Bad ID"),
+            (r#"{"id":"a/b","t":21.5}"#, "Bad ID"),
+            (r#"{"id":"+","t":21.5}"#, "Bad ID"),
+        ] {
+            assert_eq!(
+                route.build_message(&reading(body)).err(),
+                Some(error),
+                "{}",
+                body
+            );
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::net::Ipv4Addr;
+
+/// Longest host name the DHCP client can send.
+pub const MAX_DHCP_HOSTNAME_LEN: usize = 30;
+
+/// Addressing of the station interface. With DHCP only the host name is
+/// used, the static addresses are kept so they survive a mode toggle.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct StaIpConfig {
+    pub static_ip: bool,
+    pub ip: Option<Ipv4Addr>,
+    pub mask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+    pub secondary_dns: Option<Ipv4Addr>,
+    /// Sent in the DHCP request, the IDF default when empty.
+    pub hostname: String,
+}
+
+impl StaIpConfig {
+    /// Prefix length of the static mask, `None` when unset or not contiguous.
+    pub fn prefix_len(&self) -> Option<u8> {
+        self.mask.and_then(mask_prefix_len)
+    }
+}
+
+/// `255.255.255.0` is 24. Masks with holes are refused.
+pub fn mask_prefix_len(mask: Ipv4Addr) -> Option<u8> {
+    let bits = u32::from(mask);
+
+    if bits.leading_ones() + bits.trailing_zeros() != 32 {
+        return None;
+    }
+
+    Some(bits.leading_ones() as u8)
+}
+
+/// Whether `a` and `b` are in the same `prefix_len` subnet.
+pub fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+/// Empty is `None`, anything else must be a dotted IPv4 address.
+pub fn parse_optional_ipv4(s: &str) -> Result<Option<Ipv4Addr>, &'static str> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return Ok(None);
+    }
+
+    s.parse()
+        .map(Some)
+        .map_err(|_| "Not a valid IPv4 address, e.g. 192.168.1.20")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contiguous_masks_give_their_prefix_length() {
+        for (mask, len) in [
+            ("0.0.0.0", Some(0)),
+            ("255.0.0.0", Some(8)),
+            ("255.255.255.0", Some(24)),
+            ("255.255.255.252", Some(30)),
+            ("255.255.255.255", Some(32)),
+            ("255.0.255.0", None),
+            ("0.255.255.255", None),
+        ] {
+            assert_eq!(mask_prefix_len(mask.parse().unwrap()), len, "{}", mask);
+        }
+    }
+
+    #[test]
+    fn subnet_membership() {
+        let ip = Ipv4Addr::new(192, 168, 1, 20);
+
+        assert!(same_subnet(ip, Ipv4Addr::new(192, 168, 1, 1), 24));
+        assert!(!same_subnet(ip, Ipv4Addr::new(192, 168, 2, 1), 24));
+        assert!(same_subnet(ip, Ipv4Addr::new(192, 168, 2, 1), 16));
+        assert!(same_subnet(ip, Ipv4Addr::new(10, 0, 0, 1), 0));
+    }
+
+    #[test]
+    fn optional_addresses() {
+        assert_eq!(parse_optional_ipv4(" "), Ok(None));
+        assert_eq!(
+            parse_optional_ipv4(" 192.168.1.20 "),
+            Ok(Some(Ipv4Addr::new(192, 168, 1, 20)))
+        );
+        assert!(parse_optional_ipv4("192.168.1").is_err());
+        assert!(parse_optional_ipv4("192.168.1.256").is_err());
+    }
+}
@@ -0,0 +1,197 @@
+use std::cmp::Reverse;
+
+use serde_json::{json, Value};
+
+use crate::auth_mode::AuthMode;
+use crate::string_error::StringError;
+
+/// Upstream networks, the first one being the preferred.
+pub const MAX_STA_NETWORKS: usize = 3;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct StaNetwork {
+    pub ssid: String,
+    pub passphrase: String,
+}
+
+impl StaNetwork {
+    pub fn new(ssid: &str, passphrase: &str) -> Self {
+        Self {
+            ssid: ssid.to_string(),
+            passphrase: passphrase.to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ssid": self.ssid,
+            "pass": self.passphrase,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, StringError> {
+        let ssid = value
+            .get("ssid")
+            .and_then(Value::as_str)
+            .ok_or(StringError("Station network without SSID"))?;
+
+        let passphrase = value.get("pass").and_then(Value::as_str).unwrap_or("");
+
+        Ok(Self::new(ssid, passphrase))
+    }
+}
+
+/// A network seen by the last scan.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    pub rssi: i8,
+    /// `None` when the network uses a mode the proxy does not handle.
+    pub auth_mode: Option<AuthMode>,
+}
+
+/// Auth mode advertised by the strongest access point named `ssid`.
+pub fn scanned_auth_mode(scan: &[ScannedNetwork], ssid: &str) -> Option<AuthMode> {
+    scan.iter()
+        .filter(|seen| seen.ssid == ssid)
+        .max_by_key(|seen| seen.rssi)
+        .and_then(|seen| seen.auth_mode)
+}
+
+pub fn networks_from_json(s: &str) -> Result<Vec<StaNetwork>, StringError> {
+    let value: Value =
+        serde_json::from_str(s).map_err(|_| StringError("Station networks are not valid JSON"))?;
+
+    let networks = value
+        .as_array()
+        .ok_or(StringError("Station networks must be a JSON array"))?
+        .iter()
+        .map(StaNetwork::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if networks.len() > MAX_STA_NETWORKS {
+        return Err(StringError("Too many station networks"));
+    }
+
+    Ok(networks)
+}
+
+pub fn networks_to_json(networks: &[StaNetwork]) -> String {
+    Value::Array(networks.iter().map(StaNetwork::to_json).collect()).to_string()
+}
+
+/// Order in which the configured networks are tried: the visible ones from
+/// the strongest to the weakest, then the others (hidden or out of range) by
+/// rank. Returns indexes into `networks`.
+pub fn rank_networks(networks: &[StaNetwork], scan: &[ScannedNetwork]) -> Vec<usize> {
+    let best_rssi = |network: &StaNetwork| {
+        scan.iter()
+            .filter(|seen| seen.ssid == network.ssid)
+            .map(|seen| seen.rssi)
+            .max()
+    };
+
+    let mut visible: Vec<(usize, i8)> = networks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, network)| best_rssi(network).map(|rssi| (i, rssi)))
+        .collect();
+
+    // Stable sort, networks with the same signal keep their rank.
+    visible.sort_by_key(|&(_, rssi)| Reverse(rssi));
+
+    let hidden = (0..networks.len()).filter(|i| !visible.iter().any(|&(v, _)| v == *i));
+
+    visible.iter().map(|&(i, _)| i).chain(hidden).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn networks() -> Vec<StaNetwork> {
+        vec![
+            StaNetwork::new("Home", "passphrase"),
+            StaNetwork::new("Garage", "passphrase"),
+            StaNetwork::new("Phone", "passphrase"),
+        ]
+    }
+
+    fn seen(ssid: &str, rssi: i8) -> ScannedNetwork {
+        ScannedNetwork {
+            ssid: ssid.to_string(),
+            rssi,
+            auth_mode: Some(AuthMode::Wpa2),
+        }
+    }
+
+    #[test]
+    fn visible_networks_come_first_by_signal() {
+        let scan = [seen("Phone", -50), seen("Other", -30), seen("Home", -70)];
+
+        assert_eq!(rank_networks(&networks(), &scan), [2, 0, 1]);
+    }
+
+    #[test]
+    fn without_scan_the_rank_is_kept() {
+        assert_eq!(rank_networks(&networks(), &[]), [0, 1, 2]);
+    }
+
+    #[test]
+    fn equal_signals_keep_the_rank() {
+        let scan = [seen("Phone", -60), seen("Garage", -60)];
+
+        assert_eq!(rank_networks(&networks(), &scan), [1, 2, 0]);
+    }
+
+    #[test]
+    fn strongest_access_point_of_an_ssid_counts() {
+        let scan = [seen("Home", -80), seen("Garage", -60), seen("Home", -40)];
+
+        assert_eq!(rank_networks(&networks(), &scan), [0, 1, 2]);
+    }
+
+    #[test]
+    fn networks_round_trip_as_json() {
+        let networks = networks();
+
+        assert_eq!(
+            networks_from_json(&networks_to_json(&networks)),
+            Ok(networks)
+        );
+        assert_eq!(
+            networks_from_json(r#"[{"ssid": "Open"}]"#),
+            Ok(vec![StaNetwork::new("Open", "")])
+        );
+    }
+
+    #[test]
+    fn invalid_networks_are_refused() {
+        let too_many = networks_to_json(&[networks(), networks()].concat());
+
+        for (json, error) in [
+            ("[", "Station networks are not valid JSON"),
+            ("{}", "Station networks must be a JSON array"),
+            (r#"[{"pass": "x"}]"#, "Station network without SSID"),
+            (too_many.as_str(), "Too many station networks"),
+        ] {
+            assert_eq!(
+                networks_from_json(json),
+                Err(StringError(error)),
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn strongest_access_point_gives_the_auth_mode() {
+        let mut wpa3 = seen("Home", -40);
+        wpa3.auth_mode = Some(AuthMode::Wpa3);
+        let scan = [seen("Home", -70), wpa3, seen("Garage", -50)];
+
+        assert_eq!(scanned_auth_mode(&scan, "Home"), Some(AuthMode::Wpa3));
+        assert_eq!(scanned_auth_mode(&scan, "Garage"), Some(AuthMode::Wpa2));
+        assert_eq!(scanned_auth_mode(&scan, "Phone"), None);
+    }
+}
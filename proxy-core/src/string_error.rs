@@ -0,0 +1,12 @@
+use core::fmt;
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct StringError(pub &'static str);
+
+impl std::error::Error for StringError {}
+
+impl fmt::Display for StringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
@@ -0,0 +1,16 @@
+use std::net::Ipv4Addr;
+
+use crate::link_supervisor::LinkState;
+
+pub trait WifiStatus {
+    fn is_sta_connected(&self) -> bool;
+    fn sta_ip(&self) -> Option<Ipv4Addr>;
+
+    fn sta_link_state(&self) -> LinkState {
+        if self.is_sta_connected() {
+            LinkState::Connected
+        } else {
+            LinkState::Connecting
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::fs;
+use std::path::PathBuf;
+
+use proxy_core::config::{ConfigStore, MemoryConfigStore};
+use proxy_core::config_journal::recover;
+use proxy_core::migration::migrate;
+use proxy_core::string_error::StringError;
+use serde_json::Value;
+
+/// Configuration kept in a JSON file, using the same keys as the firmware NVS
+/// namespace. The file is rewritten after every change.
+pub struct FileConfigStore {
+    path: Option<PathBuf>,
+    store: MemoryConfigStore,
+}
+
+impl FileConfigStore {
+    pub fn load(path: Option<PathBuf>) -> Result<Self, StringError> {
+        let store = match &path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .map_err(|_| StringError("Failed to read configuration file"))?;
+
+                match serde_json::from_str::<Value>(&content) {
+                    Ok(Value::Object(values)) => MemoryConfigStore::from_json(values),
+                    _ => return Err(StringError("Configuration file must be a JSON object")),
+                }
+            }
+            _ => MemoryConfigStore::new(),
+        };
+
+        let mut config = Self { path, store };
+        recover(&mut config)?;
+        migrate(&mut config)?;
+
+        Ok(config)
+    }
+
+    fn persist(&self) -> Result<(), StringError> {
+        match &self.path {
+            Some(path) => fs::write(
+                path,
+                serde_json::to_string_pretty(self.store.as_json()).unwrap_or_default(),
+            )
+            .map_err(|_| StringError("Failed to write configuration file")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ConfigStore for FileConfigStore {
+    type Error = StringError;
+
+    fn load_str(&self, key: &str) -> Option<String> {
+        self.store.load_str(key)
+    }
+
+    fn save_str(&mut self, key: &str, value: &str) -> Result<(), Self::Error> {
+        self.store.save_str(key, value)?;
+        self.persist()
+    }
+
+    fn load_u8(&self, key: &str) -> Option<u8> {
+        self.store.load_u8(key)
+    }
+
+    fn save_u8(&mut self, key: &str, value: u8) -> Result<(), Self::Error> {
+        self.store.save_u8(key, value)?;
+        self.persist()
+    }
+
+    fn load_u16(&self, key: &str) -> Option<u16> {
+        self.store.load_u16(key)
+    }
+
+    fn save_u16(&mut self, key: &str, value: u16) -> Result<(), Self::Error> {
+        self.store.save_u16(key, value)?;
+        self.persist()
+    }
+
+    fn load_blob(&self, key: &str) -> Option<Vec<u8>> {
+        self.store.load_blob(key)
+    }
+
+    fn save_blob(&mut self, key: &str, value: &[u8]) -> Result<(), Self::Error> {
+        self.store.save_blob(key, value)?;
+        self.persist()
+    }
+}
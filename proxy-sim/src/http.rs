@@ -0,0 +1,121 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use proxy_core::ingest::check_content_length;
+
+const MAX_HEADER_LEN: usize = 1024;
+
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn read_request(stream: &TcpStream, max_body_len: usize) -> Result<HttpRequest, &'static str> {
+    let mut reader = BufReader::new(stream);
+    let mut header_len = 0;
+
+    let request_line = read_line(&mut reader, &mut header_len)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or("Bad request line")?.to_string();
+    let path = parts.next().ok_or("Bad request line")?.to_string();
+
+    let mut headers = Vec::new();
+
+    loop {
+        let line = read_line(&mut reader, &mut header_len)?;
+
+        if line.is_empty() {
+            break;
+        }
+
+        let (key, value) = line.split_once(':').ok_or("Bad header")?;
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = HttpRequest {
+        method,
+        path,
+        headers,
+        body: String::new(),
+    };
+
+    if request.method == "POST" {
+        let len_body = request
+            .header("Content-Length")
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(0);
+
+        check_content_length(len_body, max_body_len)?;
+
+        let mut body = vec![0u8; len_body];
+        reader
+            .read_exact(&mut body)
+            .map_err(|_| "Failed to read request.")?;
+
+        request.body = String::from_utf8(body).map_err(|_| "Body is not valid UTF-8")?;
+    }
+
+    Ok(request)
+}
+
+pub fn write_response(
+    mut stream: &TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason_phrase(status),
+        content_type,
+        body.len(),
+        body
+    )?;
+
+    stream.flush()
+}
+
+fn read_line(reader: &mut impl BufRead, header_len: &mut usize) -> Result<String, &'static str> {
+    let mut line = String::new();
+
+    let read = reader
+        .read_line(&mut line)
+        .map_err(|_| "Failed to read request.")?;
+
+    *header_len += read;
+
+    if read == 0 {
+        return Err("Connection closed");
+    } else if *header_len > MAX_HEADER_LEN {
+        return Err("Header too long.");
+    }
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    env,
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use proxy_core::{
+    config::ConfigStore,
+    discovery::HomeAssistantDiscovery,
+    forwarder::Forwarder,
+    ingest::{handle_reading, status_json, MAX_JSON_BODY_LEN},
+    mode::Mode,
+    mqtt,
+    outbox::{MemorySpillStorage, Outbox},
+    sensor_allowlist::SensorAllowlist,
+    sensor_auth::{
+        load_sensor_nonces, save_sensor_nonces, ReadingHeaders, SensorAuth, HEADER_AUTHORIZATION,
+        HEADER_NONCE, HEADER_SIGNATURE,
+    },
+    sensor_route::SensorRoute,
+    wifi_status::WifiStatus,
+};
+
+use file_config_store::FileConfigStore;
+use http::{read_request, write_response};
+use mqtt_client::SimMqttPublisher;
+
+mod file_config_store;
+mod http;
+mod mqtt_client;
+
+/// Locally administered address, only used to derive the default client ID.
+const SIM_MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x5e, 0x51, 0x4d];
+const OUTBOX_RAM_CAPACITY: usize = 16;
+const OUTBOX_SPILL_CAPACITY: usize = 64;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+type SimForwarder = Forwarder<SimMqttPublisher, MemorySpillStorage>;
+
+/// The host is always "connected", there is no station interface to watch.
+struct HostWifi;
+
+impl WifiStatus for HostWifi {
+    fn is_sta_connected(&self) -> bool {
+        true
+    }
+
+    fn sta_ip(&self) -> Option<Ipv4Addr> {
+        None
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn main() -> anyhow::Result<()> {
+    log::set_logger(&StderrLogger).map_err(|e| anyhow::Error::msg(e.to_string()))?;
+    log::set_max_level(log::LevelFilter::Info);
+
+    let mut listen = "127.0.0.1:8080".to_string();
+    let mut config_path: Option<PathBuf> = None;
+    let mut enrol = false;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().ok_or(anyhow::Error::msg("Missing address"))?,
+            "--config" => config_path = args.next().map(PathBuf::from),
+            "--enrol" => enrol = true,
+            _ => {
+                eprintln!("Usage: proxy-sim [--listen ADDR:PORT] [--config FILE.json] [--enrol]");
+                return Ok(());
+            }
+        }
+    }
+
+    let started = Instant::now();
+    let mut config = FileConfigStore::load(config_path)?;
+    let topic_prefix = config.get_mqtt_topic_prefix();
+    let routes: Vec<SensorRoute> = config
+        .get_sensor_routes()
+        .into_iter()
+        .map(|route| route.with_topic_prefix(&topic_prefix))
+        .collect();
+
+    if config.get_mqtt_tls() {
+        log::warn!("TLS is not supported by the simulator, connecting in plain MQTT.");
+    }
+
+    let client_id = mqtt::client_id(&config, &SIM_MAC);
+    let forwarder = Arc::new(Mutex::new(Forwarder::new(
+        SimMqttPublisher::new(
+            &config.get_mqtt_server(),
+            config.get_mqtt_port(),
+            &client_id,
+        )
+        .with_credentials(&config.get_mqtt_username(), &config.get_mqtt_password())
+        .with_availability(&mqtt::availability_topic(&config, &client_id)),
+        Outbox::new(
+            OUTBOX_RAM_CAPACITY,
+            MemorySpillStorage::new(OUTBOX_SPILL_CAPACITY),
+        ),
+    )));
+
+    let discovery = if config.get_ha_discovery() {
+        let discovery =
+            HomeAssistantDiscovery::new(&client_id, &mqtt::availability_topic(&config, &client_id));
+
+        for message in discovery.proxy_messages() {
+            if let Err(e) = forwarder.lock().unwrap().forward(message) {
+                log::warn!("Failed to announce the proxy ({})", e);
+            }
+        }
+
+        Some(Mutex::new(discovery))
+    } else {
+        None
+    };
+
+    let sensor_auth = SensorAuth::new(
+        config.get_sensor_credentials(),
+        &load_sensor_nonces(&config),
+    );
+    if !sensor_auth.is_enabled() {
+        log::warn!("No sensor credentials, readings are not authenticated.");
+    }
+    let sensor_auth = Arc::new(Mutex::new(sensor_auth));
+
+    let mut allowlist =
+        SensorAllowlist::new(config.get_sensor_allowlist(), config.get_sensor_allow_all());
+    if allowlist.refuses_all() && !enrol {
+        log::warn!("No allowed sensors, readings are refused until an enrolment.");
+    }
+    if enrol {
+        allowlist.start_enrolment(started.elapsed());
+    }
+    let allowlist = Arc::new(Mutex::new(allowlist));
+    let diagnostics_topic = mqtt::diagnostics_topic(&config, &client_id);
+
+    let supervisor_forwarder = forwarder.clone();
+    let supervisor_allowlist = allowlist.clone();
+    let supervisor_auth = sensor_auth.clone();
+    thread::spawn(move || loop {
+        {
+            let (enrolled, rejections) = {
+                let mut allowlist = supervisor_allowlist.lock().unwrap();
+                allowlist.expire_enrolment(started.elapsed());
+                (
+                    allowlist.take_enrolled(),
+                    allowlist.take_rejections(started.elapsed()),
+                )
+            };
+
+            if let Some(ids) = enrolled {
+                if let Err(e) = config.set_sensor_allowlist(&ids) {
+                    log::error!("Failed to save the sensor allowlist ({})", e);
+                }
+            }
+
+            let nonces = supervisor_auth
+                .lock()
+                .unwrap()
+                .take_nonces(started.elapsed());
+            if let Some(nonces) = nonces {
+                if let Err(e) = save_sensor_nonces(&mut config, &nonces) {
+                    log::error!("Failed to save the sensor nonces ({})", e);
+                }
+            }
+
+            let mut forwarder = supervisor_forwarder.lock().unwrap();
+
+            for rejection in rejections {
+                if let Err(e) = forwarder.forward(rejection.to_message(&diagnostics_topic)) {
+                    log::warn!("Failed to queue diagnostics ({})", e);
+                }
+            }
+
+            if !forwarder.is_connected() {
+                if let Err(e) = forwarder.publisher_mut().connect() {
+                    log::warn!("MQTT broker unreachable ({})", e);
+                }
+            }
+
+            forwarder.flush();
+        }
+
+        thread::sleep(RECONNECT_INTERVAL);
+    });
+
+    let listener = TcpListener::bind(&listen)?;
+    log::info!("Listening on http://{}", listen);
+
+    for route in &routes {
+        log::info!("Register sensor route {} -> {}", route.path, route.topic);
+    }
+
+    let routes = Arc::new(routes);
+    let discovery = Arc::new(discovery);
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let routes = routes.clone();
+        let forwarder = forwarder.clone();
+        let discovery = discovery.clone();
+        let sensor_auth = sensor_auth.clone();
+        let allowlist = allowlist.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(
+                &stream,
+                &routes,
+                &forwarder,
+                discovery.as_ref().as_ref(),
+                &sensor_auth,
+                &allowlist,
+            ) {
+                log::error!("Connection error: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(
+    stream: &TcpStream,
+    routes: &[SensorRoute],
+    forwarder: &Mutex<SimForwarder>,
+    discovery: Option<&Mutex<HomeAssistantDiscovery>>,
+    sensor_auth: &Mutex<SensorAuth>,
+    allowlist: &Mutex<SensorAllowlist>,
+) -> std::io::Result<()> {
+    let request = match read_request(stream, MAX_JSON_BODY_LEN) {
+        Ok(request) => request,
+        Err(e) => return write_response(stream, 400, "text/plain", e),
+    };
+
+    if request.method == "GET" && request.path == "/status" {
+        let forwarder = forwarder.lock().unwrap();
+        let mode = if forwarder.is_connected() {
+            Mode::Proxy
+        } else {
+            Mode::Degraded
+        };
+        let status = status_json(mode, &HostWifi, &forwarder);
+        return write_response(stream, 200, "application/json", &status);
+    }
+
+    let Some(route) = routes.iter().find(|r| r.path == request.path) else {
+        return write_response(stream, 404, "text/plain", "Not found");
+    };
+
+    if request.method != "POST" {
+        return write_response(stream, 405, "text/plain", "Method not allowed");
+    }
+
+    let headers = ReadingHeaders {
+        authorization: request.header(HEADER_AUTHORIZATION).map(str::to_string),
+        signature: request.header(HEADER_SIGNATURE).map(str::to_string),
+        nonce: request.header(HEADER_NONCE).map(str::to_string),
+    };
+
+    let mut discovery = discovery.map(|d| d.lock().unwrap());
+    let response = handle_reading(
+        route,
+        &request.body,
+        &headers,
+        &mut sensor_auth.lock().unwrap(),
+        &mut allowlist.lock().unwrap(),
+        &mut forwarder.lock().unwrap(),
+        discovery.as_deref_mut(),
+    );
+    log::info!("{} {} -> {}", request.method, request.path, response.status);
+
+    write_response(stream, response.status, "text/plain", &response.body)
+}
@@ -0,0 +1,235 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use proxy_core::mqtt::{availability_message, MqttPublisher, AVAILABILITY_OFFLINE};
+use proxy_core::outbox::OutboxMessage;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+
+/// Minimal MQTT 3.1.1 client, only what is needed to publish readings.
+/// Keep alive is disabled, a dead connection is detected on the next publish.
+pub struct SimMqttPublisher {
+    address: String,
+    client_id: String,
+    username: String,
+    password: String,
+    availability_topic: Option<String>,
+    stream: Option<TcpStream>,
+    packet_id: u16,
+}
+
+impl SimMqttPublisher {
+    pub fn new(server: &str, port: u16, client_id: &str) -> Self {
+        Self {
+            address: format!("{}:{}", server, port),
+            client_id: client_id.to_string(),
+            username: String::new(),
+            password: String::new(),
+            availability_topic: None,
+            stream: None,
+            packet_id: 0,
+        }
+    }
+
+    /// Empty values are not sent, the password is only sent with a user name.
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.username = username.to_string();
+        self.password = password.to_string();
+        self
+    }
+
+    /// Registers `offline` as last will and publishes `online` on connection.
+    pub fn with_availability(mut self, topic: &str) -> Self {
+        self.availability_topic = Some(topic.to_string());
+        self
+    }
+
+    pub fn connect(&mut self) -> io::Result<()> {
+        self.stream = None;
+
+        let mut stream = TcpStream::connect(&self.address)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut body = Vec::new();
+        push_string(&mut body, "MQTT");
+        body.push(4); // Protocol level 3.1.1
+        body.push(self.connect_flags());
+        body.extend_from_slice(&0u16.to_be_bytes()); // No keep alive
+        push_string(&mut body, &self.client_id);
+        if let Some(topic) = &self.availability_topic {
+            push_string(&mut body, topic);
+            push_string(&mut body, AVAILABILITY_OFFLINE);
+        }
+        if !self.username.is_empty() {
+            push_string(&mut body, &self.username);
+            if !self.password.is_empty() {
+                push_string(&mut body, &self.password);
+            }
+        }
+
+        write_packet(&mut stream, CONNECT, &body)?;
+
+        let (header, ack) = read_packet(&mut stream)?;
+
+        if header != CONNACK || ack.len() != 2 || ack[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Broker refused the connection",
+            ));
+        }
+
+        log::info!("Connected to MQTT broker {}", self.address);
+        self.stream = Some(stream);
+
+        if let Some(topic) = self.availability_topic.clone() {
+            self.publish(&availability_message(&topic, true))?;
+        }
+
+        Ok(())
+    }
+
+    fn connect_flags(&self) -> u8 {
+        let mut flags = 0x02; // Clean session
+
+        if self.availability_topic.is_some() {
+            flags |= 0x04 | 0x08 | 0x20; // Will, QoS 1, retained
+        }
+
+        if !self.username.is_empty() {
+            flags |= 0x80;
+            if !self.password.is_empty() {
+                flags |= 0x40;
+            }
+        }
+
+        flags
+    }
+
+    fn next_packet_id(&mut self) -> u16 {
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        self.packet_id
+    }
+
+    fn try_publish(&mut self, message: &OutboxMessage) -> io::Result<()> {
+        let qos = message.qos.min(2);
+        let packet_id = self.next_packet_id();
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(io::Error::new(io::ErrorKind::NotConnected, "Not connected"))?;
+
+        let mut body = Vec::new();
+        push_string(&mut body, &message.topic);
+        if qos > 0 {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        body.extend_from_slice(&message.payload);
+
+        write_packet(stream, PUBLISH | (qos << 1) | message.retain as u8, &body)?;
+
+        match qos {
+            0 => Ok(()),
+            1 => expect_ack(stream, PUBACK, packet_id),
+            _ => {
+                expect_ack(stream, PUBREC, packet_id)?;
+                write_packet(stream, PUBREL, &packet_id.to_be_bytes())?;
+                expect_ack(stream, PUBCOMP, packet_id)
+            }
+        }
+    }
+}
+
+impl MqttPublisher for SimMqttPublisher {
+    type Error = io::Error;
+
+    fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn publish(&mut self, message: &OutboxMessage) -> Result<(), Self::Error> {
+        let result = self.try_publish(message);
+
+        if result.is_err() {
+            log::warn!("Lost connection to MQTT broker {}", self.address);
+            self.stream = None;
+        }
+
+        result
+    }
+}
+
+fn push_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+
+        if len > 0 {
+            byte |= 0x80;
+        }
+
+        packet.push(byte);
+
+        if len == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    stream.write_all(&packet)
+}
+
+fn read_packet(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+
+    stream.read_exact(&mut byte)?;
+    let header = byte[0];
+
+    let mut len = 0usize;
+    let mut multiplier = 1usize;
+
+    loop {
+        stream.read_exact(&mut byte)?;
+        len += (byte[0] & 0x7F) as usize * multiplier;
+        multiplier *= 128;
+
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+
+    Ok((header, body))
+}
+
+fn expect_ack(stream: &mut TcpStream, expected: u8, packet_id: u16) -> io::Result<()> {
+    let (header, body) = read_packet(stream)?;
+
+    if header & 0xF0 != expected & 0xF0 || body != packet_id.to_be_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unexpected acknowledgement from broker",
+        ));
+    }
+
+    Ok(())
+}